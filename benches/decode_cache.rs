@@ -0,0 +1,48 @@
+//! Instructions per second with and without the decode cache.
+//!
+//! Run with `cargo bench --bench decode_cache`.
+
+use std::time::Instant;
+use virtual_vm::console::Console;
+use virtual_vm::run::{Registers, VM};
+
+// Increments the word at R3 and sums it into R1, COUNT times:
+//       AND R1,R1,#0 ; LD R0,COUNT
+// LOOP  LDR R2,R3,#0 ; ADD R2,R2,#1 ; STR R2,R3,#0
+//       ADD R1,R1,R2 ; ADD R0,R0,#-1 ; BRp LOOP ; HALT
+// COUNT .FILL x7FFF
+const PROGRAM: [u16; 10] = [
+    0x5260, 0x2007, 0x64C0, 0x14A1, 0x74C0, 0x1242, 0x103F, 0x03FA, 0xF025, 0x7FFF,
+];
+const RUNS: usize = 100;
+
+/// Best instructions per second over `RUNS` runs of the workload.
+fn measure(cached: bool) -> f64 {
+    let mut template = VM::new();
+    template.console = Console::buffered(b"");
+    template.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    template.registers_storage[Registers::R_R3 as usize] = 0x4000;
+    template.decode_cache.set_enabled(cached);
+
+    let mut best: f64 = 0.0;
+    for _ in 0..RUNS {
+        let mut vm = template.clone();
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        let start = Instant::now();
+        vm.execute().unwrap();
+        let rate = vm.instruction_count as f64 / start.elapsed().as_secs_f64();
+        best = best.max(rate);
+    }
+    best
+}
+
+fn main() {
+    let before = measure(false);
+    let after = measure(true);
+    println!("decode cache off: {:>8.2} M instructions/s", before / 1e6);
+    println!(
+        "decode cache on:  {:>8.2} M instructions/s ({:.2}x)",
+        after / 1e6,
+        after / before
+    );
+}
@@ -0,0 +1,250 @@
+//! Representative workloads and per-opcode costs for each execution engine.
+//!
+//! Run with `cargo bench --bench suite` (add `--features jit` to include the
+//! JIT). Every workload is checked after each run, so a broken engine fails
+//! the benchmark instead of reporting a meaningless rate.
+
+use std::time::Instant;
+use virtual_vm::blocks::Engine;
+use virtual_vm::console::Console;
+use virtual_vm::run::{Registers, VM};
+
+const RUNS: usize = 20;
+
+#[cfg(feature = "jit")]
+const ENGINES: &[(&str, Engine)] = &[
+    ("interpreter", Engine::Interpreter),
+    ("threaded", Engine::Threaded),
+    ("jit", Engine::Jit),
+];
+#[cfg(not(feature = "jit"))]
+const ENGINES: &[(&str, Engine)] = &[
+    ("interpreter", Engine::Interpreter),
+    ("threaded", Engine::Threaded),
+];
+
+struct Workload {
+    name: &'static str,
+    program: Vec<u16>,
+    setup: fn(&mut VM),
+    check: fn(&mut VM),
+}
+
+// Sums COUNT..1 into R1 (x7FFF * x8000 / 2, so xC000) and mixes it into R2:
+//       AND R1,R1,#0 ; AND R2,R2,#0 ; LD R0,COUNT
+// LOOP  ADD R1,R1,R0 ; ADD R2,R2,R1 ; AND R3,R1,R2 ; NOT R3,R3
+//       ADD R2,R2,R3 ; ADD R0,R0,#-1 ; BRp LOOP ; HALT
+// COUNT .FILL x7FFF
+const ARITHMETIC: [u16; 12] = [
+    0x5260, 0x54A0, 0x2008, 0x1240, 0x1481, 0x5642, 0x96FF, 0x1483, 0x103F, 0x03F9, 0xF025, 0x7FFF,
+];
+
+// Copies LEN words from SRC to DST, REPS times:
+//       LD R4,REPS
+// OUTER LD R1,SRC ; LD R2,DST ; LD R0,LEN
+// COPY  LDR R3,R1,#0 ; STR R3,R2,#0 ; ADD R1,R1,#1 ; ADD R2,R2,#1
+//       ADD R0,R0,#-1 ; BRp COPY ; ADD R4,R4,#-1 ; BRp OUTER ; HALT
+// REPS .FILL #8 ; SRC .FILL x4000 ; DST .FILL x6000 ; LEN .FILL #4096
+const MEMCPY: [u16; 17] = [
+    0x280C, 0x220C, 0x240C, 0x200C, 0x6640, 0x7680, 0x1261, 0x14A1, 0x103F, 0x03FA, 0x193F, 0x03F5,
+    0xF025, 8, 0x4000, 0x6000, 4096,
+];
+
+// Prints MSG REPS times:
+//       LD R1,REPS
+// LOOP  LEA R0,MSG ; PUTS ; ADD R1,R1,#-1 ; BRp LOOP ; HALT
+// REPS .FILL #256 ; MSG .STRINGZ "..."
+const PUTS: [u16; 7] = [0x2205, 0xE005, 0xF022, 0x127F, 0x03FC, 0xF025, 256];
+const MESSAGE: &[u8] = b"The quick brown fox jumps over the lazy dog. 0123456789 LC-3!\n";
+
+// Computes fib(N) into R1 recursively, saving R7 and n on the R6 stack:
+//       LD R6,STACK ; LD R0,N ; JSR FIB ; HALT
+// N .FILL #20 ; STACK .FILL xF000
+// FIB   ADD R6,R6,#-1 ; STR R7,R6,#0 ; ADD R1,R0,#-2 ; BRzp RECURSE
+//       ADD R1,R0,#0 ; BRnzp DONE
+// RECURSE ADD R6,R6,#-1 ; STR R0,R6,#0 ; ADD R0,R0,#-1 ; JSR FIB
+//       LDR R0,R6,#0 ; STR R1,R6,#0 ; ADD R0,R0,#-2 ; JSR FIB
+//       LDR R2,R6,#0 ; ADD R1,R1,R2 ; ADD R6,R6,#1
+// DONE  LDR R7,R6,#0 ; ADD R6,R6,#1 ; RET
+const RECURSION: [u16; 26] = [
+    0x2C04, 0x2002, 0x4803, 0xF025, 20, 0xF000, 0x1DBF, 0x7F80, 0x123E, 0x0602, 0x1220, 0x0E0B,
+    0x1DBF, 0x7180, 0x103F, 0x4FF6, 0x6180, 0x7380, 0x103E, 0x4FF2, 0x6580, 0x1242, 0x1DA1, 0x6F80,
+    0x1DA1, 0xC1C0,
+];
+
+fn workloads() -> Vec<Workload> {
+    let mut puts = PUTS.to_vec();
+    puts.extend(MESSAGE.iter().map(|&byte| u16::from(byte)));
+    puts.push(0);
+
+    vec![
+        Workload {
+            name: "arithmetic loop",
+            program: ARITHMETIC.to_vec(),
+            setup: |_| {},
+            check: |vm| assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 0xC000),
+        },
+        Workload {
+            name: "memcpy",
+            program: MEMCPY.to_vec(),
+            setup: |vm| {
+                for (offset, word) in vm.memory[0x4000..0x5000].iter_mut().enumerate() {
+                    *word = (offset as u16).wrapping_mul(0x9E37);
+                }
+            },
+            check: |vm| assert!(vm.memory[0x4000..0x5000] == vm.memory[0x6000..0x7000]),
+        },
+        Workload {
+            name: "PUTS",
+            program: puts,
+            setup: |_| {},
+            check: |vm| {
+                let output = vm.console.take_output();
+                assert!(output.starts_with(&MESSAGE.repeat(256)));
+            },
+        },
+        Workload {
+            name: "JSR recursion",
+            program: RECURSION.to_vec(),
+            setup: |_| {},
+            check: |vm| assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 6765),
+        },
+    ]
+}
+
+/// A VM with `program` loaded at x3000 and a console with no input.
+fn load(program: &[u16], engine: Engine) -> VM {
+    let mut vm = VM::new();
+    vm.console = Console::buffered(b"");
+    vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+    vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+    vm.engine = engine;
+    vm
+}
+
+/// Best time in seconds over `RUNS` runs of `template`, with the number of
+/// instructions one run executes.
+fn measure(template: &VM, check: fn(&mut VM)) -> (f64, u64) {
+    let mut best = f64::INFINITY;
+    let mut count = 0;
+    for _ in 0..RUNS {
+        let mut vm = template.clone();
+        let start = Instant::now();
+        vm.execute().unwrap();
+        best = best.min(start.elapsed().as_secs_f64());
+        count = vm.instruction_count;
+        check(&mut vm);
+    }
+    (best, count)
+}
+
+/// PC-relative offset from the instruction at `pc` to `target`, in `bits` bits.
+fn offset(pc: u16, target: u16, bits: u32) -> u16 {
+    target.wrapping_sub(pc.wrapping_add(1)) & ((1 << bits) - 1)
+}
+
+/// Addresses an opcode benchmark can refer to.
+struct Layout {
+    /// Holds x4000, the address in R5.
+    data: u16,
+    /// A RET.
+    subroutine: u16,
+    /// A word to store into.
+    scratch: u16,
+}
+
+const COPIES: u16 = 16;
+const ITERATIONS: u16 = 8192;
+
+/// A loop running `copies` instructions built by `op` per iteration:
+///
+/// ```text
+///       LD R4,COUNT
+/// LOOP  op x copies ; ADD R4,R4,#-1 ; BRp LOOP ; HALT
+/// COUNT .FILL ITERATIONS ; DATA .FILL x4000 ; SUB RET ; SCRATCH .BLKW 1
+/// ```
+fn opcode_loop(op: fn(u16, &Layout) -> u16, copies: u16) -> Vec<u16> {
+    let count = 0x3001 + copies + 3;
+    let layout = Layout {
+        data: count + 1,
+        subroutine: count + 2,
+        scratch: count + 3,
+    };
+    let mut program = vec![0x2800 | offset(0x3000, count, 9)];
+    for pc in 0x3001..0x3001 + copies {
+        program.push(op(pc, &layout));
+    }
+    let branch = 0x3001 + copies + 1;
+    program.push(0x193F);
+    program.push(0x0200 | offset(branch, 0x3001, 9));
+    program.push(0xF025);
+    program.push(ITERATIONS);
+    program.push(0x4000);
+    program.push(0xC1C0);
+    program.push(0);
+    program
+}
+
+type OpcodeBench = (&'static str, fn(u16, &Layout) -> u16);
+
+const OPCODES: &[OpcodeBench] = &[
+    ("ADD", |_, _| 0x1261),                            // ADD R1,R1,#1
+    ("AND", |_, _| 0x5242),                            // AND R1,R1,R2
+    ("NOT", |_, _| 0x927F),                            // NOT R1,R1
+    ("LEA", |_, _| 0xE200),                            // LEA R1,#0
+    ("BR", |_, _| 0x0E00),                             // BRnzp #0
+    ("LDR", |_, _| 0x6340),                            // LDR R1,R5,#0
+    ("STR", |_, _| 0x7340),                            // STR R1,R5,#0
+    ("LD", |pc, l| 0x2200 | offset(pc, l.data, 9)),    // LD R1,DATA
+    ("ST", |pc, l| 0x3200 | offset(pc, l.scratch, 9)), // ST R1,SCRATCH
+    ("LDI", |pc, l| 0xA200 | offset(pc, l.data, 9)),   // LDI R1,DATA
+    ("STI", |pc, l| 0xB200 | offset(pc, l.data, 9)),   // STI R1,DATA
+    ("JSR+RET", |pc, l| 0x4800 | offset(pc, l.subroutine, 11)),
+    ("TRAP OUT", |_, _| 0xF021),
+];
+
+/// Nanoseconds per instruction built by `op`, with the loop overhead
+/// measured separately and subtracted.
+fn opcode_cost(op: fn(u16, &Layout) -> u16, engine: Engine) -> f64 {
+    let setup = |program: &[u16]| {
+        let mut vm = load(program, engine);
+        vm.registers_storage[Registers::R_R0 as usize] = u16::from(b'.');
+        vm.registers_storage[Registers::R_R5 as usize] = 0x4000;
+        vm
+    };
+    let (base_time, base_count) = measure(&setup(&opcode_loop(op, 0)), |_| {});
+    let (time, count) = measure(&setup(&opcode_loop(op, COPIES)), |_| {});
+    (time - base_time) * 1e9 / (count - base_count) as f64
+}
+
+fn main() {
+    print!("{:<20}", "Workload (MIPS)");
+    for (name, _) in ENGINES {
+        print!("{:>14}", name);
+    }
+    println!();
+    for workload in workloads() {
+        print!("{:<20}", workload.name);
+        for &(_, engine) in ENGINES {
+            let mut vm = load(&workload.program, engine);
+            (workload.setup)(&mut vm);
+            let (time, count) = measure(&vm, workload.check);
+            print!("{:>14.2}", count as f64 / time / 1e6);
+        }
+        println!();
+    }
+
+    println!();
+    print!("{:<20}", "Opcode (ns/instr)");
+    for (name, _) in ENGINES {
+        print!("{:>14}", name);
+    }
+    println!();
+    for &(name, op) in OPCODES {
+        print!("{:<20}", name);
+        for &(_, engine) in ENGINES {
+            print!("{:>14.2}", opcode_cost(op, engine));
+        }
+        println!();
+    }
+}
@@ -0,0 +1,271 @@
+//! Two-pass LC-3 assembler producing `.obj` images and `.sym` files in the
+//! formats `lc3as` uses.
+//!
+//! The first pass is `scan_asm`, which lays out every line and collects the
+//! labels. The second pass encodes each line now that all labels are known.
+//! PC-relative operands may be labels or literal offsets (`#-3`, `x10`).
+
+use crate::extension::Extension;
+use crate::loader::ImageFormat;
+use crate::symbols::{
+    is_directive, is_mnemonic, parse_number, parse_string, scan_asm_with, tokenize, SourceMap,
+    SymbolTable,
+};
+
+use std::fmt::Write;
+
+/// Words assembled from one `.ORIG` block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Section {
+    /// The section as an `.obj` image: the origin followed by the words, all
+    /// big-endian.
+    pub fn to_obj(&self) -> Vec<u8> {
+        self.to_image(ImageFormat::Obj)
+    }
+
+    /// The section as an image in `format`, origin first.
+    pub fn to_image(&self, format: ImageFormat) -> Vec<u8> {
+        let words: Vec<u16> = std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .collect();
+        format.encode(&words)
+    }
+}
+
+/// The output of `assemble`.
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub sections: Vec<Section>,
+    pub symbols: SymbolTable,
+    pub map: SourceMap,
+}
+
+/// Formats `symbols` as an `lc3as` `.sym` file, which `SymbolTable::parse`
+/// reads back.
+pub fn format_symbols(symbols: &SymbolTable) -> String {
+    let mut out = String::new();
+    writeln!(out, "// Symbol table").unwrap();
+    writeln!(out, "// Scope level 0:").unwrap();
+    writeln!(out, "//\tSymbol Name       Page Address").unwrap();
+    writeln!(out, "//\t----------------  ------------").unwrap();
+    for (name, address) in symbols.iter() {
+        writeln!(out, "//\t{:<16}  {:04X}", name, address).unwrap();
+    }
+    out
+}
+
+/// A register operand, `R0`-`R7`, as its number.
+pub fn register(token: Option<&str>) -> Result<u16, String> {
+    let token = token.ok_or("missing register operand")?;
+    match token.as_bytes() {
+        [b'R' | b'r', digit @ b'0'..=b'7'] => Ok(u16::from(digit - b'0')),
+        _ => Err(format!("expected a register, found {}", token)),
+    }
+}
+
+/// A signed immediate that must fit in `bits` bits, masked to them.
+pub fn immediate(token: Option<&str>, bits: u32) -> Result<u16, String> {
+    let token = token.ok_or("missing immediate operand")?;
+    let value = parse_number(token).ok_or_else(|| format!("bad number {}", token))?;
+    let limit = 1 << (bits - 1);
+    if !(-limit..limit).contains(&value) {
+        return Err(format!("{} does not fit in {} bits", token, bits));
+    }
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+/// A PC-relative operand: a label, or a literal offset.
+fn pc_offset(
+    token: Option<&str>,
+    address: u16,
+    bits: u32,
+    symbols: &SymbolTable,
+) -> Result<u16, String> {
+    let token = token.ok_or("missing label operand")?;
+    if parse_number(token).is_some() {
+        return immediate(Some(token), bits);
+    }
+    let target = symbols
+        .address_of(token)
+        .ok_or_else(|| format!("undefined label {}", token))?;
+    let offset = i32::from(target.wrapping_sub(address.wrapping_add(1)) as i16);
+    let limit = 1 << (bits - 1);
+    if !(-limit..limit).contains(&offset) {
+        return Err(format!("{} is too far away ({} words)", token, offset));
+    }
+    Ok(offset as u16 & ((1 << bits) - 1))
+}
+
+/// A 16-bit value: a number or a label's address.
+fn word(token: Option<&str>, symbols: &SymbolTable) -> Result<u16, String> {
+    let token = token.ok_or("missing operand")?;
+    match parse_number(token) {
+        Some(value) if (-0x8000..=0xFFFF).contains(&value) => Ok(value as u16),
+        Some(_) => Err(format!("{} does not fit in 16 bits", token)),
+        None => symbols
+            .address_of(token)
+            .ok_or_else(|| format!("undefined label {}", token)),
+    }
+}
+
+/// Encodes one instruction at `address`.
+fn encode(
+    mnemonic: &str,
+    operands: &mut dyn Iterator<Item = &str>,
+    address: u16,
+    symbols: &SymbolTable,
+) -> Result<u16, String> {
+    let upper = mnemonic.to_ascii_uppercase();
+    let mut next = || operands.next();
+    let instr = match upper.as_str() {
+        "ADD" | "AND" => {
+            let opcode = if upper == "ADD" { 0x1000 } else { 0x5000 };
+            let dr = register(next())?;
+            let sr1 = register(next())?;
+            let operand = next();
+            let last = match operand.and_then(parse_number) {
+                Some(_) => 0x20 | immediate(operand, 5)?,
+                None => register(operand)?,
+            };
+            opcode | dr << 9 | sr1 << 6 | last
+        }
+        "NOT" => 0x903F | register(next())? << 9 | register(next())? << 6,
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            let opcode = match upper.as_str() {
+                "LD" => 0x2000,
+                "LDI" => 0xA000,
+                "LEA" => 0xE000,
+                "ST" => 0x3000,
+                _ => 0xB000,
+            };
+            opcode | register(next())? << 9 | pc_offset(next(), address, 9, symbols)?
+        }
+        "LDR" | "STR" => {
+            let opcode = if upper == "LDR" { 0x6000 } else { 0x7000 };
+            opcode | register(next())? << 9 | register(next())? << 6 | immediate(next(), 6)?
+        }
+        "JMP" => 0xC000 | register(next())? << 6,
+        "RET" => 0xC1C0,
+        "JSR" => 0x4800 | pc_offset(next(), address, 11, symbols)?,
+        "JSRR" => 0x4000 | register(next())? << 6,
+        "RTI" => 0x8000,
+        "NOP" => 0x0000,
+        "TRAP" => {
+            let token = next().ok_or("missing trap vector")?;
+            match parse_number(token) {
+                Some(vector @ 0..=0xFF) => 0xF000 | vector as u16,
+                _ => return Err(format!("bad trap vector {}", token)),
+            }
+        }
+        "GETC" => 0xF020,
+        "OUT" => 0xF021,
+        "PUTS" => 0xF022,
+        "IN" => 0xF023,
+        "PUTSP" => 0xF024,
+        "HALT" => 0xF025,
+        _ => {
+            let flags = &upper[2..];
+            let mut nzp = 0;
+            for (flag, bit) in [('N', 4), ('Z', 2), ('P', 1)] {
+                if flags.contains(flag) {
+                    nzp |= bit;
+                }
+            }
+            if nzp == 0 {
+                nzp = 7;
+            }
+            nzp << 9 | pc_offset(next(), address, 9, symbols)?
+        }
+    };
+    if let Some(extra) = operands.next() {
+        return Err(format!("unexpected operand {}", extra));
+    }
+    Ok(instr)
+}
+
+/// Assembles `source`. Errors name the line they were found on.
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    assemble_with(source, None)
+}
+
+/// Like `assemble`, but `extension` encodes its own mnemonics.
+pub fn assemble_with(source: &str, extension: Option<&dyn Extension>) -> Result<Assembly, String> {
+    let (map, symbols) = scan_asm_with(source, extension)?;
+    let extended = |token: &str| extension.is_some_and(|e| e.is_mnemonic(token));
+    let mut sections: Vec<Section> = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |e: String| format!("line {}: {}", line, e);
+        let tokens = tokenize(text);
+        let mut tokens = tokens.iter().map(String::as_str);
+        let Some(mut first) = tokens.next() else {
+            continue;
+        };
+        if !is_directive(first) && !is_mnemonic(first) && !extended(first) {
+            match tokens.next() {
+                Some(next) => first = next,
+                None => continue,
+            }
+        }
+
+        let upper = first.to_ascii_uppercase();
+        let words = match upper.as_str() {
+            ".ORIG" => {
+                let origin = word(tokens.next(), &symbols).map_err(error)?;
+                sections.push(Section {
+                    origin,
+                    words: Vec::new(),
+                });
+                continue;
+            }
+            ".END" => continue,
+            ".FILL" => vec![word(tokens.next(), &symbols).map_err(error)?],
+            // `scan_asm` has already checked the count
+            ".BLKW" => vec![0; tokens.next().and_then(parse_number).unwrap_or(0) as usize],
+            ".STRINGZ" => {
+                let operand = tokens.next().unwrap_or_default();
+                let mut words: Vec<u16> = parse_string(operand)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(u16::from)
+                    .collect();
+                words.push(0);
+                words
+            }
+            _ => {
+                let section = sections
+                    .last()
+                    .ok_or_else(|| error("code outside .ORIG".into()))?;
+                let address = section.origin.wrapping_add(section.words.len() as u16);
+                let instr = match extension {
+                    Some(extension) if extension.is_mnemonic(first) => {
+                        let operands: Vec<&str> = tokens.collect();
+                        extension.assemble(first, &operands)
+                    }
+                    _ => encode(first, &mut tokens, address, &symbols),
+                };
+                vec![instr.map_err(error)?]
+            }
+        };
+        sections
+            .last_mut()
+            .ok_or_else(|| error("code outside .ORIG".into()))?
+            .words
+            .extend(words);
+    }
+
+    if sections.is_empty() {
+        return Err("no .ORIG directive".to_string());
+    }
+    Ok(Assembly {
+        sections,
+        symbols,
+        map,
+    })
+}
@@ -0,0 +1,275 @@
+//! Basic-block translation and threaded-code execution.
+//!
+//! The threaded engine splits the program into basic blocks, each ending at
+//! the first BR, JMP/RET, JSR/JSRR, TRAP or undecodable word. A block is
+//! decoded once into an array of `DecodedInstr`s, and that array is run
+//! without the per-instruction fetch and decode of the interpreter. Writing
+//! to a word a block was built from discards the block; it is rebuilt from
+//! the new contents the next time it is entered.
+
+use crate::decode::{decode, DecodedInstr};
+use crate::memory::ADDRESS_SPACE;
+use crate::run::{Fault, Isa, Registers, Step, MR_KBSR, VM};
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Longest block built, in instructions.
+pub(crate) const MAX_BLOCK_LEN: usize = 64;
+
+/// How `VM::execute` runs a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Fetch, decode and execute one instruction at a time.
+    #[default]
+    Interpreter,
+    /// Run cached basic blocks of predecoded instructions.
+    Threaded,
+    /// Like `Threaded`, but hot blocks are compiled to native code.
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+impl Engine {
+    /// Most instructions a single `VM::advance` can run with this engine.
+    pub(crate) fn max_advance(self) -> u64 {
+        match self {
+            Engine::Interpreter => 1,
+            Engine::Threaded => MAX_BLOCK_LEN as u64,
+            #[cfg(feature = "jit")]
+            Engine::Jit => crate::jit::MAX_NATIVE_RUN,
+        }
+    }
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "interpreter" => Ok(Engine::Interpreter),
+            "threaded" => Ok(Engine::Threaded),
+            #[cfg(feature = "jit")]
+            "jit" => Ok(Engine::Jit),
+            _ => Err(format!("unknown engine `{}`", name)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Block {
+    /// The words the block was decoded from.
+    words: Vec<u16>,
+    pub(crate) code: Vec<DecodedInstr>,
+}
+
+/// Basic blocks built so far, keyed by start address. Tables are allocated
+/// on first use, so VMs running the interpreter pay nothing for them.
+#[derive(Clone, Default)]
+pub struct BlockCache {
+    blocks: Vec<Option<Arc<Block>>>,
+    /// Number of blocks built from each address (at most `MAX_BLOCK_LEN`).
+    covered: Vec<u8>,
+    /// Set when a write discards a block, so the block running can stop.
+    modified: bool,
+    compiled: u64,
+    invalidated: u64,
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("blocks", &self.blocks.iter().flatten().count())
+            .field("compiled", &self.compiled)
+            .field("invalidated", &self.invalidated)
+            .finish()
+    }
+}
+
+/// True for instructions that end a basic block.
+fn ends_block(decoded: DecodedInstr) -> bool {
+    matches!(
+        decoded,
+        DecodedInstr::Br { .. }
+            | DecodedInstr::Jmp { .. }
+            | DecodedInstr::Jsr { .. }
+            | DecodedInstr::Jsrr { .. }
+            | DecodedInstr::Trap { .. }
+            | DecodedInstr::Rti
+            | DecodedInstr::Illegal { .. }
+    )
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks built since the cache was created.
+    pub fn compiled(&self) -> u64 {
+        self.compiled
+    }
+
+    /// Blocks discarded because memory they were built from changed.
+    pub fn invalidated(&self) -> u64 {
+        self.invalidated
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+    }
+
+    /// Number of blocks built from each address; empty before the first block.
+    #[cfg(feature = "jit")]
+    pub(crate) fn covered(&self) -> &[u8] {
+        &self.covered
+    }
+
+    /// The block starting at `start`, building it if needed. A cached block
+    /// whose words no longer match memory is rebuilt.
+    fn get(&mut self, start: u16, memory: &[u16]) -> Arc<Block> {
+        if self.blocks.is_empty() {
+            self.blocks = vec![None; 1 << 16];
+            self.covered = vec![0; 1 << 16];
+        }
+        if let Some(block) = &self.blocks[start as usize] {
+            let end = start as usize + block.words.len();
+            if memory[start as usize..end] == block.words[..] {
+                return block.clone();
+            }
+            self.remove(start);
+        }
+
+        let mut words = Vec::new();
+        let mut code = Vec::new();
+        let mut address = start as usize;
+        // Stop short of the device registers and the end of memory
+        while address < MR_KBSR as usize && code.len() < MAX_BLOCK_LEN {
+            let decoded = decode(address as u16, memory[address]);
+            words.push(memory[address]);
+            code.push(decoded);
+            address += 1;
+            if ends_block(decoded) {
+                break;
+            }
+        }
+        for covered in &mut self.covered[start as usize..address] {
+            *covered += 1;
+        }
+        let block = Arc::new(Block { words, code });
+        self.blocks[start as usize] = Some(block.clone());
+        self.compiled += 1;
+        block
+    }
+
+    fn remove(&mut self, start: u16) {
+        if let Some(block) = self.blocks[start as usize].take() {
+            let end = start as usize + block.words.len();
+            for covered in &mut self.covered[start as usize..end] {
+                *covered -= 1;
+            }
+            self.invalidated += 1;
+        }
+    }
+
+    /// Discards every block built from `address`.
+    #[inline]
+    pub(crate) fn invalidate(&mut self, address: u16) {
+        if self
+            .covered
+            .get(address as usize)
+            .is_none_or(|&count| count == 0)
+        {
+            return;
+        }
+        let first = (address as usize).saturating_sub(MAX_BLOCK_LEN - 1);
+        for start in first..=address as usize {
+            let covers = self.blocks[start]
+                .as_ref()
+                .is_some_and(|block| start + block.words.len() > address as usize);
+            if covers {
+                self.remove(start as u16);
+            }
+        }
+        self.modified = true;
+    }
+}
+
+impl VM {
+    /// Runs one instruction with the selected engine: a single step for the
+    /// interpreter, or up to a whole basic block for the threaded engine.
+    pub fn advance(&mut self) -> Result<Step, Fault> {
+        match self.engine {
+            Engine::Interpreter => self.step(),
+            Engine::Threaded => self.step_block(),
+            #[cfg(feature = "jit")]
+            Engine::Jit => self.step_block(),
+        }
+    }
+
+    /// Runs the basic block at PC, stopping early on a fault, a halt, or a
+    /// write into code that has already been built into a block. Falls back
+    /// to `step` while history, profiling, coverage, loop detection,
+    /// tracing, stack checks or uninitialized read detection need to see
+    /// every instruction, for code in the device register page, for LC-3b
+    /// programs, and unless memory is a flat array of the whole address
+    /// space with full 16-bit words.
+    pub fn step_block(&mut self) -> Result<Step, Fault> {
+        let start = self.registers_storage[Registers::R_PC as usize];
+        let memory = self.memory.as_slice();
+        let Some(memory) = memory.filter(|memory| memory.len() == ADDRESS_SPACE) else {
+            return self.step();
+        };
+        if self.isa != Isa::Lc3
+            || self.memory.width() != 16
+            || self.history.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+            || self.loop_detector.is_some()
+            || self.tracer.is_some()
+            || self.stack_checker.is_some()
+            || self.init_checker.is_some()
+            || start >= MR_KBSR
+        {
+            return self.step();
+        }
+
+        let block = self.blocks.get(start, memory);
+        #[cfg(feature = "jit")]
+        if self.engine == Engine::Jit {
+            if let Some(result) = self.run_native(start, &block) {
+                return result;
+            }
+        }
+        self.blocks.modified = false;
+        for (pc, &decoded) in (start..).zip(block.code.iter()) {
+            let r7 = self.registers_storage[Registers::R_R7 as usize];
+            self.registers_storage[Registers::R_PC as usize] = pc.wrapping_add(1);
+            let mut result = self.execute_decoded(pc, decoded);
+            if self.input_trace.take_divergence() {
+                result = Err(Fault::ReplayDiverged {
+                    pc,
+                    count: self.instruction_count,
+                });
+            }
+            match result {
+                Ok(step) => {
+                    self.instruction_count += 1;
+                    if step == Step::Halted {
+                        return Ok(step);
+                    }
+                }
+                Err(fault) => {
+                    self.registers_storage[Registers::R_PC as usize] = pc;
+                    self.registers_storage[Registers::R_R7 as usize] = r7;
+                    return Err(fault);
+                }
+            }
+            if self.blocks.modified {
+                break;
+            }
+        }
+        Ok(Step::Continue)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::run::{Fault, Registers, Step, VM};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Limits on how long `VM::execute` may run. Install one in `VM::budget`;
+/// `None` fields are unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// Stop once `VM::instruction_count` reaches this many instructions.
+    pub max_instructions: Option<u64>,
+    /// Stop once a single `execute` call has run for this long.
+    pub max_time: Option<Duration>,
+}
+
+/// Which limit of a `Budget` ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetKind {
+    Instructions,
+    Time,
+}
+
+impl fmt::Display for BudgetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetKind::Instructions => write!(f, "Instruction limit"),
+            BudgetKind::Time => write!(f, "Time limit"),
+        }
+    }
+}
+
+/// Number of recent PCs kept for the loop hint.
+const RECENT: usize = 64;
+
+/// Instructions run between checks of the clock.
+const CLOCK_INTERVAL: u64 = 1 << 16;
+
+/// PCs that the most recent `VM::advance` calls started at. For the
+/// threaded engines these are block starts, so the range may end a few
+/// words early.
+struct RecentPcs {
+    pcs: [u16; RECENT],
+    next: usize,
+    len: usize,
+}
+
+impl RecentPcs {
+    fn new() -> Self {
+        Self {
+            pcs: [0; RECENT],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % RECENT;
+        self.len = (self.len + 1).min(RECENT);
+    }
+
+    /// Lowest and highest recent PC, including `pc`.
+    fn range(&self, pc: u16) -> (u16, u16) {
+        self.pcs[..self.len]
+            .iter()
+            .fold((pc, pc), |(low, high), &recent| {
+                (low.min(recent), high.max(recent))
+            })
+    }
+}
+
+impl VM {
+    /// Runs until the program halts, faults, `stop` returns true, or the
+    /// budget runs out. Close to the instruction limit the VM single-steps,
+    /// so the limit is exact whatever the engine.
+    pub(crate) fn execute_budgeted(
+        &mut self,
+        budget: Budget,
+        stop: impl Fn() -> bool,
+    ) -> Result<(), Fault> {
+        let started = Instant::now();
+        let mut next_clock_check = self.instruction_count + CLOCK_INTERVAL;
+        let mut recent = RecentPcs::new();
+        let max_advance = self.engine.max_advance();
+        while !stop() {
+            let pc = self.registers_storage[Registers::R_PC as usize];
+            let exhausted = match budget.max_instructions {
+                Some(max) if self.instruction_count >= max => Some(BudgetKind::Instructions),
+                _ => None,
+            }
+            .or_else(|| {
+                let max_time = budget.max_time?;
+                if self.instruction_count < next_clock_check {
+                    return None;
+                }
+                next_clock_check = self.instruction_count + CLOCK_INTERVAL;
+                (started.elapsed() >= max_time).then_some(BudgetKind::Time)
+            });
+            if let Some(kind) = exhausted {
+                let (low, high) = recent.range(pc);
+                return Err(Fault::BudgetExhausted {
+                    kind,
+                    pc,
+                    count: self.instruction_count,
+                    low,
+                    high,
+                });
+            }
+
+            recent.push(pc);
+            let near_limit = budget
+                .max_instructions
+                .is_some_and(|max| max - self.instruction_count < max_advance);
+            let step = if near_limit {
+                self.step()?
+            } else {
+                self.advance()?
+            };
+            if step == Step::Halted {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use crate::symbols::SymbolTable;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A subroutine call that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Address of the JSR/JSRR that made the call.
+    pub call_site: u16,
+    /// First instruction of the called subroutine.
+    pub entry: u16,
+    /// Where a RET from this call is expected to go (the value saved in R7).
+    pub return_address: u16,
+}
+
+/// Shadow call stack maintained from JSR/JSRR and RET, plus a call graph of
+/// every call made. Install one in `VM::call_stack` before running.
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    /// Where the program started; the caller of the outermost frame.
+    root: u16,
+    frames: Vec<CallFrame>,
+    /// Number of calls from each caller entry to each callee entry.
+    edges: BTreeMap<(u16, u16), u64>,
+}
+
+impl CallStack {
+    pub fn new(root: u16) -> Self {
+        Self {
+            root,
+            frames: Vec::new(),
+            edges: BTreeMap::new(),
+        }
+    }
+
+    /// Active calls, outermost first.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Entry address of the subroutine currently running.
+    pub fn current_function(&self) -> u16 {
+        self.frames.last().map_or(self.root, |frame| frame.entry)
+    }
+
+    /// Calls recorded from each caller to each callee, keyed by entry addresses.
+    pub fn edges(&self) -> &BTreeMap<(u16, u16), u64> {
+        &self.edges
+    }
+
+    pub(crate) fn call(&mut self, frame: CallFrame) {
+        *self
+            .edges
+            .entry((self.current_function(), frame.entry))
+            .or_default() += 1;
+        self.frames.push(frame);
+    }
+
+    /// Handles a RET to `target`. The innermost frame expecting that return
+    /// address is popped together with any frames above it, which were left
+    /// without a RET. A return that matches no frame leaves the stack alone.
+    /// Returns the frames removed, innermost last.
+    pub(crate) fn ret(&mut self, target: u16) -> Vec<CallFrame> {
+        match self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == target)
+        {
+            Some(index) => self.frames.split_off(index),
+            None => Vec::new(),
+        }
+    }
+
+    /// Reverses `call`, for stepping backwards. The call graph keeps its count.
+    pub(crate) fn undo_call(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Reverses `ret`, for stepping backwards.
+    pub(crate) fn undo_ret(&mut self, frames: &[CallFrame]) {
+        self.frames.extend_from_slice(frames);
+    }
+
+    /// Formats a backtrace for the machine stopped at `pc`: the innermost
+    /// frame first, then each call site with the address it returns to.
+    pub fn backtrace(&self, pc: u16, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        writeln!(out, "Backtrace:").unwrap();
+        writeln!(out, "  #0  x{:04X}  {}", pc, symbols.describe(pc)).unwrap();
+        for (index, frame) in self.frames.iter().rev().enumerate() {
+            writeln!(
+                out,
+                "  #{:<2} x{:04X}  {}  (calls {}, returns to x{:04X})",
+                index + 1,
+                frame.call_site,
+                symbols.describe(frame.call_site),
+                symbols.describe(frame.entry),
+                frame.return_address
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Formats the call graph in Graphviz DOT format. Nodes are subroutine
+    /// entry points and edges are labelled with the number of calls.
+    pub fn to_dot(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph calls {{").unwrap();
+        writeln!(out, "    node [shape=box];").unwrap();
+        writeln!(out, "    \"{}\" [style=bold];", symbols.describe(self.root)).unwrap();
+        for (&(caller, callee), count) in &self.edges {
+            writeln!(
+                out,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                symbols.describe(caller),
+                symbols.describe(callee),
+                count
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
@@ -0,0 +1,838 @@
+//! The `lc3` command line: subcommands, their options and exit codes.
+
+use crate::asm::{assemble, format_symbols};
+use crate::blocks::Engine;
+use crate::budget::Budget;
+use crate::callstack::CallStack;
+use crate::coverage::Coverage;
+use crate::dap;
+use crate::debugger::Debugger;
+use crate::disasm::disassemble;
+use crate::dump::{format_diff, parse_range, Dump, DumpFormat};
+use crate::grade;
+use crate::history::History;
+use crate::init::{parse_word, register_index, InitState};
+use crate::initcheck::{InitChecker, InitMode};
+use crate::input_buffering;
+use crate::lc3b;
+use crate::loader::{ImageFormat, ImageLoader, Strictness};
+use crate::loops::LoopDetector;
+use crate::memory::ADDRESS_SPACE;
+use crate::profiler::Profiler;
+use crate::repl::repl;
+use crate::replay::{InputLog, InputTrace};
+use crate::run::{Fault, Isa, Registers, VM};
+use crate::stackcheck::StackChecker;
+use crate::symbols::{scan_asm, SymbolTable};
+use crate::trace::Tracer;
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The program halted.
+pub const EXIT_HALTED: i32 = 0;
+/// The program faulted, or was stopped in an infinite loop.
+pub const EXIT_FAULT: i32 = 1;
+/// Bad arguments, or a file that could not be read or written.
+pub const EXIT_USAGE: i32 = 2;
+/// The instruction or time limit ran out.
+pub const EXIT_TIMEOUT: i32 = 3;
+/// Stopped with Ctrl+C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Instructions kept for stepping backwards in `lc3 debug`.
+const DEBUG_HISTORY: usize = 100_000;
+
+const USAGE: &str = "\
+Usage: lc3 [command] [options] [image-file1] ...
+
+Commands:
+  run      run images (the default)
+  asm      assemble a source file into .obj and .sym files
+  disasm   disassemble an image
+  debug    run images under an interactive debugger
+  dump     show an image in hex and ASCII
+  grade    run an image against a spec of test cases
+
+`lc3 --dap` serves the Debug Adapter Protocol on stdin and stdout.
+Run `lc3 <command> --help` for the options of each command.";
+
+/// The `lc3 run` usage text, listing `engines` for `--engine`.
+macro_rules! run_usage {
+    ($engines:literal) => {
+        concat!(
+            "\
+Usage: lc3 run [options] [image-file1] ...
+
+Options:
+  --pc address|origin         start address (default x3000)
+  --reg Rn=value              set a register before running; repeatable
+  --init file                 registers and memory to set before running
+  --symbols file              labels for reports (default: each image's .sym)
+  --trace file|-              write every instruction executed to a file or stderr
+  -q, --quiet                 no \"origin\" or \"Halting the program...\" messages
+  --strict-load               refuse odd-length, empty, overlapping or oversized
+                              images instead of warning about them
+  --max-instructions n        stop after n instructions
+  --timeout seconds           stop after this much wall-clock time
+  --detect-loops              stop programs stuck in an infinite loop
+  --check-stack               report unbalanced stacks, bad returns and R6
+                              running into the images when the program stops
+  --check-uninit warn|fault   report reads of memory and registers nothing set,
+                              or stop at the first one
+  --engine ",
+            $engines,
+            "
+  --isa lc3|lc3b              instruction set to run (default lc3)
+  --resume snapshot           restore a snapshot before running
+  --save-snapshot file        save a snapshot when the program stops
+  --record log | --replay log record or replay keyboard input
+  --profile                   print a profile when the program stops
+  --coverage file [--source asm]
+  --call-graph file           write the call graph (DOT) when the program stops
+  --dump file                 dump memory when the program stops
+  --dump-range from-to        part of memory to dump (default all of it)
+  --dump-format format        hex, obj, raw, lc3hex, lc3bin, ihex or srec
+                              (default: from the file name, else hex)
+
+Exit status: 0 halted, 1 fault or infinite loop, 2 bad arguments or files,
+3 instruction or time limit reached, 130 interrupted."
+        )
+    };
+}
+
+#[cfg(feature = "jit")]
+const RUN_USAGE: &str = run_usage!("interpreter|threaded|jit");
+#[cfg(not(feature = "jit"))]
+const RUN_USAGE: &str = run_usage!("interpreter|threaded");
+
+const ASM_USAGE: &str = "\
+Usage: lc3 asm [-o image.obj|image.hex|image.bin|image.ihex|image.srec] source.asm
+
+Writes an .obj image, lc3tools text when the output ends in .hex or .bin, or
+Intel HEX or S-records for .ihex and .srec.";
+const DISASM_USAGE: &str = "Usage: lc3 disasm [--symbols file] [--isa lc3|lc3b] image-file";
+const DUMP_USAGE: &str = "\
+Usage: lc3 dump [--from address] [--to address] [--format hex|obj|raw|lc3hex|lc3bin|ihex|srec] [-o file] image-or-dump
+       lc3 dump --diff before after
+
+Shows an image or dump in hex and ASCII, or converts it to another format,
+by default the one the -o file name implies.
+--diff lists the words that differ between two images or dumps, and exits
+with status 1 when there are any. Files ending in .obj, .hex, .bin, .ihex and
+.srec are read as images, .raw files as raw big-endian words, and anything
+else as a hex dump.";
+const DEBUG_USAGE: &str = "\
+Usage: lc3 debug [--pc address|origin] [--reg Rn=value]... [--init file] [--symbols file] [image-file1] ...
+
+Type `help` at the (lc3) prompt for commands.";
+
+/// A subcommand taking its arguments. Errors are usage errors.
+type Command = fn(Vec<String>) -> Result<i32, String>;
+
+/// Options for `lc3 run` and `lc3 debug`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+    pub images: Vec<String>,
+    /// Snapshot to restore before running.
+    pub resume: Option<String>,
+    /// Where to save a snapshot once the program stops or is interrupted.
+    pub save_snapshot: Option<String>,
+    /// Where to write the input log once the program stops or is interrupted.
+    pub record: Option<String>,
+    /// Input log to replay instead of reading the keyboard.
+    pub replay: Option<String>,
+    /// Print a profile report once the program stops.
+    pub profile: bool,
+    /// Symbol table for labels in reports; defaults to the images' `.sym` files.
+    pub symbols: Option<String>,
+    /// Where to write a coverage report once the program stops.
+    pub coverage: Option<String>,
+    /// Assembly source for the coverage report; defaults to the first image's `.asm` file.
+    pub source: Option<String>,
+    /// Where to write the call graph (DOT) once the program stops.
+    pub call_graph: Option<String>,
+    /// Where to dump memory once the program stops.
+    pub dump: Option<String>,
+    /// Range of memory to dump, such as `x3000-x30FF`.
+    pub dump_range: Option<String>,
+    /// Format of the dump; defaults to the one its file name implies.
+    pub dump_format: Option<DumpFormat>,
+    pub engine: Engine,
+    /// Instruction set the images are written for.
+    pub isa: Isa,
+    /// Instruction and wall-clock limits.
+    pub budget: Budget,
+    /// Stop with a report when the program is stuck in an infinite loop.
+    pub detect_loops: bool,
+    /// Check the stack and calling conventions, reporting when the program stops.
+    pub check_stack: bool,
+    /// Detect reads of uninitialized memory and registers.
+    pub check_uninit: Option<InitMode>,
+    /// Init file of registers and memory to set before running.
+    pub init: Option<String>,
+    /// Start address: a number, a label, or `origin` for the first image's origin.
+    pub pc: Option<String>,
+    /// `--reg` settings such as `R1=x4000`.
+    pub registers: Vec<String>,
+    /// Where to write the instruction trace; `-` is stderr.
+    pub trace: Option<String>,
+    /// Suppress the "origin" and "Halting the program..." messages.
+    pub quiet: bool,
+    /// Whether problems with an image stop it loading.
+    pub strictness: Strictness,
+}
+
+impl RunOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--resume" => options.resume = Some(value()?),
+                "--save-snapshot" => options.save_snapshot = Some(value()?),
+                "--record" => options.record = Some(value()?),
+                "--replay" => options.replay = Some(value()?),
+                "--profile" => options.profile = true,
+                "--detect-loops" => options.detect_loops = true,
+                "--check-stack" => options.check_stack = true,
+                "--check-uninit" => options.check_uninit = Some(value()?.parse()?),
+                "--init" => options.init = Some(value()?),
+                "--pc" => options.pc = Some(value()?),
+                "--reg" => options.registers.push(value()?),
+                "--symbols" => options.symbols = Some(value()?),
+                "--coverage" => options.coverage = Some(value()?),
+                "--source" => options.source = Some(value()?),
+                "--call-graph" => options.call_graph = Some(value()?),
+                "--dump" => options.dump = Some(value()?),
+                "--dump-range" => options.dump_range = Some(value()?),
+                "--dump-format" => options.dump_format = Some(value()?.parse()?),
+                "--trace" => options.trace = Some(value()?),
+                "-q" | "--quiet" => options.quiet = true,
+                "--strict-load" => options.strictness = Strictness::Error,
+                "--engine" => options.engine = value()?.parse()?,
+                "--isa" => options.isa = value()?.parse()?,
+                "--max-instructions" => {
+                    let count = value()?;
+                    let count = count
+                        .parse()
+                        .map_err(|_| format!("bad instruction count {}", count))?;
+                    options.budget.max_instructions = Some(count)
+                }
+                "--timeout" => {
+                    let seconds = value()?;
+                    let parsed = seconds
+                        .parse()
+                        .ok()
+                        .filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)
+                        .ok_or_else(|| format!("bad timeout {}", seconds))?;
+                    options.budget.max_time = Some(Duration::from_secs_f64(parsed))
+                }
+                _ if arg.starts_with('-') && arg != "-" => {
+                    return Err(format!("unknown option {}", arg))
+                }
+                _ => options.images.push(arg),
+            }
+        }
+
+        if options.images.is_empty() && options.resume.is_none() {
+            return Err("no image to run".to_string());
+        }
+        if options.record.is_some() && options.replay.is_some() {
+            return Err("--record and --replay cannot be used together".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Runs the `lc3` command line with `args` (not including the program name)
+/// and returns the exit code.
+pub fn main(args: Vec<String>) -> i32 {
+    let Some(first) = args.first() else {
+        eprintln!("{}", USAGE);
+        return EXIT_USAGE;
+    };
+    let rest = args[1..].to_vec();
+    let wants_help = rest.iter().any(|arg| arg == "--help" || arg == "-h");
+    let (usage, command): (&str, Command) = match first.as_str() {
+        "--help" | "-h" | "help" => {
+            println!("{}", USAGE);
+            return EXIT_HALTED;
+        }
+        // `--dap` serves the Debug Adapter Protocol on stdio instead of running directly
+        "--dap" => {
+            return match dap::serve_stdio() {
+                Ok(()) => EXIT_HALTED,
+                Err(e) => {
+                    eprintln!("Debug adapter failed: {}", e);
+                    EXIT_FAULT
+                }
+            };
+        }
+        // `grade image spec` runs the image against a spec of test cases
+        "grade" => (grade::USAGE, grade::main),
+        "run" => (RUN_USAGE, |args| Ok(run(RunOptions::parse(args)?))),
+        "asm" => (ASM_USAGE, asm),
+        "disasm" => (DISASM_USAGE, disasm),
+        "dump" => (DUMP_USAGE, dump),
+        "debug" => (DEBUG_USAGE, |args| Ok(debug(RunOptions::parse(args)?))),
+        // No command: the arguments are for `run`
+        _ => {
+            if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+                println!("{}", RUN_USAGE);
+                return EXIT_HALTED;
+            }
+            return match RunOptions::parse(args) {
+                Ok(options) => run(options),
+                Err(e) => {
+                    eprintln!("{}\n\n{}", e, RUN_USAGE);
+                    EXIT_USAGE
+                }
+            };
+        }
+    };
+    if wants_help {
+        println!("{}", usage);
+        return EXIT_HALTED;
+    }
+    command(rest).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, usage);
+        EXIT_USAGE
+    })
+}
+
+/// Labels for reports: the `--symbols` file, or else the `.sym` file next to
+/// each image. Missing or unreadable files just mean fewer labels.
+fn load_symbols(images: &[String], path: Option<&str>) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    let paths: Vec<_> = match path {
+        Some(path) => vec![PathBuf::from(path)],
+        None => images
+            .iter()
+            .map(|image| Path::new(image).with_extension("sym"))
+            .collect(),
+    };
+    for file in paths {
+        match SymbolTable::load(&file) {
+            Ok(table) => {
+                for (name, address) in table.iter() {
+                    symbols.insert(name, address);
+                }
+            }
+            Err(e) if path.is_some() => {
+                eprintln!("Failed to load symbols: {} ({})", file.display(), e)
+            }
+            Err(_) => {}
+        }
+    }
+    symbols
+}
+
+/// Loads the images, snapshot and machine setup of `options` into `vm`.
+/// Returns the symbols for reports.
+fn load(vm: &mut VM, options: &RunOptions) -> Result<SymbolTable, String> {
+    if let Some(path) = &options.replay {
+        let log = InputLog::load(path)
+            .map_err(|e| format!("Failed to load input log: {} ({})", path, e))?;
+        vm.input_trace = InputTrace::replaying(log);
+    } else if options.record.is_some() {
+        vm.input_trace = InputTrace::Recording(InputLog::new());
+    }
+
+    vm.engine = options.engine;
+    vm.isa = options.isa;
+    vm.quiet = options.quiet;
+    if options.budget != Budget::default() {
+        vm.budget = Some(options.budget);
+    }
+    if options.profile {
+        vm.profiler = Some(Profiler::new());
+    }
+    if options.coverage.is_some() {
+        vm.coverage = Some(Coverage::new());
+    }
+    if options.detect_loops {
+        vm.loop_detector = Some(LoopDetector::new());
+    }
+
+    if let Some(mode) = options.check_uninit {
+        vm.init_checker = Some(InitChecker::new(mode));
+    }
+
+    if let Some(path) = &options.resume {
+        vm.load_snapshot(path)
+            .map_err(|e| format!("Failed to load snapshot: {} ({})", path, e))?;
+        // A snapshot doesn't say what was set, so trust all of it
+        if let Some(checker) = vm.init_checker.as_mut() {
+            checker.initialize(0..ADDRESS_SPACE);
+            for register in 0..Registers::R_COUNT as usize {
+                checker.initialize_register(register);
+            }
+        }
+    } else {
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+    }
+
+    let mut origin = None;
+    let mut loader = ImageLoader::new(options.strictness);
+    for filename in &options.images {
+        let loaded = loader
+            .load(vm, filename)
+            .map_err(|e| format!("Failed to load image: {} ({})", filename, e))?;
+        for warning in &loaded.warnings {
+            eprintln!("Warning: {}: {}", filename, warning);
+        }
+        if !options.quiet {
+            println!("origin {:}", loaded.origin);
+        }
+        origin.get_or_insert(loaded.origin);
+    }
+    if options.check_stack {
+        let mut checker = StackChecker::new();
+        for range in loader.ranges().filter(|range| !range.is_empty()) {
+            let (start, end) = (range.start as u16, (range.end - 1) as u16);
+            match vm.isa {
+                Isa::Lc3 => checker.protect(start, end),
+                // R6 holds byte addresses
+                Isa::Lc3b => checker.protect(start << 1, (end << 1) | 1),
+            }
+        }
+        vm.stack_checker = Some(checker);
+    }
+
+    let symbols = load_symbols(&options.images, options.symbols.as_deref());
+    initialize(vm, options, origin, &symbols)?;
+    if let Some(path) = &options.trace {
+        let tracer = Tracer::create(path, symbols.clone())
+            .map_err(|e| format!("Failed to create trace: {} ({})", path, e))?;
+        vm.tracer = Some(tracer);
+    }
+    Ok(symbols)
+}
+
+/// Applies the init file, then `--reg` and `--pc`, which take precedence.
+fn initialize(
+    vm: &mut VM,
+    options: &RunOptions,
+    origin: Option<u16>,
+    symbols: &SymbolTable,
+) -> Result<(), String> {
+    let mut init = match &options.init {
+        Some(path) => InitState::load(path, symbols)
+            .map_err(|e| format!("Failed to load init file: {} ({})", path, e))?,
+        None => InitState::new(),
+    };
+    for setting in &options.registers {
+        let register = setting
+            .split_once('=')
+            .and_then(|(name, value)| Some((register_index(name)?, parse_word(value, symbols)?)))
+            .ok_or(format!(
+                "Bad register setting: {} (expected e.g. R1=x4000)",
+                setting
+            ))?;
+        init.set_register(register.0, register.1);
+    }
+    if let Some(pc) = &options.pc {
+        let pc = if pc == "origin" {
+            origin.ok_or("--pc origin needs an image")?
+        } else {
+            parse_word(pc, symbols).ok_or(format!("Bad start address: {}", pc))?
+        };
+        init.set_register(Registers::R_PC as usize, pc);
+    }
+    init.apply(vm)
+        .map_err(|e| format!("Failed to set up memory: {}", e))
+}
+
+/// The exit code for how a run ended.
+pub fn exit_code(result: &Result<(), Fault>, interrupted: bool) -> i32 {
+    match result {
+        _ if interrupted => EXIT_INTERRUPTED,
+        Ok(()) => EXIT_HALTED,
+        Err(Fault::BudgetExhausted { .. }) => EXIT_TIMEOUT,
+        Err(_) => EXIT_FAULT,
+    }
+}
+
+/// `lc3 run`: runs the images on the terminal, then writes the reports asked for.
+pub fn run(options: RunOptions) -> i32 {
+    let mut vm = VM::new();
+    input_buffering::setup();
+    let code = run_on_terminal(&mut vm, &options);
+    input_buffering::restore_input_buffering();
+    code
+}
+
+fn run_on_terminal(vm: &mut VM, options: &RunOptions) -> i32 {
+    let symbols = match load(vm, options) {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let pc = vm.registers_storage[Registers::R_PC as usize];
+    vm.call_stack = Some(CallStack::new(pc));
+
+    if options.save_snapshot.is_some() || options.record.is_some() {
+        input_buffering::defer_interrupts();
+    }
+
+    let result = vm.execute_until_interrupted();
+    if let Some(tracer) = vm.tracer.as_mut() {
+        tracer.flush();
+    }
+    let pc = vm.registers_storage[Registers::R_PC as usize];
+    let interrupted = input_buffering::interrupted();
+    let call_stack = vm.call_stack.as_ref().unwrap();
+    if interrupted {
+        eprintln!("\nInterrupted at x{:04X}", pc);
+        eprint!("{}", call_stack.backtrace(pc, &symbols));
+    } else if let Err(fault) = result {
+        eprintln!("{}", fault);
+        if let (Fault::InfiniteLoop { .. }, Some(detector)) = (fault, &vm.loop_detector) {
+            eprint!(
+                "{}",
+                detector.report(vm.isa, &vm.memory.contents(), &symbols)
+            );
+        }
+        eprint!("{}", call_stack.backtrace(pc, &symbols));
+    } else if call_stack.depth() > 0 {
+        // Halted inside a subroutine
+        eprint!("{}", call_stack.backtrace(pc, &symbols));
+    }
+    if let Some(path) = &options.call_graph {
+        match std::fs::write(path, call_stack.to_dot(&symbols)) {
+            Ok(()) => eprintln!("Call graph written to {}", path),
+            Err(e) => eprintln!("Failed to write call graph: {} ({})", path, e),
+        }
+    }
+
+    if let Some(checker) = &vm.stack_checker {
+        eprint!("{}", checker.report(&symbols));
+    }
+    if let Some(checker) = &vm.init_checker {
+        eprint!("{}", checker.report(&symbols));
+    }
+
+    if let Some(profiler) = &vm.profiler {
+        eprint!(
+            "\n{}",
+            profiler.report(vm.isa, &vm.memory.contents(), &symbols, 20)
+        );
+    }
+
+    if let (Some(path), Some(coverage)) = (&options.coverage, &vm.coverage) {
+        match write_coverage(vm, coverage, path, options, &symbols) {
+            Ok(()) => eprintln!("Coverage written to {}", path),
+            Err(e) => eprintln!("Failed to write coverage: {} ({})", path, e),
+        }
+    }
+
+    if let Some(path) = &options.dump {
+        match write_dump(vm, path, options, &symbols) {
+            Ok(()) => eprintln!("Memory dumped to {}", path),
+            Err(e) => eprintln!("Failed to dump memory: {} ({})", path, e),
+        }
+    }
+
+    if let Some(path) = &options.save_snapshot {
+        match vm.save_snapshot(path) {
+            Ok(()) => eprintln!("Snapshot saved to {}", path),
+            Err(e) => eprintln!("Failed to save snapshot: {} ({})", path, e),
+        }
+    }
+    if let (Some(path), Some(log)) = (&options.record, vm.input_trace.log()) {
+        match log.save(path) {
+            Ok(()) => eprintln!("Input log saved to {}", path),
+            Err(e) => eprintln!("Failed to save input log: {} ({})", path, e),
+        }
+    }
+    exit_code(&result, interrupted)
+}
+
+fn write_dump(
+    vm: &VM,
+    path: &str,
+    options: &RunOptions,
+    symbols: &SymbolTable,
+) -> Result<(), String> {
+    let (from, to) = match &options.dump_range {
+        Some(range) => parse_range(range, symbols).ok_or(format!("bad range {}", range))?,
+        None => (0, u16::MAX),
+    };
+    let format = options
+        .dump_format
+        .unwrap_or_else(|| DumpFormat::from_path(path));
+    vm.dump(from, to)
+        .save(path, format)
+        .map_err(|e| e.to_string())
+}
+
+/// Writes lcov output when assembly source is available, and the
+/// address-level report otherwise.
+fn write_coverage(
+    vm: &VM,
+    coverage: &Coverage,
+    path: &str,
+    options: &RunOptions,
+    symbols: &SymbolTable,
+) -> io::Result<()> {
+    let source = match &options.source {
+        Some(source) => Some(PathBuf::from(source)),
+        None => options
+            .images
+            .first()
+            .map(|image| Path::new(image).with_extension("asm"))
+            .filter(|source| source.exists()),
+    };
+    let report = match source {
+        // Assembly source is LC-3, so LC-3b coverage is always by address
+        Some(source) if vm.isa == Isa::Lc3 => {
+            let text = std::fs::read_to_string(&source)?;
+            let (map, _) =
+                scan_asm(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            coverage.lcov(&vm.memory.contents(), &map, &source.display().to_string())
+        }
+        _ => coverage.address_report(vm.isa, &vm.memory.contents(), symbols),
+    };
+    std::fs::write(path, report)
+}
+
+/// `lc3 debug`: loads the images and hands over to the interactive debugger.
+/// The terminal stays in line mode, so the program reads whole lines too.
+fn debug(options: RunOptions) -> i32 {
+    let mut vm = VM::new();
+    vm.history = Some(History::new(DEBUG_HISTORY));
+    let symbols = match load(&mut vm, &options) {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let pc = vm.registers_storage[Registers::R_PC as usize];
+    vm.call_stack = Some(CallStack::new(pc));
+
+    input_buffering::handle_interrupts();
+    input_buffering::defer_interrupts();
+    let mut debugger = Debugger::new(vm);
+    let stdin = io::stdin();
+    match repl(&mut debugger, &symbols, stdin.lock(), io::stdout()) {
+        Ok(()) => EXIT_HALTED,
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_USAGE
+        }
+    }
+}
+
+/// Takes the single positional argument, handing each option and its value
+/// to `option`.
+fn single_file(
+    args: Vec<String>,
+    mut option: impl FnMut(&str, String) -> Result<(), String>,
+) -> Result<String, String> {
+    let mut file = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with('-') {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} needs a value", arg))?;
+            option(&arg, value)?;
+        } else if file.replace(arg).is_some() {
+            return Err("only one file can be given".to_string());
+        }
+    }
+    file.ok_or_else(|| "no file given".to_string())
+}
+
+/// `lc3 asm`: writes the image next to the source (or to `-o`), with the
+/// symbol table beside it. Each extra `.ORIG` block goes to its own image,
+/// named after its origin.
+fn asm(args: Vec<String>) -> Result<i32, String> {
+    let mut output = None;
+    let source = single_file(args, |option, value| {
+        match option {
+            "-o" => output = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    })?;
+    let output = output.unwrap_or_else(|| Path::new(&source).with_extension("obj"));
+
+    let text = match std::fs::read_to_string(&source) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Failed to read source: {} ({})", source, e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    let assembly = match assemble(&text) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            return Ok(EXIT_FAULT);
+        }
+    };
+
+    let format = ImageFormat::from_path(&output).unwrap_or(ImageFormat::Obj);
+    let stem = output.with_extension("");
+    let mut files = vec![(
+        output.with_extension("sym"),
+        format_symbols(&assembly.symbols).into_bytes(),
+    )];
+    for (index, section) in assembly.sections.iter().enumerate() {
+        let path = match index {
+            0 => output.clone(),
+            _ => PathBuf::from(format!(
+                "{}-x{:04X}.{}",
+                stem.display(),
+                section.origin,
+                format
+            )),
+        };
+        files.push((path, section.to_image(format)));
+    }
+    for (path, contents) in files {
+        if let Err(e) = std::fs::write(&path, contents) {
+            eprintln!("Failed to write {} ({})", path.display(), e);
+            return Ok(EXIT_USAGE);
+        }
+        println!("Wrote {}", path.display());
+    }
+    Ok(EXIT_HALTED)
+}
+
+/// `lc3 disasm`: lists every word of an image with its label and disassembly.
+/// LC-3b images are listed at byte addresses, taking the origin as `run`
+/// does.
+fn disasm(args: Vec<String>) -> Result<i32, String> {
+    let mut symbols_path = None;
+    let mut isa = Isa::Lc3;
+    let image = single_file(args, |option, value| {
+        match option {
+            "--symbols" => symbols_path = Some(value),
+            "--isa" => isa = value.parse()?,
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    })?;
+    let Dump { start, words } = match load_dump(&image) {
+        Ok(dump) => dump,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    let symbols = load_symbols(std::slice::from_ref(&image), symbols_path.as_deref());
+    let (start, step) = match isa {
+        Isa::Lc3 => (start, 1),
+        // Intel HEX and S-record addresses count words
+        Isa::Lc3b => match ImageFormat::from_path(&image) {
+            Some(ImageFormat::IntelHex | ImageFormat::SRecord) => (start.wrapping_mul(2), 2),
+            _ => (start, 2),
+        },
+    };
+    for (address, &word) in (start..=u16::MAX).step_by(step).zip(&words) {
+        let text = match isa {
+            Isa::Lc3 => disassemble(address, word, &symbols),
+            Isa::Lc3b => lc3b::disassemble(address, word, &symbols),
+        };
+        println!(
+            "x{:04X}  {:04X}  {:<16} {}",
+            address,
+            word,
+            symbols.label_at(address).unwrap_or(""),
+            text
+        );
+    }
+    Ok(EXIT_HALTED)
+}
+
+/// `lc3 dump`: shows an image or dump in hex and ASCII, by default all of
+/// it, or converts it to another format.
+fn dump(args: Vec<String>) -> Result<i32, String> {
+    if args.first().map(String::as_str) == Some("--diff") {
+        return diff(&args[1..]);
+    }
+    let (mut from, mut to, mut format, mut output) = (None, None, None, None);
+    let path = single_file(args, |option, value| {
+        let address =
+            || parse_word(&value, &SymbolTable::new()).ok_or(format!("bad address {}", value));
+        match option {
+            "--from" => from = Some(address()?),
+            "--to" => to = Some(address()?),
+            "--format" => format = Some(value.parse()?),
+            "-o" => output = Some(value),
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    })?;
+    let image = match load_dump(&path) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    if image.words.is_empty() {
+        return Ok(EXIT_HALTED);
+    }
+    // In usize, as a full image holds 65536 words
+    let start = image.start as usize;
+    let end = start + image.words.len() - 1;
+    let from = from.map_or(start, usize::from);
+    let to = to.map_or(end, usize::from);
+    if from > to || from < start || to > end {
+        return Err(format!(
+            "x{:04X}-x{:04X} is not within x{:04X}-x{:04X}",
+            from, to, start, end
+        ));
+    }
+    let words = image.words[from - start..=to - start].to_vec();
+    let format = format.unwrap_or_else(|| match &output {
+        Some(path) => DumpFormat::from_path(path),
+        None => DumpFormat::Hex,
+    });
+    let bytes = Dump {
+        start: from as u16,
+        words,
+    }
+    .to_bytes(format);
+    let written = match &output {
+        Some(path) => std::fs::write(path, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
+    if let Err(e) = written {
+        eprintln!("Failed to write dump ({})", e);
+        return Ok(EXIT_USAGE);
+    }
+    Ok(EXIT_HALTED)
+}
+
+/// `lc3 dump --diff`: lists the words that differ, exiting with 1 if any do.
+fn diff(paths: &[String]) -> Result<i32, String> {
+    let [before, after] = paths else {
+        return Err("--diff needs two files".to_string());
+    };
+    let (before, after) = match (load_dump(before), load_dump(after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    let differences = before.diff(&after);
+    let symbols = load_symbols(&paths[..1], None);
+    print!("{}", format_diff(&differences, &symbols));
+    Ok(i32::from(!differences.is_empty()))
+}
+
+fn load_dump(path: &str) -> Result<Dump, String> {
+    Dump::load(path).map_err(|e| format!("Failed to load {} ({})", path, e))
+}
@@ -0,0 +1,109 @@
+use crate::input_buffering::{check_key, interrupted, interrupts_deferred};
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+/// Where the VM's keyboard input comes from and where its display output goes.
+///
+/// `Terminal` talks to the real stdin/stdout (the default for the CLI), while
+/// `Buffered` keeps both sides in memory so the VM can be driven headlessly
+/// (tests, the debug adapter, ...).
+#[derive(Debug, Clone, Default)]
+pub enum Console {
+    #[default]
+    Terminal,
+    Buffered {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    },
+}
+
+impl Console {
+    pub fn buffered(input: &[u8]) -> Self {
+        Console::Buffered {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Returns true when a key can be read without blocking.
+    pub fn poll_key(&mut self) -> bool {
+        match self {
+            Console::Terminal => check_key(),
+            Console::Buffered { input, .. } => !input.is_empty(),
+        }
+    }
+
+    /// Reads one byte of input, or `None` once the input is exhausted.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        match self {
+            Console::Terminal => {
+                // Wait in small steps while Ctrl+C is deferred so it can end the wait
+                while interrupts_deferred() && !check_key() {
+                    if interrupted() {
+                        return None;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                let mut buffer = [0u8; 1];
+                io::stdin().read_exact(&mut buffer).ok()?;
+                Some(buffer[0])
+            }
+            Console::Buffered { input, .. } => input.pop_front(),
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Console::Terminal => {
+                let mut out = io::stdout();
+                out.write_all(bytes).unwrap();
+                out.flush().unwrap();
+            }
+            Console::Buffered { output, .. } => output.extend_from_slice(bytes),
+        }
+    }
+
+    /// Queues more keyboard input. Has no effect on the terminal console.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        if let Console::Buffered { input, .. } = self {
+            input.extend(bytes);
+        }
+    }
+
+    /// Puts bytes back at the front of the input, as if they were never read.
+    /// Has no effect on the terminal console.
+    pub fn unread(&mut self, bytes: &[u8]) {
+        if let Console::Buffered { input, .. } = self {
+            for &byte in bytes.iter().rev() {
+                input.push_front(byte);
+            }
+        }
+    }
+
+    /// Input queued but not yet read. Always empty for the terminal console.
+    pub fn pending_input(&self) -> Vec<u8> {
+        match self {
+            Console::Terminal => Vec::new(),
+            Console::Buffered { input, .. } => input.iter().copied().collect(),
+        }
+    }
+
+    /// Discards any queued input in favour of `bytes`. Has no effect on the
+    /// terminal console.
+    pub fn replace_input(&mut self, bytes: &[u8]) {
+        if let Console::Buffered { input, .. } = self {
+            *input = bytes.iter().copied().collect();
+        }
+    }
+
+    /// Drains everything written so far. Always empty for the terminal console.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        match self {
+            Console::Terminal => Vec::new(),
+            Console::Buffered { output, .. } => std::mem::take(output),
+        }
+    }
+}
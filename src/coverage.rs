@@ -0,0 +1,157 @@
+use crate::disasm::disassemble_in;
+use crate::run::Isa;
+use crate::symbols::{SourceMap, SymbolTable};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// How often a BR instruction went each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Executed addresses and branch directions, collected while the VM runs.
+/// Install one in `VM::coverage` before running.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, BranchCounts>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// True for BR instructions that test at least one condition code.
+fn is_branch(instr: u16) -> bool {
+    instr >> 12 == 0 && (instr >> 9) & 0x7 != 0
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            hits: vec![0; 1 << 16],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    pub fn branch(&self, address: u16) -> Option<BranchCounts> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Executed addresses in ascending order.
+    pub fn executed(&self) -> impl Iterator<Item = u16> + '_ {
+        self.hits
+            .iter()
+            .enumerate()
+            .filter(|(_, &hits)| hits > 0)
+            .map(|(address, _)| address as u16)
+    }
+
+    /// Records an instruction that completed. `cond` is the condition code
+    /// register when it ran, which decides the direction of a BR.
+    pub(crate) fn record(&mut self, pc: u16, instr: u16, cond: u16) {
+        self.hits[pc as usize] += 1;
+        if is_branch(instr) {
+            let counts = self.branches.entry(pc).or_default();
+            if (instr >> 9) & cond != 0 {
+                counts.taken += 1;
+            } else {
+                counts.not_taken += 1;
+            }
+        }
+    }
+
+    /// Formats the coverage of the instructions in `map` as an lcov tracefile
+    /// for `source_path`. Each BR gets two lcov branches: taken, then not taken.
+    /// `memory` is used to find the BR instructions that never executed.
+    pub fn lcov(&self, memory: &[u16], map: &SourceMap, source_path: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", source_path).unwrap();
+
+        let (mut lines_found, mut lines_hit) = (0, 0);
+        let (mut branches_found, mut branches_hit) = (0, 0);
+        for entry in map.lines().iter().filter(|entry| entry.is_instruction) {
+            let hits = self.hits(entry.address);
+            lines_found += 1;
+            if hits > 0 {
+                lines_hit += 1;
+            }
+            writeln!(out, "DA:{},{}", entry.line, hits).unwrap();
+
+            if !is_branch(memory[entry.address as usize]) {
+                continue;
+            }
+            let counts = self.branch(entry.address).unwrap_or_default();
+            for (index, count) in [counts.taken, counts.not_taken].into_iter().enumerate() {
+                branches_found += 1;
+                if count > 0 {
+                    branches_hit += 1;
+                }
+                if hits > 0 {
+                    writeln!(out, "BRDA:{},0,{},{}", entry.line, index, count).unwrap();
+                } else {
+                    writeln!(out, "BRDA:{},0,{},-", entry.line, index).unwrap();
+                }
+            }
+        }
+
+        writeln!(out, "BRF:{}", branches_found).unwrap();
+        writeln!(out, "BRH:{}", branches_hit).unwrap();
+        writeln!(out, "LF:{}", lines_found).unwrap();
+        writeln!(out, "LH:{}", lines_hit).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+        out
+    }
+
+    /// Formats coverage by address, for programs without assembly source:
+    /// every executed address with its hit count and disassembly, and the
+    /// taken/not-taken counts of each BR. `memory` is disassembled as `isa`.
+    pub fn address_report(&self, isa: Isa, memory: &[u16], symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        let executed: Vec<u16> = self.executed().collect();
+        let directions = self
+            .branches
+            .values()
+            .map(|counts| (counts.taken > 0) as usize + (counts.not_taken > 0) as usize)
+            .sum::<usize>();
+        writeln!(
+            out,
+            "Coverage: {} addresses executed, {} of {} branch directions taken",
+            executed.len(),
+            directions,
+            self.branches.len() * 2
+        )
+        .unwrap();
+        for address in executed {
+            write!(
+                out,
+                "x{:04X} {:>10}  {:<16} {}",
+                address,
+                self.hits(address),
+                symbols.label_at(address).unwrap_or(""),
+                disassemble_in(isa, address, memory, symbols)
+            )
+            .unwrap();
+            if let Some(counts) = self.branch(address) {
+                write!(
+                    out,
+                    "  (taken {}, not taken {})",
+                    counts.taken, counts.not_taken
+                )
+                .unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,680 @@
+//! Debug Adapter Protocol server, so editors can drive the VM as a debuggee.
+//!
+//! Messages are exchanged over stdio using the usual `Content-Length`
+//! framing. The program's console is buffered and its output is forwarded as
+//! `output` events, keeping stdout free for the protocol.
+
+use crate::callstack::CallStack;
+use crate::console::Console;
+use crate::debugger::{Debugger, StopReason};
+use crate::history::History;
+use crate::run::{Registers, VM};
+use crate::symbols::{parse_number, scan_asm, SourceMap, SymbolTable};
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+const THREAD_ID: i64 = 1;
+/// Instructions executed between checks for incoming requests while running.
+const RUN_SLICE: u64 = 10_000;
+/// Words shown by each memory region in the variables view.
+const REGION_WORDS: u16 = 16;
+/// Instructions kept for stepping backwards unless `history` is given at launch.
+const DEFAULT_HISTORY: usize = 100_000;
+
+const REGISTERS_REF: i64 = 1;
+const MEMORY_REF: i64 = 2;
+const REGION_REF_BASE: i64 = 100;
+
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+/// Reads one framed message. Returns `Ok(None)` at end of stream.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves a single debug session over stdin/stdout.
+pub fn serve_stdio() -> io::Result<()> {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = BufReader::new(io::stdin());
+        while let Ok(Some(message)) = read_message(&mut stdin) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    DapServer::new(io::stdout()).serve(requests)
+}
+
+/// A named block of memory shown under the "Memory" scope.
+#[derive(Debug, Clone)]
+struct Region {
+    name: String,
+    start: u16,
+    length: u16,
+}
+
+#[derive(Debug)]
+struct Session {
+    debugger: Debugger,
+    symbols: SymbolTable,
+    source_map: SourceMap,
+    source: Option<PathBuf>,
+    regions: Vec<Region>,
+    stop_on_entry: bool,
+    /// Breakpoints by the request that set them; each request replaces its own kind.
+    line_breakpoints: Vec<u16>,
+    function_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+}
+
+pub struct DapServer<W: Write> {
+    writer: W,
+    seq: i64,
+    session: Option<Session>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            seq: 0,
+            session: None,
+        }
+    }
+
+    /// Handles requests until the client disconnects or the channel closes.
+    pub fn serve(mut self, requests: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let running = self
+                .session
+                .as_ref()
+                .is_some_and(|session| session.debugger.is_running());
+            let request = if running {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match request {
+                Some(request) => {
+                    if !self.handle(&request)? {
+                        return Ok(());
+                    }
+                }
+                None => self.run_slice()?,
+            }
+        }
+    }
+
+    /// Dispatches one request. Returns false once the session should end.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(arguments),
+            "configurationDone" => {
+                self.respond(request, Ok(Value::Null))?;
+                return self.configuration_done().map(|_| true);
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "LC-3" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REF, "expensive": false },
+            ]})),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self
+                .resume(Debugger::continue_)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.resume(Debugger::step_over).map(|_| Value::Null),
+            "stepIn" => self.resume(Debugger::step_into).map(|_| Value::Null),
+            "stepOut" => self.resume(Debugger::step_out).map(|_| Value::Null),
+            "stepBack" | "reverseContinue" => {
+                let Some(session) = self.session.as_mut() else {
+                    return self
+                        .respond(request, Err("no program launched".to_string()))
+                        .map(|_| true);
+                };
+                let reason = if command == "stepBack" {
+                    session.debugger.step_back()
+                } else {
+                    session.debugger.reverse_continue()
+                };
+                self.respond(request, Ok(Value::Null))?;
+                self.report_stop(reason)?;
+                return Ok(true);
+            }
+            "pause" => {
+                self.respond(request, Ok(Value::Null))?;
+                if let Some(session) = self.session.as_mut() {
+                    if session.debugger.is_running() {
+                        session.debugger.interrupt();
+                        self.flush_output()?;
+                        self.stopped("pause", None)?;
+                    }
+                }
+                return Ok(true);
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                if command == "terminate" {
+                    self.event("terminated", Value::Null)?;
+                }
+                return Ok(false);
+            }
+            _ => Err(format!("unsupported request: {}", command)),
+        };
+        self.respond(request, result)?;
+
+        if command == "launch" && self.session.is_some() {
+            self.event("initialized", Value::Null)?;
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("launch requires a `program` (.obj) path")?;
+        let program = Path::new(program);
+
+        let mut vm = VM::new();
+        let input = arguments["input"].as_str().unwrap_or_default();
+        vm.console = Console::buffered(input.as_bytes());
+        let origin = vm
+            .read_image(&program.to_string_lossy())
+            .map_err(|e| format!("Failed to load image: {} ({})", program.display(), e))?;
+        vm.registers_storage[Registers::R_PC as usize] = origin;
+        vm.call_stack = Some(CallStack::new(origin));
+        let history = arguments["history"]
+            .as_u64()
+            .map_or(DEFAULT_HISTORY, |size| size as usize);
+        if history > 0 {
+            vm.history = Some(History::new(history));
+        }
+
+        let source = optional_path(&arguments["source"], program, "asm");
+        let (source_map, mut symbols) = match &source {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read source {}: {}", path.display(), e))?;
+                scan_asm(&text)?
+            }
+            None => (SourceMap::new(), SymbolTable::new()),
+        };
+        if let Some(path) = optional_path(&arguments["symbols"], program, "sym") {
+            symbols = SymbolTable::load(&path)
+                .map_err(|e| format!("Could not read symbols {}: {}", path.display(), e))?;
+        }
+
+        let mut regions = Vec::new();
+        if let Some(configured) = arguments["memory"].as_array() {
+            for region in configured {
+                let start = parse_address(&region["start"], &symbols)
+                    .ok_or("memory regions need a `start` address or label")?;
+                regions.push(Region {
+                    name: region["name"].as_str().unwrap_or("region").to_string(),
+                    start,
+                    length: region["length"].as_u64().unwrap_or(REGION_WORDS as u64) as u16,
+                });
+            }
+        }
+
+        self.session = Some(Session {
+            debugger: Debugger::new(vm),
+            symbols,
+            source_map,
+            source,
+            regions,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+            line_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+        });
+        Ok(Value::Null)
+    }
+
+    fn configuration_done(&mut self) -> io::Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        if session.stop_on_entry {
+            self.stopped("entry", None)
+        } else {
+            session.debugger.continue_();
+            Ok(())
+        }
+    }
+
+    fn resume(&mut self, start: fn(&mut Debugger)) -> Result<(), String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        start(&mut session.debugger);
+        Ok(())
+    }
+
+    fn run_slice(&mut self) -> io::Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        let stop = session.debugger.run(RUN_SLICE);
+        self.flush_output()?;
+        match stop {
+            Some(reason) => self.report_stop(reason),
+            None => Ok(()),
+        }
+    }
+
+    fn report_stop(&mut self, reason: StopReason) -> io::Result<()> {
+        match reason {
+            StopReason::Step => self.stopped("step", None),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::HistoryStart => self.stopped(
+                "step",
+                Some("Reached the start of recorded history".to_string()),
+            ),
+            StopReason::Halted => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", Value::Null)
+            }
+            StopReason::Fault(fault) => {
+                self.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{}\n", fault) }),
+                )?;
+                self.stopped("exception", Some(fault.to_string()))
+            }
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        let path = arguments["source"]["path"].as_str().unwrap_or_default();
+        let in_program = same_file(session.source.as_deref(), path);
+
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = bp["line"].as_u64().unwrap_or_default();
+            let resolved = in_program
+                .then(|| session.source_map.resolve_line(line as usize))
+                .flatten();
+            match resolved {
+                Some(entry) => {
+                    addresses.push(entry.address);
+                    breakpoints.push(json!({ "verified": true, "line": entry.line }));
+                }
+                None => breakpoints.push(
+                    json!({ "verified": false, "line": line, "message": "no code at this line" }),
+                ),
+            }
+        }
+        session.line_breakpoints = addresses;
+        session.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = bp["name"].as_str().unwrap_or_default();
+            match session.symbols.address_of(name) {
+                Some(address) => {
+                    addresses.push(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "instructionReference": format!("0x{:04X}", address),
+                    }));
+                }
+                None => breakpoints.push(
+                    json!({ "verified": false, "message": format!("unknown label {}", name) }),
+                ),
+            }
+        }
+        session.function_breakpoints = addresses;
+        session.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = bp["instructionReference"].as_str().unwrap_or_default();
+            let offset = bp["offset"].as_i64().unwrap_or(0);
+            match parse_number(reference) {
+                Some(address) => {
+                    let address = (address as i64 + offset) as u16;
+                    addresses.push(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "instructionReference": format!("0x{:04X}", address),
+                    }));
+                }
+                None => breakpoints.push(
+                    json!({ "verified": false, "message": format!("bad address {}", reference) }),
+                ),
+            }
+        }
+        session.instruction_breakpoints = addresses;
+        session.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        // The innermost frame is at PC; each caller is at its call site
+        let mut addresses = vec![session.debugger.pc()];
+        if let Some(call_stack) = &session.debugger.vm.call_stack {
+            addresses.extend(
+                call_stack
+                    .frames()
+                    .iter()
+                    .rev()
+                    .map(|frame| frame.call_site),
+            );
+        }
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, &pc)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": session.symbols.describe(pc),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:04X}", pc),
+                });
+                if let (Some(source), Some(entry)) =
+                    (&session.source, session.source_map.line_at(pc))
+                {
+                    frame["source"] = json!({ "path": source.to_string_lossy() });
+                    frame["line"] = json!(entry.line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        Ok(json!({ "totalFrames": frames.len(), "stackFrames": frames }))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program launched")?;
+        let reference = arguments["variablesReference"].as_i64().unwrap_or_default();
+        let vm = &session.debugger.vm;
+        let variables: Vec<Value> = match reference {
+            REGISTERS_REF => REGISTER_NAMES
+                .iter()
+                .enumerate()
+                .map(|(index, name)| {
+                    let value = vm.registers_storage[index];
+                    let shown = if index == Registers::R_COND as usize {
+                        condition_name(value).to_string()
+                    } else {
+                        format_word(value)
+                    };
+                    json!({ "name": name, "value": shown, "variablesReference": 0 })
+                })
+                .collect(),
+            MEMORY_REF => session
+                .regions_with_defaults()
+                .iter()
+                .enumerate()
+                .map(|(index, region)| {
+                    json!({
+                        "name": region.name,
+                        "value": format!("x{:04X}..x{:04X}", region.start, region.start.wrapping_add(region.length.saturating_sub(1))),
+                        "variablesReference": REGION_REF_BASE + index as i64,
+                        "memoryReference": format!("0x{:04X}", region.start),
+                    })
+                })
+                .collect(),
+            _ => {
+                let regions = session.regions_with_defaults();
+                let region = usize::try_from(reference - REGION_REF_BASE)
+                    .ok()
+                    .and_then(|index| regions.get(index))
+                    .ok_or("unknown variables reference")?;
+                (0..region.length)
+                    .map(|offset| {
+                        let address = region.start.wrapping_add(offset);
+                        let name = match session.symbols.label_at(address) {
+                            Some(label) => format!("x{:04X} {}", address, label),
+                            None => format!("x{:04X}", address),
+                        };
+                        json!({
+                            "name": name,
+                            "value": format_word(vm.memory.read(address as usize).unwrap_or(0)),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect()
+            }
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program launched")?;
+        let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+        let vm = &session.debugger.vm;
+        let result = if let Some(target) = expression.strip_prefix("lastwrite ") {
+            let address = parse_address(&json!(target), &session.symbols)
+                .ok_or_else(|| format!("cannot evaluate {}", target))?;
+            match vm
+                .history
+                .as_ref()
+                .and_then(|history| history.last_write(address))
+            {
+                Some((record, write)) => format!(
+                    "x{:04X} last written at instruction {} by x{:04X} ({}): x{:04X} -> x{:04X}",
+                    address,
+                    record.count,
+                    record.pc,
+                    session.symbols.describe(record.pc),
+                    write.old,
+                    write.new
+                ),
+                None => format!(
+                    "x{:04X} has not been written within the recorded history",
+                    address
+                ),
+            }
+        } else if let Some(index) = REGISTER_NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(expression))
+        {
+            format_word(vm.registers_storage[index])
+        } else {
+            let address = parse_address(&json!(expression), &session.symbols)
+                .ok_or_else(|| format!("cannot evaluate {}", expression))?;
+            format!(
+                "[x{:04X}] = {}",
+                address,
+                format_word(vm.memory.read(address as usize).unwrap_or(0))
+            )
+        };
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        let output = session.debugger.vm.console.take_output();
+        if output.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&output).into_owned();
+        self.event("output", json!({ "category": "stdout", "output": text }))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body =
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+            body["description"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        self.seq += 1;
+        let mut response = json!({
+            "seq": self.seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        write_message(&mut self.writer, &response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.seq += 1;
+        let mut message = json!({ "seq": self.seq, "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        write_message(&mut self.writer, &message)
+    }
+}
+
+impl Session {
+    fn sync_breakpoints(&mut self) {
+        let all = self
+            .line_breakpoints
+            .iter()
+            .chain(&self.function_breakpoints)
+            .chain(&self.instruction_breakpoints)
+            .copied();
+        self.debugger.set_breakpoints(all);
+    }
+
+    /// The configured regions, preceded by views around PC and the R6 stack
+    /// pointer and the keyboard device registers.
+    fn regions_with_defaults(&self) -> Vec<Region> {
+        let vm = &self.debugger.vm;
+        let mut regions = vec![
+            Region {
+                name: "PC".to_string(),
+                start: vm.registers_storage[Registers::R_PC as usize],
+                length: REGION_WORDS,
+            },
+            Region {
+                name: "Stack (R6)".to_string(),
+                start: vm.registers_storage[Registers::R_R6 as usize],
+                length: REGION_WORDS,
+            },
+            Region {
+                name: "Keyboard (KBSR/KBDR)".to_string(),
+                start: 0xFE00,
+                length: 3,
+            },
+        ];
+        regions.extend(self.regions.iter().cloned());
+        regions
+    }
+}
+
+/// Uses an explicit launch argument, falling back to the program's sibling
+/// file with the given extension when it exists.
+fn optional_path(argument: &Value, program: &Path, extension: &str) -> Option<PathBuf> {
+    match argument.as_str() {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(program.with_extension(extension)).filter(|path| path.exists()),
+    }
+}
+
+fn same_file(source: Option<&Path>, path: &str) -> bool {
+    let Some(source) = source else {
+        return false;
+    };
+    let path = Path::new(path);
+    match (fs::canonicalize(source), fs::canonicalize(path)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => source.file_name() == path.file_name(),
+    }
+}
+
+fn parse_address(value: &Value, symbols: &SymbolTable) -> Option<u16> {
+    if let Some(number) = value.as_u64() {
+        return u16::try_from(number).ok();
+    }
+    let text = value.as_str()?.trim();
+    symbols
+        .address_of(text)
+        .or_else(|| parse_number(text).map(|n| n as u16))
+}
+
+fn format_word(value: u16) -> String {
+    format!("x{:04X} ({})", value, value as i16)
+}
+
+fn condition_name(cond: u16) -> &'static str {
+    match cond {
+        1 => "P",
+        2 => "Z",
+        4 => "N",
+        _ => "?",
+    }
+}
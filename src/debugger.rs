@@ -0,0 +1,231 @@
+use crate::run::{Fault, Registers, Step, VM};
+
+use std::collections::BTreeSet;
+
+/// Why a debugger run came to a stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A requested step (into, over or out) completed.
+    Step,
+    Breakpoint(u16),
+    Halted,
+    Fault(Fault),
+    /// Reverse execution ran out of recorded history.
+    HistoryStart,
+}
+
+/// What the debugger is currently running towards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Continue,
+    StepInto,
+    /// Stop once the call depth is back to (or below) this level.
+    StepOver(i64),
+    /// Stop once the call depth drops below this level.
+    StepOut(i64),
+}
+
+/// Breakpoints and stepping on top of a `VM`. Execution is driven in slices
+/// by `run`, so a front end can interleave its own work (such as handling a
+/// pause request) with a long-running continue.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub vm: VM,
+    breakpoints: BTreeSet<u16>,
+    target: Option<Target>,
+    /// True until the first instruction of a resumed run has executed, so
+    /// resuming from a breakpoint does not immediately stop on it again.
+    resuming: bool,
+    /// Where the last run stopped; `None` before the first run.
+    stopped_at: Option<u16>,
+    depth: i64,
+    halted: bool,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            target: None,
+            resuming: false,
+            stopped_at: None,
+            depth: 0,
+            halted: false,
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.vm.registers_storage[Registers::R_PC as usize]
+    }
+
+    pub fn set_breakpoints(&mut self, addresses: impl IntoIterator<Item = u16>) {
+        self.breakpoints = addresses.into_iter().collect();
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Number of subroutine calls (JSR/JSRR) not yet matched by a RET.
+    pub fn call_depth(&self) -> i64 {
+        self.depth
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.target.is_some()
+    }
+
+    pub fn continue_(&mut self) {
+        self.start(Target::Continue);
+    }
+
+    pub fn step_into(&mut self) {
+        self.start(Target::StepInto);
+    }
+
+    /// Steps one instruction, treating a JSR/JSRR and everything it calls as one step.
+    pub fn step_over(&mut self) {
+        self.start(Target::StepOver(self.depth));
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self) {
+        self.start(Target::StepOut(self.depth));
+    }
+
+    /// Abandons the current run, e.g. in response to a pause request.
+    pub fn interrupt(&mut self) {
+        self.target = None;
+        self.stopped_at = Some(self.pc());
+    }
+
+    /// Steps always execute at least one instruction; a continue only skips
+    /// the breakpoint it is currently stopped on, so a breakpoint on the
+    /// entry point is still hit.
+    fn start(&mut self, target: Target) {
+        self.target = Some(target);
+        self.resuming = target != Target::Continue || self.stopped_at == Some(self.pc());
+    }
+
+    /// Executes at most `budget` instructions towards the current target.
+    /// Returns `None` if the budget ran out before anything stopped the run.
+    pub fn run(&mut self, budget: u64) -> Option<StopReason> {
+        let target = self.target?;
+        if self.halted {
+            self.target = None;
+            return Some(StopReason::Halted);
+        }
+
+        for _ in 0..budget {
+            let pc = self.pc();
+            if !self.resuming && self.breakpoints.contains(&pc) {
+                return self.stop(StopReason::Breakpoint(pc));
+            }
+            self.resuming = false;
+
+            let instr = self.vm.fetch(pc).unwrap_or(0);
+            match self.vm.step() {
+                Ok(Step::Continue) => {}
+                Ok(Step::Halted) => {
+                    self.halted = true;
+                    return self.stop(StopReason::Halted);
+                }
+                Err(fault) => return self.stop(StopReason::Fault(fault)),
+            }
+            self.track_depth(instr);
+
+            let done = match target {
+                Target::Continue => false,
+                Target::StepInto => true,
+                Target::StepOver(depth) => self.depth <= depth,
+                Target::StepOut(depth) => self.depth < depth,
+            };
+            if done {
+                return self.stop(StopReason::Step);
+            }
+        }
+        None
+    }
+
+    /// Undoes one instruction. Needs `vm.history` to be enabled.
+    pub fn step_back(&mut self) -> StopReason {
+        self.target = None;
+        let reason = if self.undo() {
+            StopReason::Step
+        } else {
+            StopReason::HistoryStart
+        };
+        self.stopped_at = Some(self.pc());
+        reason
+    }
+
+    /// Runs backwards until a breakpoint is reached or the history runs out.
+    pub fn reverse_continue(&mut self) -> StopReason {
+        self.target = None;
+        let reason = loop {
+            if !self.undo() {
+                break StopReason::HistoryStart;
+            }
+            let pc = self.pc();
+            if self.breakpoints.contains(&pc) {
+                break StopReason::Breakpoint(pc);
+            }
+        };
+        self.stopped_at = Some(self.pc());
+        reason
+    }
+
+    fn undo(&mut self) -> bool {
+        let Some(instr) = self
+            .vm
+            .history
+            .as_ref()
+            .and_then(|history| history.records().next_back())
+            .map(|record| record.instr)
+        else {
+            return false;
+        };
+        self.vm.step_back();
+        self.halted = false;
+        match instr >> 12 {
+            0x4 => self.depth -= 1,
+            0xC if (instr >> 6) & 0x7 == 7 => self.depth += 1,
+            _ => {}
+        }
+        true
+    }
+
+    /// Runs the current target to completion, ignoring any slicing.
+    pub fn run_to_stop(&mut self) -> Option<StopReason> {
+        self.run(u64::MAX)
+    }
+
+    fn stop(&mut self, reason: StopReason) -> Option<StopReason> {
+        self.target = None;
+        self.stopped_at = Some(self.pc());
+        Some(reason)
+    }
+
+    fn track_depth(&mut self, instr: u16) {
+        match instr >> 12 {
+            // JSR / JSRR
+            0x4 => self.depth += 1,
+            // RET (JMP R7)
+            0xC if (instr >> 6) & 0x7 == 7 => self.depth -= 1,
+            _ => {}
+        }
+    }
+}
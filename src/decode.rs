@@ -0,0 +1,232 @@
+use crate::run::sign_extend;
+
+use std::fmt;
+
+/// An instruction with its operand fields extracted and sign-extended, and
+/// its PC-relative address resolved against the address it was fetched from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedInstr {
+    Add {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AddImm {
+        dr: u8,
+        sr1: u8,
+        imm: u16,
+    },
+    And {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AndImm {
+        dr: u8,
+        sr1: u8,
+        imm: u16,
+    },
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    /// BR; `nzp` is the condition mask in bits 2..0.
+    Br {
+        nzp: u16,
+        target: u16,
+    },
+    /// JMP, and RET when `base` is 7.
+    Jmp {
+        base: u8,
+    },
+    Jsr {
+        target: u16,
+    },
+    Jsrr {
+        base: u8,
+    },
+    Ld {
+        dr: u8,
+        address: u16,
+    },
+    Ldi {
+        dr: u8,
+        address: u16,
+    },
+    Ldr {
+        dr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Lea {
+        dr: u8,
+        address: u16,
+    },
+    St {
+        sr: u8,
+        address: u16,
+    },
+    Sti {
+        sr: u8,
+        address: u16,
+    },
+    Str {
+        sr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Trap {
+        vector: u16,
+    },
+    Rti,
+    /// The reserved opcode.
+    Illegal {
+        instr: u16,
+    },
+}
+
+/// Decodes `instr`, fetched from `address`.
+pub fn decode(address: u16, instr: u16) -> DecodedInstr {
+    let dr = ((instr >> 9) & 0x7) as u8;
+    let sr1 = ((instr >> 6) & 0x7) as u8;
+    let next = address.wrapping_add(1);
+    let pc_offset9 = next.wrapping_add(sign_extend(instr & 0x1FF, 9));
+    let immediate = instr & 0x20 != 0;
+
+    match instr >> 12 {
+        0x0 => DecodedInstr::Br {
+            nzp: (instr >> 9) & 0x7,
+            target: pc_offset9,
+        },
+        0x1 if immediate => DecodedInstr::AddImm {
+            dr,
+            sr1,
+            imm: sign_extend(instr & 0x1F, 5),
+        },
+        0x1 => DecodedInstr::Add {
+            dr,
+            sr1,
+            sr2: (instr & 0x7) as u8,
+        },
+        0x2 => DecodedInstr::Ld {
+            dr,
+            address: pc_offset9,
+        },
+        0x3 => DecodedInstr::St {
+            sr: dr,
+            address: pc_offset9,
+        },
+        0x4 if instr & 0x800 != 0 => DecodedInstr::Jsr {
+            target: next.wrapping_add(sign_extend(instr & 0x7FF, 11)),
+        },
+        0x4 => DecodedInstr::Jsrr { base: sr1 },
+        0x5 if immediate => DecodedInstr::AndImm {
+            dr,
+            sr1,
+            imm: sign_extend(instr & 0x1F, 5),
+        },
+        0x5 => DecodedInstr::And {
+            dr,
+            sr1,
+            sr2: (instr & 0x7) as u8,
+        },
+        0x6 => DecodedInstr::Ldr {
+            dr,
+            base: sr1,
+            offset: sign_extend(instr & 0x3F, 6),
+        },
+        0x7 => DecodedInstr::Str {
+            sr: dr,
+            base: sr1,
+            offset: sign_extend(instr & 0x3F, 6),
+        },
+        0x8 => DecodedInstr::Rti,
+        0x9 => DecodedInstr::Not { dr, sr: sr1 },
+        0xA => DecodedInstr::Ldi {
+            dr,
+            address: pc_offset9,
+        },
+        0xB => DecodedInstr::Sti {
+            sr: dr,
+            address: pc_offset9,
+        },
+        0xC => DecodedInstr::Jmp { base: sr1 },
+        0xE => DecodedInstr::Lea {
+            dr,
+            address: pc_offset9,
+        },
+        0xF => DecodedInstr::Trap {
+            vector: instr & 0xFF,
+        },
+        _ => DecodedInstr::Illegal { instr },
+    }
+}
+
+/// Decoded instructions cached per address. Each entry remembers the word
+/// it was decoded from, so a stale entry is never used even if memory was
+/// changed without going through `VM::mem_write`.
+#[derive(Clone)]
+pub struct DecodeCache {
+    entries: Vec<Option<(u16, DecodedInstr)>>,
+    enabled: bool,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DecodeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodeCache")
+            .field("cached", &self.entries.iter().flatten().count())
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![None; 1 << 16],
+            enabled: true,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns caching on or off. While off every fetch is decoded afresh.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    /// The decoded form of `word`, fetched from `address`.
+    #[inline]
+    pub fn get(&mut self, address: u16, word: u16) -> DecodedInstr {
+        if !self.enabled {
+            return decode(address, word);
+        }
+        let entry = &mut self.entries[address as usize];
+        match *entry {
+            Some((cached, decoded)) if cached == word => decoded,
+            _ => {
+                let decoded = decode(address, word);
+                *entry = Some((word, decoded));
+                decoded
+            }
+        }
+    }
+
+    #[inline]
+    pub fn invalidate(&mut self, address: u16) {
+        self.entries[address as usize] = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::extension::{Extension, OP_RES};
+use crate::lc3b;
+use crate::run::{sign_extend, Isa};
+use crate::symbols::SymbolTable;
+
+/// Mnemonic for each 4-bit opcode, indexed by `instr >> 12`.
+pub const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP", "RES",
+    "LEA", "TRAP",
+];
+
+/// Mnemonic for the opcode of `instr`. JSRR and RET are reported as JSR and
+/// JMP, since they share an opcode with them.
+pub fn opcode_name(instr: u16) -> &'static str {
+    OPCODE_NAMES[(instr >> 12) as usize]
+}
+
+/// Disassembles the instruction `instr` stored at `address` into `lc3as`
+/// syntax. PC-relative targets are shown as labels when `symbols` has one
+/// for the exact address, and as `x3000`-style addresses otherwise. Words
+/// that do not decode to an instruction are shown as `.FILL`.
+pub fn disassemble(address: u16, instr: u16, symbols: &SymbolTable) -> String {
+    disassemble_with(address, instr, symbols, None)
+}
+
+/// Disassembles the instruction at `address` of `memory`, which holds the
+/// whole address space, as `isa`. LC-3b addresses are byte addresses.
+pub fn disassemble_in(isa: Isa, address: u16, memory: &[u16], symbols: &SymbolTable) -> String {
+    match isa {
+        Isa::Lc3 => disassemble(address, memory[address as usize], symbols),
+        Isa::Lc3b => {
+            let instr = memory[lc3b::word_address(address) as usize];
+            lc3b::disassemble(address, instr, symbols)
+        }
+    }
+}
+
+/// Like `disassemble`, but the reserved opcode is shown the way
+/// `extension` disassembles it.
+pub fn disassemble_with(
+    address: u16,
+    instr: u16,
+    symbols: &SymbolTable,
+    extension: Option<&dyn Extension>,
+) -> String {
+    if instr >> 12 == OP_RES {
+        if let Some(text) = extension.and_then(|extension| extension.disassemble(instr)) {
+            return text;
+        }
+    }
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let target = |bits: u16| {
+        let target = address
+            .wrapping_add(1)
+            .wrapping_add(sign_extend(instr & ((1 << bits) - 1), bits));
+        match symbols.label_at(target) {
+            Some(label) => label.to_string(),
+            None => format!("x{:04X}", target),
+        }
+    };
+    let operand2 = || {
+        if instr & 0x20 != 0 {
+            format!("#{}", sign_extend(instr & 0x1F, 5) as i16)
+        } else {
+            format!("R{}", instr & 0x7)
+        }
+    };
+    let offset6 = || sign_extend(instr & 0x3F, 6) as i16;
+
+    match instr >> 12 {
+        0x0 => {
+            if dr == 0 {
+                return if instr == 0 {
+                    "NOP".to_string()
+                } else {
+                    format!(".FILL x{:04X}", instr)
+                };
+            }
+            let mut name = String::from("BR");
+            for (bit, flag) in [(4, 'n'), (2, 'z'), (1, 'p')] {
+                if dr & bit != 0 {
+                    name.push(flag);
+                }
+            }
+            format!("{} {}", name, target(9))
+        }
+        0x1 => format!("ADD R{}, R{}, {}", dr, sr1, operand2()),
+        0x5 => format!("AND R{}, R{}, {}", dr, sr1, operand2()),
+        0x2 => format!("LD R{}, {}", dr, target(9)),
+        0x3 => format!("ST R{}, {}", dr, target(9)),
+        0xA => format!("LDI R{}, {}", dr, target(9)),
+        0xB => format!("STI R{}, {}", dr, target(9)),
+        0xE => format!("LEA R{}, {}", dr, target(9)),
+        0x4 if instr & 0x800 != 0 => format!("JSR {}", target(11)),
+        0x4 => format!("JSRR R{}", sr1),
+        0x6 => format!("LDR R{}, R{}, #{}", dr, sr1, offset6()),
+        0x7 => format!("STR R{}, R{}, #{}", dr, sr1, offset6()),
+        0x8 => "RTI".to_string(),
+        0x9 => format!("NOT R{}, R{}", dr, sr1),
+        0xC if sr1 == 7 => "RET".to_string(),
+        0xC => format!("JMP R{}", sr1),
+        0xF => match instr & 0xFF {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        _ => format!(".FILL x{:04X}", instr),
+    }
+}
@@ -0,0 +1,367 @@
+//! Memory dumps: ranges of memory saved as a hex listing with ASCII
+//! columns, a loadable `.obj`, `.hex` or `.bin` image, Intel HEX or
+//! S-records, or raw big-endian words, and comparison of two dumps.
+//!
+//! A hex dump shows eight words per line. A run of lines identical to the
+//! one before is shown as a single `*`, as `hexdump` does:
+//!
+//! ```text
+//! x3000  E002 F022 F025 0048 0069 0000 0000 0000  ...Hi...
+//! x3008  0000 0000 0000 0000 0000 0000 0000 0000  ........
+//! *
+//! x30F8  0000 0000 0000 0000 0000 0000 0000 0000  ........
+//! ```
+
+use crate::init::parse_word;
+use crate::loader::ImageFormat;
+use crate::run::VM;
+use crate::symbols::SymbolTable;
+
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Words shown per line.
+const WORDS_PER_LINE: usize = 8;
+
+/// How a dump is written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Hex words with ASCII columns.
+    #[default]
+    Hex,
+    /// An `.obj` image: the start address, then the words, big-endian.
+    Obj,
+    /// The words alone, big-endian. The start address is not recorded.
+    Raw,
+    /// An lc3tools `.hex` image: the start address, then the words, as four
+    /// hex digits per line.
+    Lc3Hex,
+    /// An lc3tools `.bin` image: as `Lc3Hex`, in sixteen binary digits.
+    Lc3Bin,
+    /// Intel HEX records with word addresses.
+    IntelHex,
+    /// Motorola S-records with word addresses.
+    SRecord,
+}
+
+impl DumpFormat {
+    /// The format for a file name: files with an image extension, such as
+    /// `.obj`, `.hex` or `.srec`, are images, `.raw` files are bare words,
+    /// and anything else is a hex listing.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if let Some(format) = ImageFormat::from_path(path) {
+            return DumpFormat::from_image(format);
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("raw") => DumpFormat::Raw,
+            _ => DumpFormat::Hex,
+        }
+    }
+
+    /// The format of the file at `path` holding `bytes`: as `from_path`,
+    /// with images recognised as `ImageFormat::detect` does.
+    pub fn detect(path: impl AsRef<Path>, bytes: &[u8]) -> Self {
+        match DumpFormat::from_path(&path) {
+            format @ (DumpFormat::Hex | DumpFormat::Raw) => format,
+            _ => DumpFormat::from_image(ImageFormat::detect(path, bytes)),
+        }
+    }
+
+    fn from_image(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Obj => DumpFormat::Obj,
+            ImageFormat::Hex => DumpFormat::Lc3Hex,
+            ImageFormat::Bin => DumpFormat::Lc3Bin,
+            ImageFormat::IntelHex => DumpFormat::IntelHex,
+            ImageFormat::SRecord => DumpFormat::SRecord,
+        }
+    }
+
+    /// The image format this dump format writes, if it is one.
+    fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            DumpFormat::Obj => Some(ImageFormat::Obj),
+            DumpFormat::Lc3Hex => Some(ImageFormat::Hex),
+            DumpFormat::Lc3Bin => Some(ImageFormat::Bin),
+            DumpFormat::IntelHex => Some(ImageFormat::IntelHex),
+            DumpFormat::SRecord => Some(ImageFormat::SRecord),
+            DumpFormat::Hex | DumpFormat::Raw => None,
+        }
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "hex" => Ok(DumpFormat::Hex),
+            "obj" => Ok(DumpFormat::Obj),
+            "raw" => Ok(DumpFormat::Raw),
+            "lc3hex" => Ok(DumpFormat::Lc3Hex),
+            "lc3bin" => Ok(DumpFormat::Lc3Bin),
+            "ihex" => Ok(DumpFormat::IntelHex),
+            "srec" => Ok(DumpFormat::SRecord),
+            _ => Err(format!(
+                "unknown dump format {} (hex, obj, raw, lc3hex, lc3bin, ihex or srec)",
+                name
+            )),
+        }
+    }
+}
+
+/// A copy of consecutive words of memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub start: u16,
+    pub words: Vec<u16>,
+}
+
+/// A word that differs between two dumps. `None` means the address is
+/// outside that dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Difference {
+    pub address: u16,
+    pub before: Option<u16>,
+    pub after: Option<u16>,
+}
+
+/// Parses a range such as `x3000-x30FF` or `MAIN-END`, both ends included.
+pub fn parse_range(text: &str, symbols: &SymbolTable) -> Option<(u16, u16)> {
+    let (from, to) = text.split_once('-')?;
+    let (from, to) = (parse_word(from, symbols)?, parse_word(to, symbols)?);
+    (from <= to).then_some((from, to))
+}
+
+/// The character a word shows as in a dump: its low byte when that is
+/// printable ASCII, and `.` otherwise.
+fn printable(word: u16) -> char {
+    match word {
+        0x20..=0x7E => word as u8 as char,
+        _ => '.',
+    }
+}
+
+/// Formats `memory[from..=to]` eight words per line, collapsing repeated
+/// lines. The last line is always shown, so the end of the range is clear.
+pub fn hex_dump(memory: &[u16], from: u16, to: u16) -> String {
+    format_hex(from, &memory[from as usize..=to as usize])
+}
+
+fn format_hex(from: u16, words: &[u16]) -> String {
+    let mut out = String::new();
+    let rows: Vec<&[u16]> = words.chunks(WORDS_PER_LINE).collect();
+    let mut collapsed = false;
+    for (row, chunk) in rows.iter().enumerate() {
+        let repeated = row > 0 && row + 1 < rows.len() && rows[row - 1] == *chunk;
+        if repeated {
+            if !collapsed {
+                out.push_str("*\n");
+                collapsed = true;
+            }
+            continue;
+        }
+        collapsed = false;
+        let address = from as usize + row * WORDS_PER_LINE;
+        write!(out, "x{:04X} ", address).unwrap();
+        for word in chunk.iter() {
+            write!(out, " {:04X}", word).unwrap();
+        }
+        let padding = (WORDS_PER_LINE - chunk.len()) * 5;
+        let text: String = chunk.iter().map(|&word| printable(word)).collect();
+        writeln!(out, "{:padding$}  {}", "", text, padding = padding).unwrap();
+    }
+    out
+}
+
+/// Reads a hex dump back, filling in collapsed lines.
+fn parse_hex(text: &str) -> Result<Dump, String> {
+    let mut dump: Option<Dump> = None;
+    let mut last_row: Vec<u16> = Vec::new();
+    let mut collapsed = false;
+    for (number, line) in text.lines().enumerate() {
+        let error = |e: &str| format!("line {}: {}", number + 1, e);
+        if line.trim() == "*" {
+            collapsed = true;
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let address = line
+            .get(1..5)
+            .filter(|_| line.starts_with('x'))
+            .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| error("expected an address such as x3000"))?;
+        // Each word is a space and four hex digits; the ASCII column
+        // follows two spaces after the last one
+        let mut row = Vec::new();
+        let mut rest = line.as_bytes()[5..]
+            .strip_prefix(b" ")
+            .ok_or_else(|| error("expected words after the address"))?;
+        while row.len() < WORDS_PER_LINE
+            && rest.len() >= 5
+            && rest[0] == b' '
+            && rest[1..5].iter().all(u8::is_ascii_hexdigit)
+            && rest.get(5).is_none_or(|&c| c == b' ')
+        {
+            let digits = std::str::from_utf8(&rest[1..5]).unwrap();
+            row.push(u16::from_str_radix(digits, 16).unwrap());
+            rest = &rest[5..];
+        }
+        if row.is_empty() {
+            return Err(error("no words"));
+        }
+
+        let dump = dump.get_or_insert_with(|| Dump {
+            start: address,
+            words: Vec::new(),
+        });
+        let expected = dump.start as usize + dump.words.len();
+        if collapsed {
+            while (dump.start as usize + dump.words.len()) < address as usize {
+                dump.words.extend_from_slice(&last_row);
+            }
+            collapsed = false;
+        }
+        if dump.start as usize + dump.words.len() != address as usize {
+            return Err(error(&format!("expected x{:04X} next", expected)));
+        }
+        dump.words.extend_from_slice(&row);
+        last_row = row;
+    }
+    dump.ok_or_else(|| "empty dump".to_string())
+}
+
+impl Dump {
+    /// Copies `memory[from..=to]`.
+    pub fn of(memory: &[u16], from: u16, to: u16) -> Self {
+        Self {
+            start: from,
+            words: memory[from as usize..=to as usize].to_vec(),
+        }
+    }
+
+    pub fn to_bytes(&self, format: DumpFormat) -> Vec<u8> {
+        if let Some(image) = format.image_format() {
+            let words: Vec<u16> = std::iter::once(self.start)
+                .chain(self.words.iter().copied())
+                .collect();
+            return image.encode(&words);
+        }
+        match format {
+            DumpFormat::Hex => format_hex(self.start, &self.words).into_bytes(),
+            _ => self
+                .words
+                .iter()
+                .flat_map(|word| word.to_be_bytes())
+                .collect(),
+        }
+    }
+
+    /// Reads a dump written with `to_bytes`. Raw dumps do not record where
+    /// they start, so they are placed at `raw_start`.
+    pub fn parse(bytes: &[u8], format: DumpFormat, raw_start: u16) -> Result<Self, String> {
+        let words = || -> Result<Vec<u16>, String> {
+            if !bytes.len().is_multiple_of(2) {
+                return Err("odd number of bytes".to_string());
+            }
+            Ok(bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect())
+        };
+        let dump = match format {
+            DumpFormat::Hex => {
+                let text = std::str::from_utf8(bytes).map_err(|_| "not a text file")?;
+                parse_hex(text)?
+            }
+            DumpFormat::Raw => Self {
+                start: raw_start,
+                words: words()?,
+            },
+            _ => {
+                let words = match format.image_format() {
+                    Some(ImageFormat::Obj) | None => words()?,
+                    Some(text) => text.decode(bytes).map_err(|e| e.to_string())?,
+                };
+                let (&start, words) = words.split_first().ok_or("empty image")?;
+                Self {
+                    start,
+                    words: words.to_vec(),
+                }
+            }
+        };
+        if dump.start as usize + dump.words.len() > 1 << 16 {
+            return Err("runs past xFFFF".to_string());
+        }
+        Ok(dump)
+    }
+
+    /// Reads the dump at `path` in the format `DumpFormat::detect` finds.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        Self::parse(&bytes, DumpFormat::detect(path, &bytes), 0)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, format: DumpFormat) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes(format))
+    }
+
+    /// The word at `address`, if the dump covers it.
+    pub fn get(&self, address: u16) -> Option<u16> {
+        let offset = usize::from(address.checked_sub(self.start)?);
+        self.words.get(offset).copied()
+    }
+
+    /// Every address where `other` differs from this dump, in order.
+    pub fn diff(&self, other: &Dump) -> Vec<Difference> {
+        let end = |dump: &Dump| dump.start as usize + dump.words.len();
+        let from = self.start.min(other.start) as usize;
+        let to = end(self).max(end(other));
+        (from..to)
+            .map(|address| address as u16)
+            .filter_map(|address| {
+                let (before, after) = (self.get(address), other.get(address));
+                (before != after).then_some(Difference {
+                    address,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+}
+
+/// One line per difference: `x3004  0003 -> 0005  RESULT`, with `----` for
+/// an address outside one of the dumps.
+pub fn format_diff(differences: &[Difference], symbols: &SymbolTable) -> String {
+    let word = |word: Option<u16>| word.map_or("----".to_string(), |word| format!("{:04X}", word));
+    let mut out = String::new();
+    for difference in differences {
+        let address = difference.address;
+        write!(
+            out,
+            "x{:04X}  {} -> {}",
+            address,
+            word(difference.before),
+            word(difference.after)
+        )
+        .unwrap();
+        if symbols.nearest(address).is_some() {
+            write!(out, "  {}", symbols.describe(address)).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+impl VM {
+    /// Copies `memory[from..=to]` without going through the device registers.
+    pub fn dump(&self, from: u16, to: u16) -> Dump {
+        Dump::of(&self.memory.contents(), from, to)
+    }
+}
@@ -0,0 +1,68 @@
+//! Experimental instructions in the reserved opcode.
+//!
+//! The LC-3 leaves opcode xD (`OP_RES`) unused, and executing it faults with
+//! `Fault::IllegalOpcode`. An `Extension` installed in `VM::extension`
+//! gives it meaning instead: MUL, shifts, XOR, PUSH/POP or whatever an
+//! experiment needs. The handler sees the whole instruction word and the
+//! VM, so it can read and change registers, memory and the console.
+//!
+//! The same extension teaches the assembler (`asm::assemble_with`) its
+//! mnemonics and the disassembler (`disasm::disassemble_with`) how to show
+//! them. LC-3b programs use opcode xD for SHF, so extensions only apply to
+//! the LC-3.
+
+use crate::run::{Fault, Step, VM};
+
+use std::fmt;
+
+/// The reserved opcode extensions take over.
+pub const OP_RES: u16 = 0xD;
+
+/// Semantics for instructions with opcode `OP_RES`.
+pub trait Extension: fmt::Debug + Send {
+    /// Executes `instr`, fetched from `pc`. PC already points past it. Use
+    /// `VM::memory_read` and `VM::mem_write` for memory, so the keyboard
+    /// registers, history and caches see the accesses, and `VM::update_flags`
+    /// to set the condition codes. Returning a fault restores PC and R7 as
+    /// for any other faulting instruction.
+    fn execute(&mut self, vm: &mut VM, pc: u16, instr: u16) -> Result<Step, Fault>;
+
+    /// Assembly text for `instr`, or `None` to show it as `.FILL`.
+    fn disassemble(&self, _instr: u16) -> Option<String> {
+        None
+    }
+
+    /// Whether the assembler should treat `mnemonic` as one of this
+    /// extension's instructions.
+    fn is_mnemonic(&self, _mnemonic: &str) -> bool {
+        false
+    }
+
+    /// Encodes the instruction `mnemonic` with `operands`. Only called for
+    /// mnemonics `is_mnemonic` accepts; `asm::register` and `asm::immediate`
+    /// parse the usual operand forms.
+    fn assemble(&self, mnemonic: &str, _operands: &[&str]) -> Result<u16, String> {
+        Err(format!("unknown instruction {}", mnemonic))
+    }
+
+    fn clone_box(&self) -> Box<dyn Extension>;
+}
+
+impl Clone for Box<dyn Extension> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl VM {
+    /// Runs the reserved-opcode instruction `instr` with the installed
+    /// extension, which is taken out of the VM while it runs.
+    pub(crate) fn execute_extension(&mut self, pc: u16, instr: u16) -> Result<Step, Fault> {
+        let Some(mut extension) = self.extension.take() else {
+            return Err(Fault::IllegalOpcode { pc, instr });
+        };
+        let result = extension.execute(self, pc, instr);
+        self.extension.get_or_insert(extension);
+        result
+    }
+}
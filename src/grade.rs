@@ -0,0 +1,438 @@
+//! Headless autograder: runs an image against a spec of test cases.
+//!
+//! A spec is a JSON or TOML document with a list of `cases`. Each case gives
+//! the keyboard input and what is expected once the program halts:
+//!
+//! ```toml
+//! max_instructions = 100000   # default for every case
+//!
+//! [[cases]]
+//! name = "adds two digits"
+//! input = "34"
+//! output = "7\n"
+//! registers = { R0 = 55 }
+//! memory = { RESULT = 7, x4001 = "x0000" }
+//! max_instructions = 5000     # overrides the default
+//! ```
+//!
+//! Numbers may be written as integers or as strings in LC-3 notation (`x30`,
+//! `#-1`). Memory addresses may also be labels from the `.sym` file next to
+//! the image, which win over numbers of the same spelling. Every case runs
+//! in a fresh `VM`, with loop detection on.
+
+use crate::budget::Budget;
+use crate::cli::{EXIT_FAULT, EXIT_HALTED, EXIT_USAGE};
+use crate::console::Console;
+use crate::init::register_index;
+use crate::loops::LoopDetector;
+use crate::run::{Fault, Registers, VM};
+use crate::symbols::{parse_number, SymbolTable};
+
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Instruction limit for cases that do not set one, so a program that never
+/// halts fails its case instead of hanging the grader.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+/// One test case of a spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    /// Keyboard input available to the program.
+    pub input: Vec<u8>,
+    /// Expected console output, if checked.
+    pub output: Option<Vec<u8>>,
+    /// Expected register values, by `Registers` index.
+    pub registers: Vec<(usize, u16)>,
+    /// Expected memory contents.
+    pub memory: Vec<(u16, u16)>,
+    pub budget: Budget,
+}
+
+/// Parses a spec. `path` picks the format: `.toml` files are TOML and
+/// anything else is JSON. Labels in memory addresses resolve against `symbols`.
+pub fn load_spec(path: &Path, symbols: &SymbolTable) -> Result<Vec<TestCase>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let spec: Value = if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        toml::from_str(&text).map_err(|e| e.to_string())?
+    } else {
+        serde_json::from_str(&text).map_err(|e| e.to_string())?
+    };
+    parse_spec(&spec, symbols)
+}
+
+/// Builds test cases from a spec already parsed into a `Value`.
+pub fn parse_spec(spec: &Value, symbols: &SymbolTable) -> Result<Vec<TestCase>, String> {
+    let defaults = parse_budget(spec, Budget::default())?;
+    let cases = spec
+        .get("cases")
+        .and_then(Value::as_array)
+        .ok_or("the spec has no `cases` list")?;
+    cases
+        .iter()
+        .enumerate()
+        .map(|(index, case)| {
+            parse_case(case, defaults, symbols).map_err(|e| format!("case {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+fn parse_case(case: &Value, defaults: Budget, symbols: &SymbolTable) -> Result<TestCase, String> {
+    let text = |key: &str| -> Result<Option<Vec<u8>>, String> {
+        match case.get(key) {
+            None => Ok(None),
+            Some(Value::String(text)) => Ok(Some(text.as_bytes().to_vec())),
+            Some(_) => Err(format!("`{}` must be a string", key)),
+        }
+    };
+    let mut parsed = TestCase {
+        name: match case.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            _ => return Err("missing `name`".to_string()),
+        },
+        input: text("input")?.unwrap_or_default(),
+        output: text("output")?,
+        budget: parse_budget(case, defaults)?,
+        ..TestCase::default()
+    };
+    for (name, value) in table(case, "registers")? {
+        let register = register_index(name).ok_or(format!("unknown register `{}`", name))?;
+        parsed.registers.push((register, word(value)?));
+    }
+    for (location, value) in table(case, "memory")? {
+        // Labels first, as `B10` or `X1` also read as numbers
+        let address = symbols
+            .address_of(location)
+            .or_else(|| parse_number(location).map(|address| address as u16))
+            .ok_or(format!("unknown address or label `{}`", location))?;
+        parsed.memory.push((address, word(value)?));
+    }
+    parsed.registers.sort_unstable();
+    parsed.memory.sort_unstable();
+    Ok(parsed)
+}
+
+/// Limits from `max_instructions` and `timeout` (seconds), falling back to
+/// `defaults`.
+fn parse_budget(value: &Value, defaults: Budget) -> Result<Budget, String> {
+    let mut budget = defaults;
+    if let Some(max) = value.get("max_instructions") {
+        let max = max.as_u64().ok_or("`max_instructions` must be a count")?;
+        budget.max_instructions = Some(max);
+    }
+    if let Some(seconds) = value.get("timeout") {
+        let seconds = seconds
+            .as_f64()
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .ok_or("`timeout` must be a number of seconds")?;
+        budget.max_time = Some(Duration::from_secs_f64(seconds));
+    }
+    Ok(budget)
+}
+
+fn table<'a>(case: &'a Value, key: &str) -> Result<Vec<(&'a str, &'a Value)>, String> {
+    match case.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Object(entries)) => Ok(entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect()),
+        Some(_) => Err(format!("`{}` must be a table", key)),
+    }
+}
+
+/// A 16-bit value given as an integer or as an LC-3 number string.
+fn word(value: &Value) -> Result<u16, String> {
+    let number = match value {
+        Value::Number(number) => number.as_i64(),
+        Value::String(text) => parse_number(text).map(i64::from),
+        _ => None,
+    };
+    number
+        .filter(|number| (-0x8000..=0xFFFF).contains(number))
+        .map(|number| number as u16)
+        .ok_or(format!("`{}` is not a 16-bit value", value))
+}
+
+fn register_name(index: usize) -> String {
+    match index {
+        index if index == Registers::R_PC as usize => "PC".to_string(),
+        index if index == Registers::R_COND as usize => "COND".to_string(),
+        index => format!("R{}", index),
+    }
+}
+
+/// How a case went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    /// The program halted, but some expectations were not met.
+    Failed(Vec<String>),
+    /// The program faulted, looped forever or ran out of budget.
+    Error(Fault),
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: String,
+    pub verdict: Verdict,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+/// Runs every case against the image at `image`, each in a fresh VM.
+pub fn grade(image: &str, cases: &[TestCase]) -> Result<Vec<CaseResult>, String> {
+    cases
+        .iter()
+        .map(|case| {
+            let mut vm = VM::new();
+            let origin = vm
+                .read_image(image)
+                .map_err(|e| format!("Failed to load image: {} ({})", image, e))?;
+            vm.registers_storage[Registers::R_PC as usize] = origin;
+            Ok(run_case(vm, case))
+        })
+        .collect()
+}
+
+/// Runs one case on `vm`, which has the program loaded and PC set.
+pub fn run_case(mut vm: VM, case: &TestCase) -> CaseResult {
+    vm.console = Console::buffered(&case.input);
+    vm.quiet = true;
+    vm.budget = Some(Budget {
+        max_instructions: case
+            .budget
+            .max_instructions
+            .or(Some(DEFAULT_MAX_INSTRUCTIONS)),
+        ..case.budget
+    });
+    vm.loop_detector = Some(LoopDetector::new());
+
+    let started = Instant::now();
+    let result = vm.execute();
+    let time = started.elapsed();
+    let verdict = match result {
+        Err(fault) => Verdict::Error(fault),
+        Ok(()) => {
+            let failures = check(&mut vm, case);
+            if failures.is_empty() {
+                Verdict::Passed
+            } else {
+                Verdict::Failed(failures)
+            }
+        }
+    };
+    CaseResult {
+        name: case.name.clone(),
+        verdict,
+        instructions: vm.instruction_count,
+        time,
+    }
+}
+
+/// Compares the halted machine against the case's expectations.
+fn check(vm: &mut VM, case: &TestCase) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(expected) = &case.output {
+        let output = vm.console.take_output();
+        if &output != expected {
+            failures.push(format!(
+                "output: expected {:?}, got {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&output)
+            ));
+        }
+    }
+    for &(register, expected) in &case.registers {
+        let actual = vm.registers_storage[register];
+        if actual != expected {
+            failures.push(format!(
+                "{}: expected x{:04X}, got x{:04X}",
+                register_name(register),
+                expected,
+                actual
+            ));
+        }
+    }
+    for &(address, expected) in &case.memory {
+        let actual = vm.memory.read(address as usize).unwrap_or(0);
+        if actual != expected {
+            failures.push(format!(
+                "x{:04X}: expected x{:04X}, got x{:04X}",
+                address, expected, actual
+            ));
+        }
+    }
+    failures
+}
+
+/// Formats a pass/fail line per case, with the reasons for each failure.
+pub fn report(results: &[CaseResult]) -> String {
+    let mut out = String::new();
+    for result in results {
+        let status = match result.verdict {
+            Verdict::Passed => "PASS ",
+            Verdict::Failed(_) => "FAIL ",
+            Verdict::Error(_) => "ERROR",
+        };
+        writeln!(
+            out,
+            "{}  {} ({} instructions)",
+            status, result.name, result.instructions
+        )
+        .unwrap();
+        for message in messages(&result.verdict) {
+            writeln!(out, "       {}", message).unwrap();
+        }
+    }
+    let passed = results
+        .iter()
+        .filter(|result| result.verdict == Verdict::Passed)
+        .count();
+    writeln!(out, "{} of {} cases passed", passed, results.len()).unwrap();
+    out
+}
+
+fn messages(verdict: &Verdict) -> Vec<String> {
+    match verdict {
+        Verdict::Passed => Vec::new(),
+        Verdict::Failed(failures) => failures.clone(),
+        Verdict::Error(fault) => vec![fault.to_string()],
+    }
+}
+
+/// Formats the results as a JUnit XML test suite named `suite`.
+pub fn junit(suite: &str, results: &[CaseResult]) -> String {
+    let count = |error: bool| {
+        results
+            .iter()
+            .filter(|result| match result.verdict {
+                Verdict::Failed(_) => !error,
+                Verdict::Error(_) => error,
+                Verdict::Passed => false,
+            })
+            .count()
+    };
+    let total: Duration = results.iter().map(|result| result.time).sum();
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<testsuite name="{}" tests="{}" failures="{}" errors="{}" time="{:.6}">"#,
+        escape(suite),
+        results.len(),
+        count(false),
+        count(true),
+        total.as_secs_f64()
+    )
+    .unwrap();
+    for result in results {
+        write!(
+            out,
+            r#"  <testcase name="{}" classname="{}" time="{:.6}""#,
+            escape(&result.name),
+            escape(suite),
+            result.time.as_secs_f64()
+        )
+        .unwrap();
+        let element = match result.verdict {
+            Verdict::Passed => {
+                writeln!(out, "/>").unwrap();
+                continue;
+            }
+            Verdict::Failed(_) => "failure",
+            Verdict::Error(_) => "error",
+        };
+        let messages = messages(&result.verdict);
+        writeln!(out, ">").unwrap();
+        writeln!(
+            out,
+            r#"    <{} message="{}">{}</{}>"#,
+            element,
+            escape(&messages[0]),
+            escape(&messages.join("\n")),
+            element
+        )
+        .unwrap();
+        writeln!(out, "  </testcase>").unwrap();
+    }
+    writeln!(out, "</testsuite>").unwrap();
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\t' => escaped.push(c),
+            c if c.is_control() => write!(escaped, "&#{};", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub const USAGE: &str = "Usage: lc3 grade [--junit file] image-file spec-file";
+
+/// The `grade` subcommand. Prints the report and returns the exit code:
+/// 0 when every case passed, 1 when any failed, and `EXIT_USAGE` when the
+/// image or spec could not be read.
+pub fn main(args: Vec<String>) -> Result<i32, String> {
+    let mut junit_path = None;
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => {
+                junit_path = Some(args.next().ok_or("--junit needs a value")?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let [image, spec] = &paths[..] else {
+        return Err("an image file and a spec file are needed".to_string());
+    };
+
+    let symbols = SymbolTable::load(Path::new(image).with_extension("sym")).unwrap_or_default();
+    let results = load_spec(Path::new(spec), &symbols)
+        .map_err(|e| format!("Failed to load spec: {} ({})", spec, e))
+        .and_then(|cases| grade(image, &cases));
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+
+    print!("{}", report(&results));
+    if let Some(path) = junit_path {
+        let suite = Path::new(image)
+            .file_stem()
+            .map_or(image.clone(), |stem| stem.to_string_lossy().into_owned());
+        match std::fs::write(&path, junit(&suite, &results)) {
+            Ok(()) => eprintln!("JUnit report written to {}", path),
+            Err(e) => eprintln!("Failed to write JUnit report: {} ({})", path, e),
+        }
+    }
+    if results
+        .iter()
+        .all(|result| result.verdict == Verdict::Passed)
+    {
+        Ok(EXIT_HALTED)
+    } else {
+        Ok(EXIT_FAULT)
+    }
+}
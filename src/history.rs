@@ -0,0 +1,148 @@
+use crate::callstack::CallFrame;
+use crate::run::Registers;
+
+use std::collections::VecDeque;
+
+/// A memory word changed by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// Everything needed to undo one executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepRecord {
+    /// Instruction count before this instruction ran.
+    pub count: u64,
+    pub pc: u16,
+    pub instr: u16,
+    /// Register file before the instruction ran.
+    pub registers: [u16; Registers::R_COUNT as usize],
+    /// Memory writes in the order they happened.
+    pub writes: Vec<MemoryWrite>,
+    /// Keyboard input consumed by the instruction.
+    pub input: Vec<u8>,
+    /// True when the instruction pushed a frame onto the shadow call stack.
+    pub called: bool,
+    /// Frames the instruction popped off the shadow call stack.
+    pub returned: Vec<CallFrame>,
+}
+
+/// Bounded undo log of executed instructions, newest last. Once `capacity`
+/// records are held the oldest is dropped for each new one.
+#[derive(Debug, Clone)]
+pub struct History {
+    capacity: usize,
+    records: VecDeque<StepRecord>,
+    current: Option<StepRecord>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.current = None;
+    }
+
+    pub fn records(&self) -> impl DoubleEndedIterator<Item = &StepRecord> {
+        self.records.iter()
+    }
+
+    pub(crate) fn begin(
+        &mut self,
+        count: u64,
+        pc: u16,
+        instr: u16,
+        registers: [u16; Registers::R_COUNT as usize],
+    ) {
+        self.current = Some(StepRecord {
+            count,
+            pc,
+            instr,
+            registers,
+            writes: Vec::new(),
+            input: Vec::new(),
+            called: false,
+            returned: Vec::new(),
+        });
+    }
+
+    pub(crate) fn record_write(&mut self, address: u16, old: u16, new: u16) {
+        if let Some(record) = self.current.as_mut() {
+            record.writes.push(MemoryWrite { address, old, new });
+        }
+    }
+
+    pub(crate) fn record_input(&mut self, byte: u8) {
+        if let Some(record) = self.current.as_mut() {
+            record.input.push(byte);
+        }
+    }
+
+    pub(crate) fn record_call(&mut self) {
+        if let Some(record) = self.current.as_mut() {
+            record.called = true;
+        }
+    }
+
+    pub(crate) fn record_return(&mut self, frames: Vec<CallFrame>) {
+        if let Some(record) = self.current.as_mut() {
+            record.returned = frames;
+        }
+    }
+
+    pub(crate) fn commit(&mut self) {
+        let Some(record) = self.current.take() else {
+            return;
+        };
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Drops the record for an instruction that faulted instead of completing.
+    pub(crate) fn abort(&mut self) {
+        self.current = None;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<StepRecord> {
+        self.records.pop_back()
+    }
+
+    /// The most recent recorded write to `address`, with the instruction that made it.
+    pub fn last_write(&self, address: u16) -> Option<(&StepRecord, MemoryWrite)> {
+        self.records.iter().rev().find_map(|record| {
+            record
+                .writes
+                .iter()
+                .rev()
+                .find(|write| write.address == address)
+                .map(|&write| (record, write))
+        })
+    }
+}
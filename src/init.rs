@@ -0,0 +1,154 @@
+//! Machine state to set up before a program runs, so routines can be
+//! exercised without a wrapper program.
+//!
+//! An init file has one setting per line, a register or an address followed
+//! by values. `;` starts a comment.
+//!
+//! ```text
+//! ; call MULTIPLY with R0 = 6, R1 = 7, returning to the HALT at x3100
+//! PC  MULTIPLY
+//! R0  #6
+//! R1  7
+//! R7  x3100
+//! x3100  xF025
+//! TABLE  1 2 3 x10      ; consecutive words from TABLE
+//! x4000  "hi\n"         ; characters and a terminating zero, as .STRINGZ
+//! ```
+//!
+//! Addresses and register values may be numbers in LC-3 notation or labels
+//! from the program's symbol table.
+
+use crate::memory::MemoryError;
+use crate::run::{Registers, VM};
+use crate::symbols::{parse_number, parse_string, tokenize, SymbolTable};
+
+use std::path::Path;
+
+/// Registers and memory to set before running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitState {
+    /// Register values by `Registers` index, PC and COND included.
+    pub registers: Vec<(usize, u16)>,
+    /// Words to store, each run starting at its address.
+    pub memory: Vec<(u16, Vec<u16>)>,
+}
+
+/// The `Registers` index for a register name: `R0`-`R7`, `PC` or `COND`.
+pub(crate) fn register_index(name: &str) -> Option<usize> {
+    match name.to_ascii_uppercase().as_str() {
+        "PC" => Some(Registers::R_PC as usize),
+        "COND" => Some(Registers::R_COND as usize),
+        name => {
+            let index: usize = name.strip_prefix('R')?.parse().ok()?;
+            (index < 8).then_some(index)
+        }
+    }
+}
+
+/// A 16-bit word written as a number or a label.
+pub(crate) fn parse_word(token: &str, symbols: &SymbolTable) -> Option<u16> {
+    match parse_number(token) {
+        Some(number) if (-0x8000..=0xFFFF).contains(&number) => Some(number as u16),
+        Some(_) => None,
+        None => symbols.address_of(token),
+    }
+}
+
+impl InitState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>, symbols: &SymbolTable) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text, symbols)
+    }
+
+    /// Parses init file text. Errors name the offending line.
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Self, String> {
+        let mut state = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let tokens = tokenize(line);
+            let Some((target, values)) = tokens.split_first() else {
+                continue;
+            };
+            state
+                .parse_setting(target, values, symbols)
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+        Ok(state)
+    }
+
+    fn parse_setting(
+        &mut self,
+        target: &str,
+        values: &[String],
+        symbols: &SymbolTable,
+    ) -> Result<(), String> {
+        if let Some(register) = register_index(target) {
+            let [token] = values else {
+                return Err(format!("`{}` takes exactly one value", target));
+            };
+            let word = parse_word(token, symbols).ok_or(format!("bad value `{}`", token))?;
+            self.registers.push((register, word));
+            return Ok(());
+        }
+
+        let address = parse_word(target, symbols)
+            .ok_or(format!("`{}` is not a register, address or label", target))?;
+        let mut words = Vec::new();
+        for token in values {
+            if token.starts_with('"') {
+                let bytes = parse_string(token).ok_or(format!("bad string {}", token))?;
+                words.extend(bytes.iter().map(|&byte| u16::from(byte)));
+                words.push(0);
+            } else {
+                words.push(parse_word(token, symbols).ok_or(format!("bad value `{}`", token))?);
+            }
+        }
+        if words.is_empty() {
+            return Err(format!("no values for `{}`", target));
+        }
+        self.memory.push((address, words));
+        Ok(())
+    }
+
+    /// Sets `register` (a `Registers` index) to `value`, replacing any
+    /// earlier setting.
+    pub fn set_register(&mut self, register: usize, value: u16) {
+        self.registers.retain(|&(index, _)| index != register);
+        self.registers.push((register, value));
+    }
+
+    /// The PC set, if any.
+    pub fn pc(&self) -> Option<u16> {
+        self.registers
+            .iter()
+            .rev()
+            .find(|&&(index, _)| index == Registers::R_PC as usize)
+            .map(|&(_, value)| value)
+    }
+
+    /// Applies the settings to `vm` in order. Memory runs wrap around at the
+    /// end of the address space. Stops at the first word outside a smaller
+    /// memory, with the settings before it applied.
+    pub fn apply(&self, vm: &mut VM) -> Result<(), MemoryError> {
+        for &(register, value) in &self.registers {
+            vm.registers_storage[register] = value;
+            if let Some(checker) = vm.init_checker.as_mut() {
+                checker.initialize_register(register);
+            }
+        }
+        for (address, words) in &self.memory {
+            for (address, &word) in (*address..=u16::MAX).chain(0..).zip(words) {
+                let size = vm.memory.size();
+                if address as usize >= size {
+                    let address = address as usize;
+                    return Err(MemoryError { address, size });
+                }
+                vm.mem_write(address, word);
+            }
+        }
+        Ok(())
+    }
+}
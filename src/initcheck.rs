@@ -0,0 +1,257 @@
+//! Detection of reads from uninitialized memory and registers.
+//!
+//! `VM::new` fills memory and registers with zeros, so a program that reads
+//! a location it never set can work by accident. With an `InitChecker` in
+//! `VM::init_checker` the VM keeps a shadow bit per memory word and per
+//! register, much like valgrind's memcheck:
+//!
+//! - loading an image, `InitState::apply` and `VM::mem_write` initialize
+//!   memory, as does the device register page from the start;
+//! - an instruction writing a register initializes it; PC and the condition
+//!   codes start initialized;
+//! - an instruction reading a location without its bit set is reported
+//!   under `InitMode::Warn`, or faults with `Fault::UninitializedRead` under
+//!   `InitMode::Fault`.
+//!
+//! `AND R, R, #0`, and on the LC-3b `XOR R, R, R`, clear a register without
+//! depending on it, so they don't count as reads. Stepping back with
+//! `VM::step_back` leaves the shadow bits as they are.
+
+use crate::decode::DecodedInstr;
+use crate::lc3b::Lc3bInstr;
+use crate::memory::ADDRESS_SPACE;
+use crate::run::Registers;
+use crate::symbols::SymbolTable;
+
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+/// What happens when an instruction reads an uninitialized location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InitMode {
+    /// Records the read and carries on; see `InitChecker::problems`.
+    #[default]
+    Warn,
+    /// Stops the instruction with `Fault::UninitializedRead`.
+    Fault,
+}
+
+impl std::str::FromStr for InitMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "warn" => Ok(InitMode::Warn),
+            "fault" => Ok(InitMode::Fault),
+            _ => Err(format!("unknown uninitialized read mode `{}`", name)),
+        }
+    }
+}
+
+/// An uninitialized location an instruction read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uninitialized {
+    /// The instruction word itself: the program ran into memory it never
+    /// set.
+    Instruction,
+    /// A data word, by its index in `VM::memory` (the word address on the
+    /// LC-3b).
+    Memory(u16),
+    /// A register, by its `Registers` index.
+    Register(u8),
+}
+
+impl fmt::Display for Uninitialized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Uninitialized::Instruction => f.write_str("instruction"),
+            Uninitialized::Memory(address) => write!(f, "memory x{:04X}", address),
+            Uninitialized::Register(register) => write!(f, "R{}", register),
+        }
+    }
+}
+
+/// A read found under `InitMode::Warn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitRead {
+    /// The instruction that read it.
+    pub pc: u16,
+    pub location: Uninitialized,
+}
+
+/// Shadow "initialized" bits for memory and registers. Install one in
+/// `VM::init_checker` before loading images, so they are marked too.
+#[derive(Debug, Clone)]
+pub struct InitChecker {
+    pub mode: InitMode,
+    /// One bit per word of the address space.
+    memory: Vec<u64>,
+    /// One bit per `Registers` index.
+    registers: u16,
+    problems: Vec<UninitRead>,
+}
+
+impl InitChecker {
+    pub fn new(mode: InitMode) -> Self {
+        let mut checker = Self {
+            mode,
+            memory: vec![0; ADDRESS_SPACE / 64],
+            registers: 0,
+            problems: Vec::new(),
+        };
+        checker.initialize(0xFE00..ADDRESS_SPACE);
+        checker.initialize_register(Registers::R_PC as usize);
+        checker.initialize_register(Registers::R_COND as usize);
+        checker
+    }
+
+    /// Marks the words in `range` as initialized.
+    pub fn initialize(&mut self, range: Range<usize>) {
+        for address in range.take_while(|&address| address < ADDRESS_SPACE) {
+            self.memory[address / 64] |= 1 << (address % 64);
+        }
+    }
+
+    /// Marks `register`, a `Registers` index, as initialized.
+    pub fn initialize_register(&mut self, register: usize) {
+        self.registers |= 1 << register;
+    }
+
+    pub fn is_initialized(&self, address: u16) -> bool {
+        let address = address as usize;
+        self.memory[address / 64] & (1 << (address % 64)) != 0
+    }
+
+    pub fn is_register_initialized(&self, register: usize) -> bool {
+        self.registers & (1 << register) != 0
+    }
+
+    /// Reads found so far, in the order they happened.
+    pub fn problems(&self) -> &[UninitRead] {
+        &self.problems
+    }
+
+    /// The first uninitialized location among the instruction word at
+    /// `address` and the registers in `reads`.
+    pub(crate) fn check(&self, address: u16, reads: u16) -> Option<Uninitialized> {
+        if !self.is_initialized(address) {
+            return Some(Uninitialized::Instruction);
+        }
+        let uninitialized = reads & !self.registers;
+        (uninitialized != 0).then(|| Uninitialized::Register(uninitialized.trailing_zeros() as u8))
+    }
+
+    /// Marks the registers in `writes` as initialized, along with any the
+    /// instruction changed, which covers extensions.
+    pub(crate) fn written(&mut self, writes: u16, before: &[u16], after: &[u16]) {
+        self.registers |= writes;
+        for (register, (old, new)) in before.iter().zip(after).enumerate() {
+            if old != new {
+                self.initialize_register(register);
+            }
+        }
+    }
+
+    /// Records a read, once per instruction and location.
+    pub(crate) fn flag(&mut self, pc: u16, location: Uninitialized) {
+        let read = UninitRead { pc, location };
+        if !self.problems.contains(&read) {
+            self.problems.push(read);
+        }
+    }
+
+    /// Lists the reads found, one per line, or nothing when there are none.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        if self.problems.is_empty() {
+            return out;
+        }
+        writeln!(out, "Uninitialized reads:").unwrap();
+        for read in &self.problems {
+            let label = match symbols.nearest(read.pc) {
+                Some(_) => symbols.describe(read.pc),
+                None => String::new(),
+            };
+            let location = match read.location {
+                Uninitialized::Memory(address) if symbols.nearest(address).is_some() => {
+                    format!("{} ({})", read.location, symbols.describe(address))
+                }
+                location => location.to_string(),
+            };
+            writeln!(out, "  x{:04X}  {:<16} {}", read.pc, label, location).unwrap();
+        }
+        out
+    }
+}
+
+fn bit(register: u8) -> u16 {
+    1 << register
+}
+
+/// Registers an LC-3 trap routine reads and writes, besides R7.
+fn trap_registers(vector: u16) -> (u16, u16) {
+    match vector {
+        // GETC, IN
+        0x20 | 0x23 => (0, bit(0)),
+        // OUT, PUTS, PUTSP
+        0x21 | 0x22 | 0x24 => (bit(0), 0),
+        _ => (0, 0),
+    }
+}
+
+/// The registers `decoded` reads and writes, as bits of `Registers` indices.
+pub(crate) fn lc3_registers(decoded: DecodedInstr) -> (u16, u16) {
+    let r7 = bit(Registers::R_R7 as u8);
+    match decoded {
+        DecodedInstr::Add { dr, sr1, sr2 } | DecodedInstr::And { dr, sr1, sr2 } => {
+            (bit(sr1) | bit(sr2), bit(dr))
+        }
+        DecodedInstr::AndImm { dr, imm: 0, .. } => (0, bit(dr)),
+        DecodedInstr::AddImm { dr, sr1, .. }
+        | DecodedInstr::AndImm { dr, sr1, .. }
+        | DecodedInstr::Not { dr, sr: sr1 } => (bit(sr1), bit(dr)),
+        DecodedInstr::Jmp { base } => (bit(base), 0),
+        DecodedInstr::Jsr { .. } => (0, r7),
+        DecodedInstr::Jsrr { base } => (bit(base), r7),
+        DecodedInstr::Ld { dr, .. }
+        | DecodedInstr::Ldi { dr, .. }
+        | DecodedInstr::Lea { dr, .. } => (0, bit(dr)),
+        DecodedInstr::Ldr { dr, base, .. } => (bit(base), bit(dr)),
+        DecodedInstr::St { sr, .. } | DecodedInstr::Sti { sr, .. } => (bit(sr), 0),
+        DecodedInstr::Str { sr, base, .. } => (bit(sr) | bit(base), 0),
+        DecodedInstr::Trap { vector } => {
+            let (reads, writes) = trap_registers(vector);
+            (reads, writes | r7)
+        }
+        DecodedInstr::Br { .. } | DecodedInstr::Rti | DecodedInstr::Illegal { .. } => (0, 0),
+    }
+}
+
+/// The registers the LC-3b instruction `decoded` reads and writes.
+pub(crate) fn lc3b_registers(decoded: Lc3bInstr) -> (u16, u16) {
+    let r7 = bit(Registers::R_R7 as u8);
+    match decoded {
+        Lc3bInstr::Xor { dr, sr1, sr2 } if sr1 == sr2 => (0, bit(dr)),
+        Lc3bInstr::Add { dr, sr1, sr2 }
+        | Lc3bInstr::And { dr, sr1, sr2 }
+        | Lc3bInstr::Xor { dr, sr1, sr2 } => (bit(sr1) | bit(sr2), bit(dr)),
+        Lc3bInstr::AndImm { dr, imm: 0, .. } => (0, bit(dr)),
+        Lc3bInstr::AddImm { dr, sr1, .. }
+        | Lc3bInstr::AndImm { dr, sr1, .. }
+        | Lc3bInstr::XorImm { dr, sr1, .. }
+        | Lc3bInstr::Shf { dr, sr: sr1, .. } => (bit(sr1), bit(dr)),
+        Lc3bInstr::Jmp { base } => (bit(base), 0),
+        Lc3bInstr::Jsr { .. } => (0, r7),
+        Lc3bInstr::Jsrr { base } => (bit(base), r7),
+        Lc3bInstr::Ldb { dr, base, .. } | Lc3bInstr::Ldw { dr, base, .. } => (bit(base), bit(dr)),
+        Lc3bInstr::Lea { dr, .. } => (0, bit(dr)),
+        Lc3bInstr::Stb { sr, base, .. } | Lc3bInstr::Stw { sr, base, .. } => {
+            (bit(sr) | bit(base), 0)
+        }
+        Lc3bInstr::Trap { vector } => {
+            let (reads, writes) = trap_registers(vector);
+            (reads, writes | r7)
+        }
+        Lc3bInstr::Br { .. } | Lc3bInstr::Rti | Lc3bInstr::Illegal { .. } => (0, 0),
+    }
+}
@@ -2,18 +2,30 @@ use libc::*;
 use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use std::mem::MaybeUninit;
 use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Once;
 
 static mut ORIGINAL_TIO: MaybeUninit<termios> = MaybeUninit::uninit();
 static INIT: Once = Once::new();
+static DEFER_INTERRUPT: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 extern "C" fn handle_interrupt(_sig: c_int) {
+    if DEFER_INTERRUPT.load(Ordering::SeqCst) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        return;
+    }
     println!("\nSIGINT received. Restoring terminal settings...");
     restore_input_buffering();
-    std::process::exit(0);
+    std::process::exit(130);
 }
 pub fn setup() {
-    // Set up SIGINT handler (Ctrl+C)
+    handle_interrupts();
+    disable_input_buffering();
+}
+
+/// Installs the SIGINT (Ctrl+C) handler without changing the terminal mode.
+pub fn handle_interrupts() {
     unsafe {
         let sig_action = SigAction::new(
             SigHandler::Handler(handle_interrupt),
@@ -23,15 +35,32 @@ pub fn setup() {
 
         signal::sigaction(Signal::SIGINT, &sig_action).expect("Failed to register SIGINT handler");
     }
+}
 
-    disable_input_buffering();
+/// Makes Ctrl+C set a flag for the run loop to act on instead of exiting
+/// straight away, so the machine state can be saved first.
+pub fn defer_interrupts() {
+    DEFER_INTERRUPT.store(true, Ordering::SeqCst);
+}
+
+pub fn interrupts_deferred() -> bool {
+    DEFER_INTERRUPT.load(Ordering::Relaxed)
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Returns whether Ctrl+C was pressed since the last call, clearing the flag.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
 }
 
 pub fn disable_input_buffering() {
     unsafe {
         let fd: RawFd = STDIN_FILENO;
         INIT.call_once(|| {
-            tcgetattr(fd, ORIGINAL_TIO.as_mut_ptr());
+            tcgetattr(fd, std::ptr::addr_of_mut!(ORIGINAL_TIO).cast());
         });
 
         let mut new_tio = ORIGINAL_TIO.assume_init();
@@ -0,0 +1,405 @@
+//! x86-64 compiler for hot basic blocks (Linux only, `jit` feature).
+//!
+//! Blocks built by the threaded engine are compiled to native code once they
+//! have been entered `HOT_THRESHOLD` times. The code works directly on the
+//! VM's register file and memory:
+//!
+//! | Register | Holds                                      |
+//! | -------- | ------------------------------------------ |
+//! | `rdi`    | `registers_storage` (`R0`-`R7`, PC, COND)  |
+//! | `rsi`    | `memory`                                   |
+//! | `rdx`    | per-address count of blocks built from it  |
+//!
+//! ADD, AND, NOT, LEA, loads, stores and BR are compiled. Anything else
+//! (JMP/RET, JSR/JSRR, TRAP, RTI) is left to the interpreter: the native code
+//! stores the PC of that instruction and returns how many instructions it
+//! completed. Loads from the device registers and stores into device
+//! registers or into code that has been built into a block do the same, so
+//! keyboard polling and self-modifying code behave exactly as interpreted.
+//! A block whose BR jumps back to its own start loops without returning,
+//! counting the instructions completed in `r8d`.
+
+use crate::blocks::{Block, MAX_BLOCK_LEN};
+use crate::decode::DecodedInstr;
+use crate::run::{Fault, Registers, Step, MR_KBSR, VM};
+
+use std::fmt;
+use std::ptr;
+use std::sync::Arc;
+
+/// Block entries before a block is compiled.
+const HOT_THRESHOLD: u32 = 16;
+
+/// A block that branches back to its own start loops natively until it has
+/// run at least this many instructions, then returns so the VM can check for
+/// interrupts.
+const LOOP_LIMIT: u32 = 4096;
+
+/// Most instructions one call of `VM::run_native` can complete: loop
+/// iterations up to the limit, a final pass, and the interpreted instruction.
+pub(crate) const MAX_NATIVE_RUN: u64 = LOOP_LIMIT as u64 + 2 * MAX_BLOCK_LEN as u64;
+
+/// Set in the return value when the native code stopped at an instruction
+/// it leaves to the interpreter.
+const EXITED_EARLY: u32 = 1 << 31;
+
+const PC_OFFSET: u8 = 2 * Registers::R_PC as u8;
+const COND_OFFSET: u8 = 2 * Registers::R_COND as u8;
+
+type NativeFn = unsafe extern "sysv64" fn(*mut u16, *mut u16, *const u8) -> u32;
+
+/// Executable memory holding one compiled block.
+struct NativeBlock {
+    code: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is owned exclusively by the block and never written after it
+// is made executable.
+unsafe impl Send for NativeBlock {}
+unsafe impl Sync for NativeBlock {}
+
+impl NativeBlock {
+    /// Copies `bytes` into a fresh mapping and makes it executable.
+    fn new(bytes: &[u8]) -> Option<Self> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = bytes.len().div_ceil(page) * page;
+        unsafe {
+            let code = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if code == libc::MAP_FAILED {
+                return None;
+            }
+            let block = Self { code, len };
+            ptr::copy_nonoverlapping(bytes.as_ptr(), code.cast::<u8>(), bytes.len());
+            if libc::mprotect(code, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(block)
+        }
+    }
+
+    fn entry(&self) -> NativeFn {
+        unsafe { std::mem::transmute::<*mut libc::c_void, NativeFn>(self.code) }
+    }
+}
+
+impl Drop for NativeBlock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.code, self.len);
+        }
+    }
+}
+
+/// Compiled blocks and entry counts. Cloning a VM starts the clone with an
+/// empty cache, since compiled code is rebuilt on demand.
+#[derive(Default)]
+pub struct JitCache {
+    entries: Vec<u32>,
+    /// Native code for a block start, with the block it was compiled from.
+    native: Vec<Option<(Arc<Block>, Arc<NativeBlock>)>>,
+    compiled: u64,
+}
+
+impl Clone for JitCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for JitCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitCache")
+            .field("compiled", &self.compiled)
+            .finish()
+    }
+}
+
+impl JitCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks compiled to native code.
+    pub fn compiled(&self) -> u64 {
+        self.compiled
+    }
+
+    /// Native code for `block`, compiling it once it is hot. Code compiled
+    /// from a block that has since been rebuilt is discarded.
+    fn lookup(&mut self, start: u16, block: &Arc<Block>) -> Option<Arc<NativeBlock>> {
+        if self.entries.is_empty() {
+            self.entries = vec![0; 1 << 16];
+            self.native = vec![None; 1 << 16];
+        }
+        if let Some((source, native)) = &self.native[start as usize] {
+            if Arc::ptr_eq(source, block) {
+                return Some(native.clone());
+            }
+            self.native[start as usize] = None;
+            self.entries[start as usize] = 0;
+        }
+        let entries = &mut self.entries[start as usize];
+        *entries += 1;
+        if *entries < HOT_THRESHOLD {
+            return None;
+        }
+        let native = Arc::new(NativeBlock::new(&compile(start, &block.code))?);
+        self.native[start as usize] = Some((block.clone(), native.clone()));
+        self.compiled += 1;
+        Some(native)
+    }
+}
+
+impl VM {
+    /// Runs `block` as native code if it is hot. The instruction the native
+    /// code stopped at, if any, is then run by the interpreter. Returns `None`
+    /// when the block is not compiled yet.
+    pub(crate) fn run_native(
+        &mut self,
+        start: u16,
+        block: &Arc<Block>,
+    ) -> Option<Result<Step, Fault>> {
+        let native = self.jit.lookup(start, block)?;
+        let completed = unsafe {
+            (native.entry())(
+                self.registers_storage.as_mut_ptr(),
+                self.memory
+                    .as_mut_slice()
+                    .expect("native code needs flat memory")
+                    .as_mut_ptr(),
+                self.blocks.covered().as_ptr(),
+            )
+        };
+        self.instruction_count += u64::from(completed & !EXITED_EARLY);
+        if completed & EXITED_EARLY != 0 {
+            return Some(self.step());
+        }
+        Some(Ok(Step::Continue))
+    }
+}
+
+/// Emits x86-64 code for the block at `start`.
+fn compile(start: u16, code: &[DecodedInstr]) -> Vec<u8> {
+    let mut asm = Assembler::default();
+    let len = code.len() as u32;
+    asm.emit(&[0x45, 0x31, 0xC0]); // xor r8d, r8d
+    let top = asm.bytes.len();
+    for (index, &decoded) in code.iter().enumerate() {
+        let index = index as u32;
+        let pc = start.wrapping_add(index as u16);
+        match decoded {
+            DecodedInstr::Add { dr, sr1, sr2 } => {
+                asm.load_register(EAX, sr1);
+                asm.load_register(ECX, sr2);
+                asm.emit(&[0x01, 0xC8]); // add eax, ecx
+                asm.store_result(dr);
+            }
+            DecodedInstr::AddImm { dr, sr1, imm } => {
+                asm.load_register(EAX, sr1);
+                asm.emit(&[0x05]); // add eax, imm32
+                asm.emit(&u32::from(imm).to_le_bytes());
+                asm.store_result(dr);
+            }
+            DecodedInstr::And { dr, sr1, sr2 } => {
+                asm.load_register(EAX, sr1);
+                asm.load_register(ECX, sr2);
+                asm.emit(&[0x21, 0xC8]); // and eax, ecx
+                asm.store_result(dr);
+            }
+            DecodedInstr::AndImm { dr, sr1, imm } => {
+                asm.load_register(EAX, sr1);
+                asm.emit(&[0x25]); // and eax, imm32
+                asm.emit(&u32::from(imm).to_le_bytes());
+                asm.store_result(dr);
+            }
+            DecodedInstr::Not { dr, sr } => {
+                asm.load_register(EAX, sr);
+                asm.emit(&[0xF7, 0xD0]); // not eax
+                asm.store_result(dr);
+            }
+            DecodedInstr::Lea { dr, address } => {
+                asm.mov_eax(address);
+                asm.store_result(dr);
+            }
+            DecodedInstr::Ld { dr, address } if address < MR_KBSR => {
+                asm.mov_eax(address);
+                asm.load_memory();
+                asm.store_result(dr);
+            }
+            DecodedInstr::Ldi { dr, address } if address < MR_KBSR => {
+                asm.mov_eax(address);
+                asm.load_memory();
+                asm.exit_if_device(index);
+                asm.load_memory();
+                asm.store_result(dr);
+            }
+            DecodedInstr::Ldr { dr, base, offset } => {
+                asm.effective_address(base, offset);
+                asm.exit_if_device(index);
+                asm.load_memory();
+                asm.store_result(dr);
+            }
+            DecodedInstr::St { sr, address } if address < MR_KBSR => {
+                asm.mov_eax(address);
+                asm.store_memory(index, sr);
+            }
+            DecodedInstr::Sti { sr, address } if address < MR_KBSR => {
+                asm.mov_eax(address);
+                asm.load_memory();
+                asm.exit_if_device(index);
+                asm.store_memory(index, sr);
+            }
+            DecodedInstr::Str { sr, base, offset } => {
+                asm.effective_address(base, offset);
+                asm.exit_if_device(index);
+                asm.store_memory(index, sr);
+            }
+            DecodedInstr::Br { nzp, target } => {
+                // movzx eax, word [rdi + COND] ; test eax, nzp ; jz not_taken
+                asm.emit(&[0x0F, 0xB7, 0x47, COND_OFFSET, 0xA9]);
+                asm.emit(&u32::from(nzp).to_le_bytes());
+                if target == start {
+                    // Taken: add r8d, len ; cmp r8d, LOOP_LIMIT ; jb top
+                    asm.emit(&[0x74, 7 + 7 + 6 + EXIT_LEN]);
+                    asm.emit(&[0x41, 0x81, 0xC0]);
+                    asm.emit(&len.to_le_bytes());
+                    asm.emit(&[0x41, 0x81, 0xF8]);
+                    asm.emit(&LOOP_LIMIT.to_le_bytes());
+                    asm.emit(&[0x0F, 0x82]);
+                    let rel = top as i32 - (asm.bytes.len() as i32 + 4);
+                    asm.emit(&rel.to_le_bytes());
+                    // Over the limit: leave through the fall-through exit,
+                    // with PC at the loop start again
+                    asm.exit(start, 0);
+                } else {
+                    asm.emit(&[0x74, EXIT_LEN]);
+                    asm.exit(target, index + 1);
+                }
+                asm.exit(pc.wrapping_add(1), index + 1);
+                return asm.finish(start);
+            }
+            _ => {
+                asm.exit(pc, index | EXITED_EARLY);
+                return asm.finish(start);
+            }
+        }
+    }
+    asm.exit(start.wrapping_add(len as u16), len);
+    asm.finish(start)
+}
+
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+
+/// Length of the code emitted by `Assembler::exit`.
+const EXIT_LEN: u8 = 15;
+
+#[derive(Default)]
+struct Assembler {
+    bytes: Vec<u8>,
+    /// `rel32` operands to patch with the exit for an instruction index.
+    exits: Vec<(usize, u32)>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// movzx reg, word [rdi + 2 * register]
+    fn load_register(&mut self, reg: u8, register: u8) {
+        self.emit(&[0x0F, 0xB7, 0x47 | (reg << 3), 2 * register]);
+    }
+
+    fn mov_eax(&mut self, value: u16) {
+        self.emit(&[0xB8]);
+        self.emit(&u32::from(value).to_le_bytes());
+    }
+
+    /// eax = (register + offset) & 0xFFFF
+    fn effective_address(&mut self, register: u8, offset: u16) {
+        self.load_register(EAX, register);
+        self.emit(&[0x05]); // add eax, imm32
+        self.emit(&u32::from(offset).to_le_bytes());
+        self.emit(&[0x0F, 0xB7, 0xC0]); // movzx eax, ax
+    }
+
+    /// movzx eax, word [rsi + rax * 2]
+    fn load_memory(&mut self) {
+        self.emit(&[0x0F, 0xB7, 0x04, 0x46]);
+    }
+
+    /// Stores register `sr` at the address in eax, leaving the store to the
+    /// interpreter when the address holds code.
+    fn store_memory(&mut self, index: u32, sr: u8) {
+        // cmp byte [rdx + rax], 0 ; jne exit
+        self.emit(&[0x80, 0x3C, 0x02, 0x00, 0x0F, 0x85]);
+        self.exit_to(index);
+        self.load_register(ECX, sr);
+        self.emit(&[0x66, 0x89, 0x0C, 0x46]); // mov word [rsi + rax * 2], cx
+    }
+
+    /// Leaves instruction `index` to the interpreter when eax is a device address.
+    fn exit_if_device(&mut self, index: u32) {
+        self.emit(&[0x3D]); // cmp eax, imm32
+        self.emit(&u32::from(MR_KBSR).to_le_bytes());
+        self.emit(&[0x0F, 0x83]); // jae exit
+        self.exit_to(index);
+    }
+
+    fn exit_to(&mut self, index: u32) {
+        self.exits.push((self.bytes.len(), index));
+        self.emit(&[0; 4]);
+    }
+
+    /// Writes `ax` to register `dr` and sets the condition codes from it.
+    fn store_result(&mut self, dr: u8) {
+        self.emit(&[0x66, 0x89, 0x47, 2 * dr]); // mov [rdi + 2 * dr], ax
+        self.emit(&[0x66, 0x85, 0xC0]); // test ax, ax
+        self.emit(&[0xB9, 0x02, 0x00, 0x00, 0x00]); // mov ecx, FL_ZRO
+        self.emit(&[0x74, 0x0C]); // jz store
+        self.emit(&[0xB9, 0x04, 0x00, 0x00, 0x00]); // mov ecx, FL_NEG
+        self.emit(&[0x78, 0x05]); // js store
+        self.emit(&[0xB9, 0x01, 0x00, 0x00, 0x00]); // mov ecx, FL_POS
+        self.emit(&[0x66, 0x89, 0x4F, COND_OFFSET]); // store: mov [rdi + COND], cx
+    }
+
+    /// Sets PC and returns the number of instructions completed: `completed`
+    /// in this pass through the block plus earlier loop iterations in r8d.
+    fn exit(&mut self, pc: u16, completed: u32) {
+        self.emit(&[0x66, 0xC7, 0x47, PC_OFFSET]); // mov word [rdi + PC], imm16
+        self.emit(&pc.to_le_bytes());
+        self.emit(&[0xB8]); // mov eax, imm32
+        self.emit(&completed.to_le_bytes());
+        self.emit(&[0x44, 0x01, 0xC0]); // add eax, r8d
+        self.emit(&[0xC3]); // ret
+    }
+
+    /// Appends an exit for every instruction left to the interpreter and
+    /// patches the jumps to them.
+    fn finish(mut self, start: u16) -> Vec<u8> {
+        let mut stubs: Vec<(u32, usize)> = Vec::new();
+        for (operand, index) in std::mem::take(&mut self.exits) {
+            let stub = match stubs.iter().find(|&&(i, _)| i == index) {
+                Some(&(_, stub)) => stub,
+                None => {
+                    let stub = self.bytes.len();
+                    self.exit(start.wrapping_add(index as u16), index | EXITED_EARLY);
+                    stubs.push((index, stub));
+                    stub
+                }
+            };
+            let rel = (stub as i32 - (operand as i32 + 4)).to_le_bytes();
+            self.bytes[operand..operand + 4].copy_from_slice(&rel);
+        }
+        self.bytes
+    }
+}
@@ -0,0 +1,454 @@
+//! The LC-3b, the byte-addressable variant of the LC-3.
+//!
+//! Setting `VM::isa` to `Isa::Lc3b` decodes and executes programs as LC-3b.
+//! Addresses are byte addresses and words are little-endian: the byte at
+//! `a` is the low (even `a`) or high (odd `a`) half of word `a >> 1` of
+//! `VM::memory`, so LC-3b memory takes the first x7F00 words. The device
+//! registers keep their LC-3 addresses from xFE00 up, so both variants share
+//! the keyboard registers and the console.
+//!
+//! Compared to the LC-3:
+//!
+//! - PC advances by 2, and BR, JSR and LEA offsets are scaled by 2. LEA does
+//!   not set the condition codes.
+//! - LDB and STB (opcodes 2 and 3) load a sign-extended byte and store the
+//!   low byte of a register, with an unscaled offset.
+//! - LDW and STW (opcodes 6 and 7) scale their offset by 2. Word accesses at
+//!   odd addresses fault with `Fault::UnalignedAccess`.
+//! - XOR replaces NOT (opcode 9); NOT is XOR with #-1.
+//! - SHF (opcode 13, the LC-3's reserved opcode) shifts left, right
+//!   logically or right arithmetically by 0-15 bits.
+//! - LDI and STI are gone; their opcodes are illegal.
+//! - TRAP jumps to the routine whose address is at byte `vector * 2`, when
+//!   the table has one. Otherwise GETC, OUT, IN and HALT run as on the
+//!   LC-3, PUTS prints a string of bytes, and PUTSP does not exist.
+
+use crate::run::{sign_extend, Fault, Registers, Step, VM};
+use crate::symbols::SymbolTable;
+
+use std::fmt;
+
+/// Byte addresses from here up are device registers, which keep their LC-3
+/// word addresses.
+const DEVICE_PAGE: u16 = 0xFE00;
+
+/// The word of `VM::memory` holding the byte at `address`.
+pub fn word_address(address: u16) -> u16 {
+    if address >= DEVICE_PAGE {
+        address & !1
+    } else {
+        address >> 1
+    }
+}
+
+/// Mnemonic of each opcode, like `disasm::OPCODE_NAMES`. LDI and STI's
+/// opcodes are unused.
+pub const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LDB", "STB", "JSR", "AND", "LDW", "STW", "RTI", "XOR", "RES", "RES", "JMP",
+    "SHF", "LEA", "TRAP",
+];
+
+/// How SHF shifts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Left,
+    /// Right, shifting in zeros.
+    RightLogical,
+    /// Right, shifting in copies of the sign bit.
+    RightArithmetic,
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Shift::Left => "LSHF",
+            Shift::RightLogical => "RSHFL",
+            Shift::RightArithmetic => "RSHFA",
+        })
+    }
+}
+
+/// An LC-3b instruction with its operand fields extracted and its
+/// PC-relative address resolved, like `decode::DecodedInstr`. Offsets are
+/// already scaled to bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lc3bInstr {
+    Add {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AddImm {
+        dr: u8,
+        sr1: u8,
+        imm: u16,
+    },
+    And {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    AndImm {
+        dr: u8,
+        sr1: u8,
+        imm: u16,
+    },
+    Xor {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    /// XOR with an immediate; NOT when `imm` is xFFFF.
+    XorImm {
+        dr: u8,
+        sr1: u8,
+        imm: u16,
+    },
+    Br {
+        nzp: u16,
+        target: u16,
+    },
+    /// JMP, and RET when `base` is 7.
+    Jmp {
+        base: u8,
+    },
+    Jsr {
+        target: u16,
+    },
+    Jsrr {
+        base: u8,
+    },
+    Ldb {
+        dr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Ldw {
+        dr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Lea {
+        dr: u8,
+        address: u16,
+    },
+    Shf {
+        dr: u8,
+        sr: u8,
+        shift: Shift,
+        amount: u16,
+    },
+    Stb {
+        sr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Stw {
+        sr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Trap {
+        vector: u16,
+    },
+    Rti,
+    /// Opcodes 10 and 11, which the LC-3b does not use.
+    Illegal {
+        instr: u16,
+    },
+}
+
+/// Decodes the LC-3b instruction `instr`, fetched from byte `address`.
+pub fn decode(address: u16, instr: u16) -> Lc3bInstr {
+    let dr = ((instr >> 9) & 0x7) as u8;
+    let sr1 = ((instr >> 6) & 0x7) as u8;
+    let sr2 = (instr & 0x7) as u8;
+    let next = address.wrapping_add(2);
+    let target = |bits: u16| next.wrapping_add(sign_extend(instr & ((1 << bits) - 1), bits) << 1);
+    let imm = sign_extend(instr & 0x1F, 5);
+    let immediate = instr & 0x20 != 0;
+    let offset6 = sign_extend(instr & 0x3F, 6);
+
+    match instr >> 12 {
+        0x0 => Lc3bInstr::Br {
+            nzp: (instr >> 9) & 0x7,
+            target: target(9),
+        },
+        0x1 if immediate => Lc3bInstr::AddImm { dr, sr1, imm },
+        0x1 => Lc3bInstr::Add { dr, sr1, sr2 },
+        0x2 => Lc3bInstr::Ldb {
+            dr,
+            base: sr1,
+            offset: offset6,
+        },
+        0x3 => Lc3bInstr::Stb {
+            sr: dr,
+            base: sr1,
+            offset: offset6,
+        },
+        0x4 if instr & 0x800 != 0 => Lc3bInstr::Jsr { target: target(11) },
+        0x4 => Lc3bInstr::Jsrr { base: sr1 },
+        0x5 if immediate => Lc3bInstr::AndImm { dr, sr1, imm },
+        0x5 => Lc3bInstr::And { dr, sr1, sr2 },
+        0x6 => Lc3bInstr::Ldw {
+            dr,
+            base: sr1,
+            offset: offset6 << 1,
+        },
+        0x7 => Lc3bInstr::Stw {
+            sr: dr,
+            base: sr1,
+            offset: offset6 << 1,
+        },
+        0x8 => Lc3bInstr::Rti,
+        0x9 if immediate => Lc3bInstr::XorImm { dr, sr1, imm },
+        0x9 => Lc3bInstr::Xor { dr, sr1, sr2 },
+        0xC => Lc3bInstr::Jmp { base: sr1 },
+        0xD => Lc3bInstr::Shf {
+            dr,
+            sr: sr1,
+            shift: match (instr >> 4) & 0x3 {
+                0 | 2 => Shift::Left,
+                1 => Shift::RightLogical,
+                _ => Shift::RightArithmetic,
+            },
+            amount: instr & 0xF,
+        },
+        0xE => Lc3bInstr::Lea {
+            dr,
+            address: target(9),
+        },
+        0xF => Lc3bInstr::Trap {
+            vector: instr & 0xFF,
+        },
+        _ => Lc3bInstr::Illegal { instr },
+    }
+}
+
+/// Disassembles the LC-3b instruction `instr` at byte `address`, naming
+/// targets with `symbols` where it has a label for them.
+pub fn disassemble(address: u16, instr: u16, symbols: &SymbolTable) -> String {
+    let label = |target: u16| match symbols.label_at(target) {
+        Some(label) => label.to_string(),
+        None => format!("x{:04X}", target),
+    };
+    let operand = |imm: u16| format!("#{}", imm as i16);
+    match decode(address, instr) {
+        Lc3bInstr::Br { nzp: 0, .. } if instr == 0 => "NOP".to_string(),
+        Lc3bInstr::Br { nzp: 0, .. } => format!(".FILL x{:04X}", instr),
+        Lc3bInstr::Br { nzp, target } => {
+            let mut name = String::from("BR");
+            for (bit, flag) in [(4, 'n'), (2, 'z'), (1, 'p')] {
+                if nzp & bit != 0 {
+                    name.push(flag);
+                }
+            }
+            format!("{} {}", name, label(target))
+        }
+        Lc3bInstr::Add { dr, sr1, sr2 } => format!("ADD R{}, R{}, R{}", dr, sr1, sr2),
+        Lc3bInstr::AddImm { dr, sr1, imm } => format!("ADD R{}, R{}, {}", dr, sr1, operand(imm)),
+        Lc3bInstr::And { dr, sr1, sr2 } => format!("AND R{}, R{}, R{}", dr, sr1, sr2),
+        Lc3bInstr::AndImm { dr, sr1, imm } => format!("AND R{}, R{}, {}", dr, sr1, operand(imm)),
+        Lc3bInstr::Xor { dr, sr1, sr2 } => format!("XOR R{}, R{}, R{}", dr, sr1, sr2),
+        Lc3bInstr::XorImm {
+            dr,
+            sr1,
+            imm: 0xFFFF,
+        } => format!("NOT R{}, R{}", dr, sr1),
+        Lc3bInstr::XorImm { dr, sr1, imm } => format!("XOR R{}, R{}, {}", dr, sr1, operand(imm)),
+        Lc3bInstr::Jmp { base: 7 } => "RET".to_string(),
+        Lc3bInstr::Jmp { base } => format!("JMP R{}", base),
+        Lc3bInstr::Jsr { target } => format!("JSR {}", label(target)),
+        Lc3bInstr::Jsrr { base } => format!("JSRR R{}", base),
+        Lc3bInstr::Ldb { dr, base, offset } => {
+            format!("LDB R{}, R{}, {}", dr, base, operand(offset))
+        }
+        Lc3bInstr::Ldw { dr, base, offset } => {
+            format!("LDW R{}, R{}, {}", dr, base, operand(offset >> 1))
+        }
+        Lc3bInstr::Stb { sr, base, offset } => {
+            format!("STB R{}, R{}, {}", sr, base, operand(offset))
+        }
+        Lc3bInstr::Stw { sr, base, offset } => {
+            format!("STW R{}, R{}, {}", sr, base, operand(offset >> 1))
+        }
+        Lc3bInstr::Lea { dr, address } => format!("LEA R{}, {}", dr, label(address)),
+        Lc3bInstr::Shf {
+            dr,
+            sr,
+            shift,
+            amount,
+        } => format!("{} R{}, R{}, #{}", shift, dr, sr, amount),
+        Lc3bInstr::Trap { vector } => match vector {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        Lc3bInstr::Rti => "RTI".to_string(),
+        Lc3bInstr::Illegal { instr } => format!(".FILL x{:04X}", instr),
+    }
+}
+
+impl VM {
+    /// Fetches the LC-3b instruction at byte `pc`.
+    pub(crate) fn fetch_lc3b(&self, pc: u16) -> Result<u16, Fault> {
+        if pc & 1 != 0 {
+            return Err(Fault::UnalignedAccess { pc, address: pc });
+        }
+        self.memory
+            .read(word_address(pc) as usize)
+            .map_err(|e| self.out_of_range(pc, e))
+    }
+
+    /// Executes the LC-3b instruction `instr` fetched from byte `pc`.
+    pub(crate) fn execute_lc3b(&mut self, pc: u16, instr: u16) -> Result<Step, Fault> {
+        self.registers_storage[Registers::R_PC as usize] = pc.wrapping_add(2);
+        let r = |index: u8| index as usize;
+        match decode(pc, instr) {
+            Lc3bInstr::Add { dr, sr1, sr2 } => {
+                self.registers_storage[r(dr)] =
+                    self.registers_storage[r(sr1)].wrapping_add(self.registers_storage[r(sr2)]);
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::AddImm { dr, sr1, imm } => {
+                self.registers_storage[r(dr)] = self.registers_storage[r(sr1)].wrapping_add(imm);
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::And { dr, sr1, sr2 } => {
+                self.registers_storage[r(dr)] =
+                    self.registers_storage[r(sr1)] & self.registers_storage[r(sr2)];
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::AndImm { dr, sr1, imm } => {
+                self.registers_storage[r(dr)] = self.registers_storage[r(sr1)] & imm;
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::Xor { dr, sr1, sr2 } => {
+                self.registers_storage[r(dr)] =
+                    self.registers_storage[r(sr1)] ^ self.registers_storage[r(sr2)];
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::XorImm { dr, sr1, imm } => {
+                self.registers_storage[r(dr)] = self.registers_storage[r(sr1)] ^ imm;
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::Br { nzp, target } => {
+                if nzp & self.registers_storage[Registers::R_COND as usize] != 0 {
+                    self.registers_storage[Registers::R_PC as usize] = target;
+                }
+            }
+            Lc3bInstr::Jmp { base } => {
+                let target = self.registers_storage[r(base)];
+                self.registers_storage[Registers::R_PC as usize] = target;
+                if base == Registers::R_R7 as u8 {
+                    self.track_return(pc, target);
+                }
+            }
+            Lc3bInstr::Jsr { target } => self.call(pc, target),
+            Lc3bInstr::Jsrr { base } => self.call(pc, self.registers_storage[r(base)]),
+            Lc3bInstr::Ldb { dr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                let byte = self.read_byte(address);
+                self.registers_storage[r(dr)] = sign_extend(u16::from(byte), 8);
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::Ldw { dr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                self.registers_storage[r(dr)] = self.read_word(pc, address)?;
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::Lea { dr, address } => self.registers_storage[r(dr)] = address,
+            Lc3bInstr::Shf {
+                dr,
+                sr,
+                shift,
+                amount,
+            } => {
+                let value = self.registers_storage[r(sr)];
+                self.registers_storage[r(dr)] = match shift {
+                    Shift::Left => value << amount,
+                    Shift::RightLogical => value >> amount,
+                    Shift::RightArithmetic => ((value as i16) >> amount) as u16,
+                };
+                self.update_flags(dr as u16);
+            }
+            Lc3bInstr::Stb { sr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                self.write_byte(address, self.registers_storage[r(sr)] as u8);
+            }
+            Lc3bInstr::Stw { sr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                if address & 1 != 0 {
+                    return Err(Fault::UnalignedAccess { pc, address });
+                }
+                self.mem_write(word_address(address), self.registers_storage[r(sr)]);
+            }
+            Lc3bInstr::Trap { vector } => return self.trap_lc3b(pc, vector),
+            Lc3bInstr::Rti => return Err(Fault::PrivilegeViolation { pc }),
+            Lc3bInstr::Illegal { instr } => return Err(Fault::IllegalOpcode { pc, instr }),
+        }
+        Ok(Step::Continue)
+    }
+
+    /// TRAP: calls the routine in the trap vector table, or the built-in one
+    /// when the table entry is zero.
+    fn trap_lc3b(&mut self, pc: u16, vector: u16) -> Result<Step, Fault> {
+        // The entry at byte `vector * 2` is word `vector`
+        let entry = self.memory.read(vector as usize).unwrap_or(0);
+        if entry != 0 {
+            self.registers_storage[Registers::R_R7 as usize] =
+                self.registers_storage[Registers::R_PC as usize];
+            self.registers_storage[Registers::R_PC as usize] = entry;
+            return Ok(Step::Continue);
+        }
+        match vector {
+            0x22 => {
+                self.registers_storage[Registers::R_R7 as usize] =
+                    self.registers_storage[Registers::R_PC as usize];
+                self.trap_puts_bytes();
+                Ok(Step::Continue)
+            }
+            0x24 => Err(Fault::InvalidTrap { pc, vector }),
+            _ => self.trap(pc, vector),
+        }
+    }
+
+    /// PUTS: writes the bytes from byte address R0 up to a zero byte.
+    fn trap_puts_bytes(&mut self) {
+        let mut address = self.registers_storage[Registers::R_R0 as usize];
+        let mut text = Vec::new();
+        loop {
+            let c = self.read_byte(address);
+            if c == 0 {
+                break;
+            }
+            text.push(c);
+            address = address.wrapping_add(1);
+        }
+        self.console.write(&text);
+    }
+
+    fn read_byte(&mut self, address: u16) -> u8 {
+        let word = self.memory_read(word_address(address));
+        (word >> (8 * (address & 1))) as u8
+    }
+
+    fn read_word(&mut self, pc: u16, address: u16) -> Result<u16, Fault> {
+        if address & 1 != 0 {
+            return Err(Fault::UnalignedAccess { pc, address });
+        }
+        Ok(self.memory_read(word_address(address)))
+    }
+
+    /// Writes `byte` into its half of a word, leaving the other half alone.
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        let word_address = word_address(address);
+        let shift = 8 * (address & 1);
+        let old = self.memory.read(word_address as usize).unwrap_or(0);
+        let word = (old & !(0xFF << shift)) | (u16::from(byte) << shift);
+        self.mem_write(word_address, word);
+    }
+}
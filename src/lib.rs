@@ -1,2 +1,36 @@
 pub mod run;
 pub mod input_buffering;
+pub mod console;
+pub mod symbols;
+pub mod debugger;
+pub mod dap;
+pub mod history;
+pub mod snapshot;
+pub mod replay;
+pub mod disasm;
+pub mod profiler;
+pub mod coverage;
+pub mod callstack;
+pub mod decode;
+pub mod blocks;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod budget;
+pub mod loops;
+pub mod grade;
+pub mod init;
+pub mod asm;
+pub mod trace;
+pub mod dump;
+pub mod repl;
+pub mod cli;
+pub mod loader;
+pub mod records;
+pub mod memory;
+pub mod lc3b;
+pub mod extension;
+pub mod stackcheck;
+pub mod initcheck;
+
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", target_os = "linux"))))]
+compile_error!("the `jit` feature is only supported on x86-64 Linux");
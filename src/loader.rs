@@ -0,0 +1,497 @@
+//! Loading object images with validation.
+//!
+//! An `.obj` image is a big-endian origin followed by the words to place
+//! there. lc3tools also writes the same words as text, one per line: four
+//! hex digits in a `.hex` file, or sixteen binary digits in a `.bin` file.
+//! Intel HEX and S-record files, read by the `records` module, may hold
+//! several segments. `ImageFormat` tells these apart by extension and
+//! content.
+//!
+//! `ImageLoader` checks each image before it touches memory. It
+//! reports files too short to hold an origin, images with no words, a
+//! trailing odd byte, and segments that would run past the end of memory. It also
+//! reports segments that overlap one loaded earlier. Depending on the `Strictness`,
+//! problems either fail the load or come back as warnings while the image
+//! loads as well as it can.
+//!
+//! For an LC-3b `VM` the origin of an `.obj`, `.hex` or `.bin` image is a
+//! byte address, and an odd one is reported. Intel HEX and S-record
+//! addresses follow the loader's `addressing` as usual.
+
+use crate::dump::Dump;
+use crate::lc3b;
+use crate::memory::ADDRESS_SPACE;
+use crate::records::{
+    read_intel_hex, read_srecords, write_intel_hex, write_srecords, Addressing, RecordError,
+    Records,
+};
+use crate::run::{Isa, VM};
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+/// How the words of an image are stored in a file. In `.obj`, `.hex` and
+/// `.bin` images the first word is the origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Big-endian binary words.
+    Obj,
+    /// Four hex digits per line.
+    Hex,
+    /// Sixteen binary digits per line.
+    Bin,
+    /// Intel HEX records.
+    IntelHex,
+    /// Motorola S-records.
+    SRecord,
+}
+
+impl ImageFormat {
+    /// The format a file name names, if any.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "obj" => Some(ImageFormat::Obj),
+            "hex" => Some(ImageFormat::Hex),
+            "bin" => Some(ImageFormat::Bin),
+            "ihex" | "ihx" => Some(ImageFormat::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(ImageFormat::SRecord),
+            _ => None,
+        }
+    }
+
+    /// The text format `bytes` are written in, or `Obj` if they are not
+    /// records or lines of hex or binary words.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            return ImageFormat::Obj;
+        };
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        let Some(first) = lines.next() else {
+            return ImageFormat::Obj;
+        };
+        if first.starts_with(':') {
+            return ImageFormat::IntelHex;
+        }
+        if first.starts_with('S') && first[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            return ImageFormat::SRecord;
+        }
+        [ImageFormat::Bin, ImageFormat::Hex]
+            .into_iter()
+            .find(|format| {
+                format.parse_word(first).is_some()
+                    && lines.clone().all(|line| format.parse_word(line).is_some())
+            })
+            .unwrap_or(ImageFormat::Obj)
+    }
+
+    /// The format of the file at `path` holding `bytes`. Files are taken
+    /// at their extension's word, except that a `.hex` file may hold Intel
+    /// HEX records and a `.bin` file may be lc3tools text or an older binary
+    /// image. Those, and files with other names, are sniffed.
+    pub fn detect(path: impl AsRef<Path>, bytes: &[u8]) -> Self {
+        match (ImageFormat::from_path(path), ImageFormat::sniff(bytes)) {
+            (Some(ImageFormat::Hex), ImageFormat::IntelHex) => ImageFormat::IntelHex,
+            (Some(ImageFormat::Bin) | None, sniffed) => sniffed,
+            (Some(format), _) => format,
+        }
+    }
+
+    fn parse_word(self, line: &str) -> Option<u16> {
+        let (digits, radix) = match self {
+            ImageFormat::Hex => (4, 16),
+            ImageFormat::Bin => (16, 2),
+            _ => return None,
+        };
+        let valid = line.len() == digits && line.chars().all(|c| c.is_digit(radix));
+        valid.then(|| u16::from_str_radix(line, radix).unwrap())
+    }
+
+    /// The words in `bytes`, origin first. A trailing odd byte of an `.obj`
+    /// image is ignored; text formats fail on the first bad line. Intel HEX
+    /// and S-record files must hold a single segment, which is read with
+    /// word addressing.
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<u16>, ImageProblem> {
+        match self {
+            ImageFormat::Obj => {
+                return Ok(bytes
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect())
+            }
+            ImageFormat::IntelHex | ImageFormat::SRecord => {
+                let records = self.read(bytes, Addressing::Word)?;
+                return match records.segments.as_slice() {
+                    [] => Ok(Vec::new()),
+                    [segment] => Ok(std::iter::once(segment.start)
+                        .chain(segment.words.iter().copied())
+                        .collect()),
+                    segments => Err(ImageProblem::BadRecord(RecordError {
+                        line: None,
+                        message: format!("{} segments where one was expected", segments.len()),
+                    })),
+                };
+            }
+            ImageFormat::Hex | ImageFormat::Bin => {}
+        }
+        let text = String::from_utf8_lossy(bytes);
+        let mut words = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let word = self.parse_word(line).ok_or_else(|| ImageProblem::BadLine {
+                line: number + 1,
+                format: self,
+                found: line.to_string(),
+            })?;
+            words.push(word);
+        }
+        Ok(words)
+    }
+
+    /// The segments in `bytes`. For `.obj`, `.hex` and `.bin` images this is
+    /// the one segment after the origin, which is also the entry point.
+    pub fn read(self, bytes: &[u8], addressing: Addressing) -> Result<Records, ImageProblem> {
+        let text = String::from_utf8_lossy(bytes);
+        let records = match self {
+            ImageFormat::IntelHex => read_intel_hex(&text, addressing),
+            ImageFormat::SRecord => read_srecords(&text, addressing),
+            _ => {
+                let words = self.decode(bytes)?;
+                let Some((&origin, words)) = words.split_first() else {
+                    return Err(ImageProblem::Empty { bytes: bytes.len() });
+                };
+                let segments = (!words.is_empty()).then(|| Dump {
+                    start: origin,
+                    words: words.to_vec(),
+                });
+                return Ok(Records {
+                    segments: segments.into_iter().collect(),
+                    entry: Some(origin),
+                });
+            }
+        };
+        records.map_err(ImageProblem::BadRecord)
+    }
+
+    /// `words`, origin first, written in this format. Intel HEX and
+    /// S-records use word addressing, with the origin as the entry point.
+    pub fn encode(self, words: &[u16]) -> Vec<u8> {
+        let records = || Records {
+            segments: match words.split_first() {
+                Some((&start, words)) if !words.is_empty() => vec![Dump {
+                    start,
+                    words: words.to_vec(),
+                }],
+                _ => Vec::new(),
+            },
+            entry: words.first().copied(),
+        };
+        match self {
+            ImageFormat::IntelHex => write_intel_hex(&records(), Addressing::Word).into_bytes(),
+            ImageFormat::SRecord => write_srecords(&records(), Addressing::Word).into_bytes(),
+            ImageFormat::Obj => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+            ImageFormat::Hex => words
+                .iter()
+                .flat_map(|word| format!("{:04X}\n", word).into_bytes())
+                .collect(),
+            ImageFormat::Bin => words
+                .iter()
+                .flat_map(|word| format!("{:016b}\n", word).into_bytes())
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Obj => "obj",
+            ImageFormat::Hex => "hex",
+            ImageFormat::Bin => "bin",
+            ImageFormat::IntelHex => "ihex",
+            ImageFormat::SRecord => "srec",
+        })
+    }
+}
+
+/// Whether problems with an image fail the load or are only reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Load what can be loaded and return the problems as warnings.
+    #[default]
+    Warn,
+    /// Refuse an image with any problem, leaving memory untouched.
+    Error,
+}
+
+/// Something wrong with an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageProblem {
+    /// Shorter than the two bytes of an origin. Always an error.
+    Empty { bytes: usize },
+    /// A line of a text image that is not a word. Always an error.
+    BadLine {
+        line: usize,
+        format: ImageFormat,
+        found: String,
+    },
+    /// A malformed Intel HEX or S-record file. Always an error.
+    BadRecord(RecordError),
+    /// An origin but nothing to load there.
+    NoWords { origin: u16 },
+    /// An odd number of bytes; the last one is ignored.
+    OddLength { bytes: usize },
+    /// More words than fit between the origin and the end of the `size`
+    /// words of memory; the rest are dropped rather than wrapped around to
+    /// x0000.
+    PastEndOfMemory {
+        origin: u16,
+        words: usize,
+        size: usize,
+    },
+    /// Words `start..=end` overwrite part of an image loaded earlier.
+    Overlap { start: u16, end: u16, other: String },
+    /// An LC-3b image origin at an odd byte address. The image is loaded
+    /// from the even address below it.
+    OddOrigin { origin: u16 },
+}
+
+impl fmt::Display for ImageProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageProblem::Empty { bytes } => {
+                write!(f, "too short to hold an origin ({} bytes)", bytes)
+            }
+            ImageProblem::BadLine {
+                line,
+                format,
+                found,
+            } => {
+                let expected = match format {
+                    ImageFormat::Bin => "16 binary digits",
+                    _ => "4 hex digits",
+                };
+                write!(f, "line {}: expected {}, found {:?}", line, expected, found)
+            }
+            ImageProblem::BadRecord(e) => write!(f, "{}", e),
+            ImageProblem::NoWords { origin } => {
+                write!(f, "no words to load at origin x{:04X}", origin)
+            }
+            ImageProblem::OddLength { bytes } => {
+                write!(f, "odd length ({} bytes); the last byte is ignored", bytes)
+            }
+            ImageProblem::PastEndOfMemory {
+                origin,
+                words,
+                size,
+            } => {
+                let dropped = (*origin as usize + words - size).min(*words);
+                let end = match size.checked_sub(1) {
+                    Some(last) => format!("x{:04X}", last),
+                    None => "the end of an empty memory".to_string(),
+                };
+                write!(
+                    f,
+                    "{} words from x{:04X} run past {}; the last {} are dropped",
+                    words, origin, end, dropped
+                )
+            }
+            ImageProblem::Overlap { start, end, other } => {
+                write!(
+                    f,
+                    "x{:04X}-x{:04X} overwrites part of {}",
+                    start, end, other
+                )
+            }
+            ImageProblem::OddOrigin { origin } => {
+                write!(
+                    f,
+                    "origin x{:04X} is an odd LC-3b address; loading at x{:04X}",
+                    origin,
+                    origin & !1
+                )
+            }
+        }
+    }
+}
+
+/// Why an image could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Invalid(ImageProblem),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Invalid(problem) => write!(f, "{}", problem),
+        }
+    }
+}
+
+impl From<ImageProblem> for LoadError {
+    fn from(problem: ImageProblem) -> Self {
+        LoadError::Invalid(problem)
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(e) => e,
+            LoadError::Invalid(problem) => {
+                io::Error::new(io::ErrorKind::InvalidData, problem.to_string())
+            }
+        }
+    }
+}
+
+/// A successfully loaded image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    /// Where the image starts. For Intel HEX and S-records this is the
+    /// entry point the file names, else the start of its first segment.
+    /// LC-3b origins are byte addresses.
+    pub origin: u16,
+    /// Words placed in memory.
+    pub words: usize,
+    /// Problems tolerated under `Strictness::Warn`.
+    pub warnings: Vec<ImageProblem>,
+}
+
+/// Loads images into a `VM`, remembering where each went so later images
+/// that overlap it are reported.
+#[derive(Debug, Clone, Default)]
+pub struct ImageLoader {
+    pub strictness: Strictness,
+    /// What Intel HEX and S-record addresses count.
+    pub addressing: Addressing,
+    /// Name and address range of each segment loaded so far.
+    loaded: Vec<(String, Range<usize>)>,
+}
+
+impl ImageLoader {
+    pub fn new(strictness: Strictness) -> Self {
+        Self {
+            strictness,
+            ..Self::default()
+        }
+    }
+
+    /// The words of memory each segment loaded so far went to.
+    pub fn ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.loaded.iter().map(|(_, range)| range.clone())
+    }
+
+    /// Loads the image at `path` in the format `ImageFormat::detect` finds.
+    pub fn load(&mut self, vm: &mut VM, path: impl AsRef<Path>) -> Result<LoadedImage, LoadError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let format = ImageFormat::detect(path, &bytes);
+        self.load_format(vm, &path.display().to_string(), &bytes, format)
+    }
+
+    /// Checks and loads the `.obj` image `bytes`. `name` identifies it in
+    /// overlap reports.
+    pub fn load_bytes(
+        &mut self,
+        vm: &mut VM,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<LoadedImage, LoadError> {
+        self.load_format(vm, name, bytes, ImageFormat::Obj)
+    }
+
+    /// Checks and loads the image `bytes` written in `format`. Intel HEX and
+    /// S-record addresses are read with the loader's `addressing`.
+    pub fn load_format(
+        &mut self,
+        vm: &mut VM,
+        name: &str,
+        bytes: &[u8],
+        format: ImageFormat,
+    ) -> Result<LoadedImage, LoadError> {
+        let mut records = format.read(bytes, self.addressing)?;
+        let first = records.segments.first().map(|segment| segment.start);
+        let mut origin = records.entry.or(first).unwrap_or(0);
+
+        let mut problems = Vec::new();
+        if vm.isa == Isa::Lc3b {
+            if matches!(format, ImageFormat::IntelHex | ImageFormat::SRecord) {
+                origin = origin.wrapping_mul(2);
+            } else {
+                if origin & 1 != 0 {
+                    problems.push(ImageProblem::OddOrigin { origin });
+                    origin &= !1;
+                }
+                for segment in &mut records.segments {
+                    segment.start = lc3b::word_address(segment.start);
+                }
+            }
+        }
+        if records.segments.is_empty() {
+            problems.push(ImageProblem::NoWords { origin });
+        }
+        if format == ImageFormat::Obj && !bytes.len().is_multiple_of(2) {
+            problems.push(ImageProblem::OddLength { bytes: bytes.len() });
+        }
+        let mut ranges = Vec::new();
+        let size = vm.memory.size().min(ADDRESS_SPACE);
+        for segment in &records.segments {
+            let (start, words) = (segment.start as usize, segment.words.len());
+            let fits = words.min(size.saturating_sub(start));
+            if fits < words {
+                problems.push(ImageProblem::PastEndOfMemory {
+                    origin: segment.start,
+                    words,
+                    size,
+                });
+            }
+            let range = start..start + fits;
+            for (other, earlier) in &self.loaded {
+                let start = range.start.max(earlier.start);
+                let end = range.end.min(earlier.end);
+                if start < end {
+                    problems.push(ImageProblem::Overlap {
+                        start: start as u16,
+                        end: (end - 1) as u16,
+                        other: other.clone(),
+                    });
+                }
+            }
+            ranges.push(range);
+        }
+
+        if self.strictness == Strictness::Error && !problems.is_empty() {
+            return Err(LoadError::Invalid(problems.remove(0)));
+        }
+        let mut placed = 0;
+        for (segment, range) in records.segments.iter().zip(ranges) {
+            placed += range.len();
+            vm.memory
+                .load(range.start, &segment.words[..range.len()])
+                .expect("segments are trimmed to fit memory");
+            if let Some(checker) = vm.init_checker.as_mut() {
+                checker.initialize(range.clone());
+            }
+            self.loaded.push((name.to_string(), range));
+        }
+        Ok(LoadedImage {
+            origin,
+            words: placed,
+            warnings: problems,
+        })
+    }
+}
@@ -0,0 +1,111 @@
+//! Detection of programs stuck in an infinite loop.
+//!
+//! Between memory writes and keyboard input the LC-3 is deterministic: its
+//! next state depends only on the registers. If the register file (including
+//! PC and COND) repeats with no write or input in between, the program will
+//! repeat the same instructions forever. Repeats are found with Brent's cycle
+//! detection, which compares each state against one saved state, so the cost
+//! per instruction is constant.
+//!
+//! Polling KBSR is never reported: every read of KBSR updates the device
+//! registers, which counts as a memory write and starts detection afresh.
+
+use crate::disasm::disassemble_in;
+use crate::run::{Isa, Registers};
+use crate::symbols::SymbolTable;
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// Number of recent PCs kept to show the body of a detected loop.
+const RECENT: usize = 256;
+
+type State = [u16; Registers::R_COUNT as usize];
+
+/// Cycle detector over machine states. Install one in `VM::loop_detector`
+/// before running; a detected loop stops the VM with `Fault::InfiniteLoop`.
+#[derive(Debug, Clone)]
+pub struct LoopDetector {
+    saved: Option<State>,
+    /// Steps between saving states; doubles each time a state is saved.
+    power: u64,
+    /// Steps since `saved` was taken.
+    distance: u64,
+    recent: VecDeque<u16>,
+    /// Instructions of the detected loop, in execution order.
+    body: Vec<u16>,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoopDetector {
+    pub fn new() -> Self {
+        Self {
+            saved: None,
+            power: 1,
+            distance: 1,
+            recent: VecDeque::with_capacity(RECENT),
+            body: Vec::new(),
+        }
+    }
+
+    /// Forgets states seen so far, after memory changed or input was read.
+    pub(crate) fn reset(&mut self) {
+        self.saved = None;
+        self.power = 1;
+        self.distance = 1;
+        self.recent.clear();
+    }
+
+    /// Checks the state before an instruction runs. Returns the length of
+    /// the loop when the state has been seen since the last reset.
+    pub(crate) fn observe(&mut self, state: &State) -> Option<u64> {
+        if self.saved.as_ref() == Some(state) {
+            let start = self.recent.len().saturating_sub(self.distance as usize);
+            self.body = self.recent.range(start..).copied().collect();
+            return Some(self.distance);
+        }
+        if self.distance == self.power {
+            self.saved = Some(*state);
+            self.power *= 2;
+            self.distance = 0;
+        }
+        self.distance += 1;
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(state[Registers::R_PC as usize]);
+        None
+    }
+
+    /// Addresses of the last loop detected, in execution order. Holds at
+    /// most the last 256 instructions of a longer loop.
+    pub fn body(&self) -> &[u16] {
+        &self.body
+    }
+
+    /// Disassembles the last loop detected, one line per distinct address
+    /// in address order, as `isa` instructions.
+    pub fn report(&self, isa: Isa, memory: &[u16], symbols: &SymbolTable) -> String {
+        let mut addresses = self.body.clone();
+        addresses.sort_unstable();
+        addresses.dedup();
+        let mut out = String::new();
+        writeln!(out, "Loop body:").unwrap();
+        for address in addresses {
+            writeln!(
+                out,
+                "  x{:04X}  {:<16} {}",
+                address,
+                symbols.label_at(address).unwrap_or(""),
+                disassemble_in(isa, address, memory, symbols)
+            )
+            .unwrap();
+        }
+        out
+    }
+}
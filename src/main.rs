@@ -1,23 +1,7 @@
+use std::env;
 
-pub mod input_buffering;
-pub mod run;    
-
-
+use virtual_vm::cli;
 
 fn main() {
-    // Initialize the VM
-    let mut vm = run::VM::new();
-
-    // // Load a program into memory
-    // let program: [u16; 5] = [
-    //     0x1234, // Example instruction
-    //     0x5678, // Example instruction
-    //     0x9ABC, // Example instruction
-    //     0xDEF0, // Example instruction
-    //     0x0000, // End of program
-    // ];
-    // vm.load_program(&program);
-
-    // Run the program
-    vm.run();
+    std::process::exit(cli::main(env::args().skip(1).collect()));
 }
@@ -0,0 +1,285 @@
+//! The memory behind a `VM`.
+//!
+//! `VM::memory` is any `Memory`: by default a `FlatMemory` covering the
+//! whole 64K address space, but a smaller one suits embedded demos, and a
+//! `SparseMemory` only stores the pages that have been written. Reads and
+//! writes outside memory return a `MemoryError`, which the VM reports as
+//! `Fault::MemoryOutOfRange` rather than panicking.
+//!
+//! The keyboard registers at xFE00 and xFE02 are words of memory, so a
+//! memory too small to hold them has no keyboard registers. Programs on it
+//! can still read and write characters with the TRAP routines.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Index, IndexMut, Range};
+
+/// Words an LC-3 address can reach.
+pub const ADDRESS_SPACE: usize = 1 << 16;
+
+/// Words per page of a `SparseMemory`.
+const PAGE_SIZE: usize = 256;
+
+/// What unwritten words of a `SparseMemory` read as.
+static ZERO: u16 = 0;
+
+/// An access outside memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryError {
+    pub address: usize,
+    /// Words of memory there are.
+    pub size: usize,
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X} is outside memory (x{:04X} words)",
+            self.address, self.size
+        )
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// Words of memory at addresses from x0000 up to `size`.
+pub trait Memory: fmt::Debug + Send {
+    /// Words of memory there are.
+    fn size(&self) -> usize;
+
+    /// Bits kept of each word written; higher bits are dropped.
+    fn width(&self) -> u32 {
+        16
+    }
+
+    /// The word at `address`, or `None` outside memory.
+    fn get(&self, address: usize) -> Option<&u16>;
+
+    /// The word at `address` for changing, or `None` outside memory.
+    fn get_mut(&mut self, address: usize) -> Option<&mut u16>;
+
+    /// Every word in order, when they are stored that way.
+    fn as_slice(&self) -> Option<&[u16]> {
+        None
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u16]> {
+        None
+    }
+
+    fn clone_box(&self) -> Box<dyn Memory>;
+
+    fn read(&self, address: usize) -> Result<u16, MemoryError> {
+        let size = self.size();
+        self.get(address)
+            .copied()
+            .ok_or(MemoryError { address, size })
+    }
+
+    /// Writes `value`, dropping the bits above `width`.
+    fn write(&mut self, address: usize, value: u16) -> Result<(), MemoryError> {
+        let (size, mask) = (self.size(), u16::MAX >> (16 - self.width()));
+        let word = self.get_mut(address).ok_or(MemoryError { address, size })?;
+        *word = value & mask;
+        Ok(())
+    }
+
+    /// Writes `words` from `start` on. Nothing is written unless they all
+    /// fit.
+    fn load(&mut self, start: usize, words: &[u16]) -> Result<(), MemoryError> {
+        let end = start + words.len();
+        if end > self.size() {
+            return Err(MemoryError {
+                address: end - 1,
+                size: self.size(),
+            });
+        }
+        for (address, &word) in (start..).zip(words) {
+            self.write(address, word)?;
+        }
+        Ok(())
+    }
+
+    /// The words at every LC-3 address, reading those outside memory as
+    /// zero. Borrowed when memory is a flat 64K array.
+    fn contents(&self) -> Cow<'_, [u16]> {
+        match self.as_slice() {
+            Some(words) if words.len() == ADDRESS_SPACE => Cow::Borrowed(words),
+            _ => Cow::Owned(
+                (0..ADDRESS_SPACE)
+                    .map(|address| self.get(address).copied().unwrap_or(0))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Clone for Box<dyn Memory> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl Index<usize> for dyn Memory {
+    type Output = u16;
+
+    /// Panics outside memory; `read` returns an error instead.
+    fn index(&self, address: usize) -> &u16 {
+        let size = self.size();
+        self.get(address)
+            .unwrap_or_else(|| panic!("{}", MemoryError { address, size }))
+    }
+}
+
+impl IndexMut<usize> for dyn Memory {
+    /// Panics outside memory; `write` returns an error instead.
+    fn index_mut(&mut self, address: usize) -> &mut u16 {
+        let size = self.size();
+        self.get_mut(address)
+            .unwrap_or_else(|| panic!("{}", MemoryError { address, size }))
+    }
+}
+
+impl Index<Range<usize>> for dyn Memory {
+    type Output = [u16];
+
+    /// Panics unless memory is stored in order and holds all of `range`.
+    fn index(&self, range: Range<usize>) -> &[u16] {
+        &self.as_slice().expect("memory is not a flat array")[range]
+    }
+}
+
+impl IndexMut<Range<usize>> for dyn Memory {
+    fn index_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        &mut self.as_mut_slice().expect("memory is not a flat array")[range]
+    }
+}
+
+/// Memory stored as one array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatMemory {
+    words: Vec<u16>,
+    width: u32,
+}
+
+impl FlatMemory {
+    /// `size` zeroed words.
+    pub fn new(size: usize) -> Self {
+        Self {
+            words: vec![0; size],
+            width: 16,
+        }
+    }
+
+    /// Keeps only the low `width` bits of each word written.
+    pub fn with_width(self, width: u32) -> Self {
+        assert!((1..=16).contains(&width), "bad word width {}", width);
+        Self { width, ..self }
+    }
+}
+
+impl Default for FlatMemory {
+    /// The whole address space.
+    fn default() -> Self {
+        Self::new(ADDRESS_SPACE)
+    }
+}
+
+impl Memory for FlatMemory {
+    fn size(&self) -> usize {
+        self.words.len()
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn get(&self, address: usize) -> Option<&u16> {
+        self.words.get(address)
+    }
+
+    fn get_mut(&mut self, address: usize) -> Option<&mut u16> {
+        self.words.get_mut(address)
+    }
+
+    fn as_slice(&self) -> Option<&[u16]> {
+        Some(&self.words)
+    }
+
+    fn as_mut_slice(&mut self) -> Option<&mut [u16]> {
+        Some(&mut self.words)
+    }
+
+    fn clone_box(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+}
+
+/// Memory stored a page at a time, so only pages that have been written
+/// take space. Unwritten words read as zero. The VM addresses only the
+/// first `ADDRESS_SPACE` words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseMemory {
+    pages: BTreeMap<usize, Box<[u16; PAGE_SIZE]>>,
+    size: usize,
+    width: u32,
+}
+
+impl SparseMemory {
+    /// `size` words, none of them stored yet.
+    pub fn new(size: usize) -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            size,
+            width: 16,
+        }
+    }
+
+    /// Keeps only the low `width` bits of each word written.
+    pub fn with_width(self, width: u32) -> Self {
+        assert!((1..=16).contains(&width), "bad word width {}", width);
+        Self { width, ..self }
+    }
+
+    /// Words stored so far, a whole page for each page written.
+    pub fn stored(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+}
+
+impl Memory for SparseMemory {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn get(&self, address: usize) -> Option<&u16> {
+        if address >= self.size {
+            return None;
+        }
+        Some(match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => &page[address % PAGE_SIZE],
+            None => &ZERO,
+        })
+    }
+
+    fn get_mut(&mut self, address: usize) -> Option<&mut u16> {
+        if address >= self.size {
+            return None;
+        }
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        Some(&mut page[address % PAGE_SIZE])
+    }
+
+    fn clone_box(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,217 @@
+use crate::disasm::{self, disassemble_in};
+use crate::lc3b;
+use crate::run::Isa;
+use crate::symbols::SymbolTable;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Per-subroutine totals. Time is measured in executed instructions, so
+/// profiles are deterministic and do not depend on the host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// Instructions executed between the call and its RET, including callees.
+    /// Recursive calls are only counted once, at the outermost level.
+    pub inclusive: u64,
+    /// Instructions executed in the subroutine itself, excluding callees.
+    pub exclusive: u64,
+}
+
+/// A subroutine call that has not returned yet.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u16,
+    /// Profiled instruction total when the call was made.
+    start: u64,
+}
+
+/// Execution counts collected while the VM runs. Install one in
+/// `VM::profiler` before running and call `report` once the program stops.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    address_counts: Vec<u64>,
+    opcode_counts: [u64; 16],
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    stack: Vec<Frame>,
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            address_counts: vec![0; 1 << 16],
+            opcode_counts: [0; 16],
+            subroutines: BTreeMap::new(),
+            stack: Vec::new(),
+            total: 0,
+        }
+    }
+
+    /// Total instructions profiled.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn count_at(&self, address: u16) -> u64 {
+        self.address_counts[address as usize]
+    }
+
+    /// Executions of each opcode, indexed by `instr >> 12`.
+    pub fn opcode_counts(&self) -> &[u64; 16] {
+        &self.opcode_counts
+    }
+
+    /// Addresses that were executed, with their counts, most executed first.
+    pub fn hot_spots(&self) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = self
+            .address_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(address, &count)| (address as u16, count))
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    /// Subroutine totals keyed by entry address. Calls that have not
+    /// returned yet are included up to the last profiled instruction.
+    pub fn subroutines(&self) -> BTreeMap<u16, SubroutineStats> {
+        let mut subroutines = self.subroutines.clone();
+        for (depth, frame) in self.stack.iter().enumerate() {
+            if self.is_outermost(depth) {
+                subroutines.entry(frame.entry).or_default().inclusive += self.total - frame.start;
+            }
+        }
+        subroutines
+    }
+
+    /// Records an instruction that completed, leaving `next_pc` behind.
+    pub(crate) fn record(&mut self, pc: u16, instr: u16, next_pc: u16) {
+        self.total += 1;
+        self.address_counts[pc as usize] += 1;
+        self.opcode_counts[(instr >> 12) as usize] += 1;
+        if let Some(frame) = self.stack.last() {
+            self.subroutines.entry(frame.entry).or_default().exclusive += 1;
+        }
+
+        match instr >> 12 {
+            // JSR / JSRR
+            0x4 => {
+                self.subroutines.entry(next_pc).or_default().calls += 1;
+                self.stack.push(Frame {
+                    entry: next_pc,
+                    start: self.total,
+                });
+            }
+            // RET
+            0xC if (instr >> 6) & 0x7 == 7 => {
+                let depth = self.stack.len().wrapping_sub(1);
+                if let Some(frame) = self.stack.last().copied() {
+                    if self.is_outermost(depth) {
+                        self.subroutines.entry(frame.entry).or_default().inclusive +=
+                            self.total - frame.start;
+                    }
+                    self.stack.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// True when the frame at `depth` is the only active call of its subroutine
+    /// below it on the stack.
+    fn is_outermost(&self, depth: usize) -> bool {
+        let entry = self.stack[depth].entry;
+        !self.stack[..depth].iter().any(|frame| frame.entry == entry)
+    }
+
+    /// Formats the profile as a text report: the `top` most executed
+    /// addresses with their labels and disassembly, then per-opcode and
+    /// per-subroutine totals. `memory` is used to disassemble the hot spots
+    /// as `isa` instructions.
+    pub fn report(&self, isa: Isa, memory: &[u16], symbols: &SymbolTable, top: usize) -> String {
+        let mut out = String::new();
+        let total = self.total.max(1) as f64;
+        let percent = |count: u64| 100.0 * count as f64 / total;
+        let label = |address: u16| symbols.label_at(address).unwrap_or("");
+
+        writeln!(out, "Profile: {} instructions", self.total).unwrap();
+
+        writeln!(out, "\nHot spots:").unwrap();
+        writeln!(
+            out,
+            "{:>12} {:>7}  address  {:<16} instruction",
+            "count", "%", "label"
+        )
+        .unwrap();
+        for (address, count) in self.hot_spots().into_iter().take(top) {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}%  x{:04X}    {:<16} {}",
+                count,
+                percent(count),
+                address,
+                label(address),
+                disassemble_in(isa, address, memory, symbols)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nOpcodes:").unwrap();
+        writeln!(out, "{:>12} {:>7}  opcode", "count", "%").unwrap();
+        let mut opcodes: Vec<(usize, u64)> = self
+            .opcode_counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let names = match isa {
+            Isa::Lc3 => disasm::OPCODE_NAMES,
+            Isa::Lc3b => lc3b::OPCODE_NAMES,
+        };
+        for (opcode, count) in opcodes {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}%  {}",
+                count,
+                percent(count),
+                names[opcode]
+            )
+            .unwrap();
+        }
+
+        let mut subroutines: Vec<(u16, SubroutineStats)> = self.subroutines().into_iter().collect();
+        if !subroutines.is_empty() {
+            subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+            writeln!(out, "\nSubroutines:").unwrap();
+            writeln!(
+                out,
+                "{:>8} {:>12} {:>12}  subroutine",
+                "calls", "inclusive", "self"
+            )
+            .unwrap();
+            for (entry, stats) in subroutines {
+                writeln!(
+                    out,
+                    "{:>8} {:>12} {:>12}  {}",
+                    stats.calls,
+                    stats.inclusive,
+                    stats.exclusive,
+                    symbols.describe(entry)
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,395 @@
+//! Intel HEX and Motorola S-record files, as used to initialise memories
+//! when the LC-3 is built on an FPGA.
+//!
+//! Both formats are lines of hex bytes with an address, data and a
+//! checksum. Files may hold several segments, and may name an entry point.
+//! The LC-3 addresses 16-bit words, so by default a record's address counts
+//! words and each word is two data bytes, high byte first. With
+//! `Addressing::Byte` addresses count bytes, as they do for byte-wide
+//! memories, and word `w` is bytes `2w` and `2w + 1`.
+//!
+//! ```text
+//! :0400000030010000CB       word x0000: 3001 0000
+//! :00000001FF               end of file
+//! S107000030010000C7
+//! S9030000FC
+//! ```
+
+use crate::dump::Dump;
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+/// Data bytes per record written.
+const BYTES_PER_RECORD: usize = 16;
+
+/// What a record's address counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Addressing {
+    /// 16-bit words, so the address is an LC-3 address.
+    #[default]
+    Word,
+    /// Bytes, so an LC-3 address is doubled.
+    Byte,
+}
+
+impl Addressing {
+    fn scale(self) -> u32 {
+        match self {
+            Addressing::Word => 1,
+            Addressing::Byte => 2,
+        }
+    }
+}
+
+/// The segments of memory a file holds, in address order, and the entry
+/// point it names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Records {
+    pub segments: Vec<Dump>,
+    pub entry: Option<u16>,
+}
+
+/// Why a file could not be read. `line` is `None` for problems with the
+/// file as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+/// Collects data bytes by their offset in a big-endian image of memory.
+struct Collector {
+    addressing: Addressing,
+    bytes: BTreeMap<u32, u8>,
+}
+
+impl Collector {
+    fn new(addressing: Addressing) -> Self {
+        Self {
+            addressing,
+            bytes: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `data` at the record address `address`.
+    fn add(&mut self, address: u32, data: &[u8]) -> Result<(), String> {
+        let start = match self.addressing {
+            Addressing::Word => address.checked_mul(2),
+            Addressing::Byte => Some(address),
+        };
+        for (offset, &byte) in data.iter().enumerate() {
+            let at = start
+                .and_then(|start| start.checked_add(offset as u32))
+                .filter(|&at| at < 1 << 17)
+                .ok_or(format!("address x{:X} is past xFFFF", address))?;
+            match self.bytes.entry(at) {
+                Entry::Occupied(_) => {
+                    return Err(format!("word x{:04X} is written twice", at / 2));
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(byte);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The entry point for the record address `address`.
+    fn entry(&self, address: u32) -> Result<u16, String> {
+        let scale = self.addressing.scale();
+        address
+            .is_multiple_of(scale)
+            .then(|| address / scale)
+            .and_then(|address| u16::try_from(address).ok())
+            .ok_or(format!("entry point x{:X} is not an LC-3 address", address))
+    }
+
+    /// Pairs the bytes into words and splits them into runs of consecutive
+    /// addresses.
+    fn finish(self, entry: Option<u16>) -> Result<Records, RecordError> {
+        let mut segments: Vec<Dump> = Vec::new();
+        let mut bytes = self.bytes.into_iter().peekable();
+        while let Some((at, high)) = bytes.next() {
+            let address = (at / 2) as u16;
+            let low = match bytes.next_if(|&(next, _)| at.is_multiple_of(2) && next == at + 1) {
+                Some((_, low)) => low,
+                None => {
+                    return Err(RecordError {
+                        line: None,
+                        message: format!("word x{:04X} has only one of its two bytes", address),
+                    })
+                }
+            };
+            let word = u16::from_be_bytes([high, low]);
+            match segments.last_mut() {
+                Some(segment)
+                    if segment.start as usize + segment.words.len() == address as usize =>
+                {
+                    segment.words.push(word)
+                }
+                _ => segments.push(Dump {
+                    start: address,
+                    words: vec![word],
+                }),
+            }
+        }
+        Ok(Records { segments, entry })
+    }
+}
+
+/// The bytes of a record after its start code, checking them against the
+/// count in the first byte. `count_excludes` is how many bytes the count
+/// leaves out.
+fn record_bytes(hex: &str, count_excludes: usize) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("expected pairs of hex digits".to_string());
+    }
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+        .collect();
+    let Some(&count) = bytes.first() else {
+        return Err("empty record".to_string());
+    };
+    if bytes.len() != count as usize + count_excludes {
+        return Err(format!(
+            "record says it has {} bytes, found {}",
+            count,
+            bytes.len() - count_excludes
+        ));
+    }
+    Ok(bytes)
+}
+
+fn checksum_error(found: u8, expected: u8) -> String {
+    format!("checksum is x{:02X}, expected x{:02X}", found, expected)
+}
+
+/// Reads an Intel HEX file. Data (00), end of file (01), extended segment
+/// and linear address (02, 04) and start address (03, 05) records are
+/// understood.
+pub fn read_intel_hex(text: &str, addressing: Addressing) -> Result<Records, RecordError> {
+    let mut collector = Collector::new(addressing);
+    let (mut base, mut entry, mut ended) = (0u32, None, false);
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| RecordError {
+            line: Some(number + 1),
+            message,
+        };
+        if ended {
+            return Err(error("record after the end of file record".to_string()));
+        }
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("expected a record starting with ':'".to_string()))?;
+        // Count, two address bytes, type, data and checksum
+        let bytes = record_bytes(hex, 5).map_err(error)?;
+        let (&checksum, body) = bytes.split_last().unwrap();
+        let expected = body
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+            .wrapping_neg();
+        if checksum != expected {
+            return Err(error(checksum_error(checksum, expected)));
+        }
+        let address = u16::from_be_bytes([body[1], body[2]]) as u32;
+        let data = &body[4..];
+        let value = || {
+            data.iter()
+                .fold(0u32, |value, &byte| value << 8 | byte as u32)
+        };
+        let sized = |size: usize| {
+            (data.len() == size)
+                .then_some(())
+                .ok_or_else(|| error(format!("expected {} data bytes", size)))
+        };
+        match body[3] {
+            0x00 => collector.add(base + address, data).map_err(error)?,
+            0x01 => ended = true,
+            0x02 => {
+                sized(2)?;
+                base = value() << 4;
+            }
+            0x04 => {
+                sized(2)?;
+                base = value() << 16;
+            }
+            0x03 => {
+                sized(4)?;
+                let (segment, offset) = (value() >> 16, value() & 0xFFFF);
+                entry = Some(collector.entry(segment * 16 + offset).map_err(error)?);
+            }
+            0x05 => {
+                sized(4)?;
+                entry = Some(collector.entry(value()).map_err(error)?);
+            }
+            kind => return Err(error(format!("unknown record type {:02X}", kind))),
+        }
+    }
+    if !ended {
+        return Err(RecordError {
+            line: None,
+            message: "no end of file record".to_string(),
+        });
+    }
+    collector.finish(entry)
+}
+
+/// Reads a Motorola S-record file. S1-S3 data records are loaded, S5 and S6
+/// counts are checked, S7-S9 give the entry point, and S0 is ignored.
+pub fn read_srecords(text: &str, addressing: Addressing) -> Result<Records, RecordError> {
+    let mut collector = Collector::new(addressing);
+    let (mut entry, mut data_records) = (None, 0u32);
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| RecordError {
+            line: Some(number + 1),
+            message,
+        };
+        let kind = line
+            .strip_prefix('S')
+            .and_then(|rest| rest.chars().next())
+            .filter(char::is_ascii_digit)
+            .ok_or_else(|| error("expected a record starting with S0-S9".to_string()))?;
+        let address_bytes = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(error(format!("unknown record type S{}", kind))),
+        };
+        // The count covers the address, data and checksum
+        let bytes = record_bytes(&line[2..], 1).map_err(error)?;
+        if bytes.len() < 2 + address_bytes {
+            return Err(error("record too short for its address".to_string()));
+        }
+        let (&checksum, body) = bytes.split_last().unwrap();
+        let expected = !body.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != expected {
+            return Err(error(checksum_error(checksum, expected)));
+        }
+        let address = body[1..=address_bytes]
+            .iter()
+            .fold(0u32, |value, &byte| value << 8 | byte as u32);
+        let data = &body[1 + address_bytes..];
+        match kind {
+            '1'..='3' => {
+                collector.add(address, data).map_err(error)?;
+                data_records += 1;
+            }
+            '5' | '6' if address != data_records => {
+                return Err(error(format!(
+                    "count record says {} data records, found {}",
+                    address, data_records
+                )))
+            }
+            '7'..='9' => entry = Some(collector.entry(address).map_err(error)?),
+            _ => {}
+        }
+    }
+    collector.finish(entry)
+}
+
+/// Splits each segment into data records of up to sixteen bytes, aligned
+/// so that none crosses a 64K boundary. Yields the record address and data.
+fn chunks(records: &Records, addressing: Addressing) -> Vec<(u32, Vec<u8>)> {
+    let words_per_record = BYTES_PER_RECORD / 2;
+    let mut chunks = Vec::new();
+    for segment in &records.segments {
+        let mut address = segment.start as usize;
+        let end = address + segment.words.len();
+        while address < end {
+            let next = ((address / words_per_record + 1) * words_per_record).min(end);
+            let offset = address - segment.start as usize;
+            let data = segment.words[offset..offset + next - address]
+                .iter()
+                .flat_map(|word| word.to_be_bytes())
+                .collect();
+            chunks.push((address as u32 * addressing.scale(), data));
+            address = next;
+        }
+    }
+    chunks
+}
+
+fn hex_line(out: &mut String, start: &str, bytes: &[u8]) {
+    out.push_str(start);
+    for byte in bytes {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+/// Writes `records` as Intel HEX, sixteen data bytes per record.
+pub fn write_intel_hex(records: &Records, addressing: Addressing) -> String {
+    let mut out = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend(address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        bytes.push(sum.wrapping_neg());
+        hex_line(&mut out, ":", &bytes);
+    };
+    let mut base = 0;
+    for (address, data) in chunks(records, addressing) {
+        if address >> 16 != base {
+            base = address >> 16;
+            record(0x04, 0, &(base as u16).to_be_bytes());
+        }
+        record(0x00, address as u16, &data);
+    }
+    if let Some(entry) = records.entry {
+        record(0x05, 0, &(entry as u32 * addressing.scale()).to_be_bytes());
+    }
+    record(0x01, 0, &[]);
+    out
+}
+
+/// Writes `records` as S-records: an S0 header, S1 data records (S2 when
+/// byte addresses pass xFFFF), an S5 count and an S9 (or S8) entry point,
+/// which is x0000 when there is none.
+pub fn write_srecords(records: &Records, addressing: Addressing) -> String {
+    let mut out = String::new();
+    let mut record = |kind: u8, address: u32, address_bytes: usize, data: &[u8]| {
+        let mut bytes = vec![(address_bytes + data.len() + 1) as u8];
+        bytes.extend_from_slice(&address.to_be_bytes()[4 - address_bytes..]);
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        bytes.push(!sum);
+        hex_line(&mut out, &format!("S{}", kind), &bytes);
+    };
+    let chunks = chunks(records, addressing);
+    let wide = chunks.iter().any(|&(address, _)| address > 0xFFFF);
+    let (data_kind, end_kind, address_bytes) = if wide { (2, 8, 3) } else { (1, 9, 2) };
+    record(0, 0, 2, &[]);
+    for (address, data) in &chunks {
+        record(data_kind, *address, address_bytes, data);
+    }
+    if chunks.len() <= 0xFFFF {
+        record(5, chunks.len() as u32, 2, &[]);
+    }
+    let entry = records.entry.unwrap_or(0) as u32 * addressing.scale();
+    record(end_kind, entry, address_bytes, &[]);
+    out
+}
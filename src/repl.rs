@@ -0,0 +1,271 @@
+//! Interactive command-line debugger on top of `Debugger`, for `lc3 debug`.
+
+use crate::debugger::{Debugger, StopReason};
+use crate::disasm::disassemble_with;
+use crate::dump::hex_dump;
+use crate::init::{parse_word, register_index};
+use crate::input_buffering::take_interrupt;
+use crate::run::Registers;
+use crate::symbols::SymbolTable;
+
+use std::io::{self, BufRead, Write};
+
+/// Instructions run between checks for Ctrl+C during a continue.
+const SLICE: u64 = 10_000;
+
+const HELP: &str = "\
+Commands:
+  break|b [address]      set a breakpoint, or list them
+  delete|d address       remove a breakpoint
+  continue|c             run to the next breakpoint, halt or fault
+  step|s [count]         execute instructions, entering subroutines
+  next|n                 execute one instruction, stepping over subroutines
+  finish|f               run until the current subroutine returns
+  back                   undo the last instruction
+  regs|r                 show the registers
+  set Rn value           change a register (R0-R7, PC, COND)
+  mem|x address [count]  show memory in hex and ASCII
+  list|l [address] [n]   disassemble n instructions (default: 8 from PC)
+  quit|q                 leave the debugger
+Addresses and values are LC-3 numbers (x3000, #12) or labels.
+";
+
+/// Reads commands from `input` until `quit` or end of input, writing
+/// everything but the program's own output to `output`.
+pub fn repl(
+    debugger: &mut Debugger,
+    symbols: &SymbolTable,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    write_location(debugger, symbols, &mut output)?;
+    let mut line = String::new();
+    loop {
+        write!(output, "(lc3) ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            continue;
+        };
+        if matches!(command, "quit" | "q") {
+            return Ok(());
+        }
+        if let Err(e) = execute(debugger, symbols, command, arguments, &mut output)? {
+            writeln!(output, "{}", e)?;
+        }
+    }
+}
+
+/// Runs one command. The inner error is a mistake in the command itself.
+fn execute(
+    debugger: &mut Debugger,
+    symbols: &SymbolTable,
+    command: &str,
+    arguments: &[&str],
+    output: &mut impl Write,
+) -> io::Result<Result<(), String>> {
+    let address = |token: Option<&&str>| match token {
+        Some(token) => parse_word(token, symbols).ok_or(format!("bad address {}", token)),
+        None => Err("an address is needed".to_string()),
+    };
+    let count = |token: Option<&&str>, default| match token {
+        Some(token) => token
+            .parse::<u16>()
+            .ok()
+            .filter(|&count| count > 0)
+            .ok_or(format!("bad count {}", token)),
+        None => Ok(default),
+    };
+
+    match command {
+        "help" | "h" => write!(output, "{}", HELP)?,
+        "break" | "b" if arguments.is_empty() => {
+            for address in debugger.breakpoints() {
+                writeln!(output, "  x{:04X}  {}", address, symbols.describe(address))?;
+            }
+        }
+        "break" | "b" => match address(arguments.first()) {
+            Ok(address) => {
+                debugger.add_breakpoint(address);
+                writeln!(output, "Breakpoint at x{:04X}", address)?;
+            }
+            Err(e) => return Ok(Err(e)),
+        },
+        "delete" | "d" => match address(arguments.first()) {
+            Ok(address) if debugger.remove_breakpoint(address) => {}
+            Ok(address) => return Ok(Err(format!("no breakpoint at x{:04X}", address))),
+            Err(e) => return Ok(Err(e)),
+        },
+        "continue" | "c" => {
+            debugger.continue_();
+            let reason = run_interruptibly(debugger);
+            report(debugger, symbols, reason, output)?;
+        }
+        "step" | "s" => {
+            let steps = match count(arguments.first(), 1) {
+                Ok(steps) => steps,
+                Err(e) => return Ok(Err(e)),
+            };
+            let mut reason = None;
+            for _ in 0..steps {
+                debugger.step_into();
+                reason = debugger.run_to_stop();
+                if reason != Some(StopReason::Step) {
+                    break;
+                }
+            }
+            report(debugger, symbols, reason, output)?;
+        }
+        "next" | "n" | "finish" | "f" => {
+            if matches!(command, "next" | "n") {
+                debugger.step_over();
+            } else {
+                debugger.step_out();
+            }
+            let reason = run_interruptibly(debugger);
+            report(debugger, symbols, reason, output)?;
+        }
+        "back" => {
+            let reason = debugger.step_back();
+            report(debugger, symbols, Some(reason), output)?;
+        }
+        "regs" | "r" => write_registers(debugger, output)?,
+        "set" => {
+            let setting = match arguments {
+                [name, value] => register_index(name).zip(parse_word(value, symbols)),
+                _ => None,
+            };
+            match setting {
+                Some((register, value)) => debugger.vm.registers_storage[register] = value,
+                None => return Ok(Err("usage: set Rn value".to_string())),
+            }
+        }
+        "mem" | "x" => {
+            let (start, words) = match (address(arguments.first()), count(arguments.get(1), 8)) {
+                (Ok(start), Ok(words)) => (start, words),
+                (Err(e), _) | (_, Err(e)) => return Ok(Err(e)),
+            };
+            let end = start.saturating_add(words - 1);
+            write!(
+                output,
+                "{}",
+                hex_dump(&debugger.vm.memory.contents(), start, end)
+            )?;
+        }
+        "list" | "l" => {
+            let start = match arguments.first() {
+                Some(_) => address(arguments.first()),
+                None => Ok(debugger.pc()),
+            };
+            let (start, lines) = match (start, count(arguments.get(1), 8)) {
+                (Ok(start), Ok(lines)) => (start, lines),
+                (Err(e), _) | (_, Err(e)) => return Ok(Err(e)),
+            };
+            for offset in 0..lines {
+                let address = start.wrapping_add(offset);
+                write_instruction(debugger, symbols, address, output)?;
+            }
+        }
+        _ => return Ok(Err(format!("unknown command {} (try help)", command))),
+    }
+    Ok(Ok(()))
+}
+
+/// Runs the current target in slices so Ctrl+C can pause it. Returns `None`
+/// when interrupted.
+fn run_interruptibly(debugger: &mut Debugger) -> Option<StopReason> {
+    loop {
+        if let Some(reason) = debugger.run(SLICE) {
+            return Some(reason);
+        }
+        if take_interrupt() {
+            debugger.interrupt();
+            return None;
+        }
+    }
+}
+
+fn report(
+    debugger: &Debugger,
+    symbols: &SymbolTable,
+    reason: Option<StopReason>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    match reason {
+        None => writeln!(output, "Interrupted")?,
+        Some(StopReason::Step) => {}
+        Some(StopReason::Breakpoint(address)) => {
+            writeln!(output, "Breakpoint at x{:04X}", address)?
+        }
+        Some(StopReason::Halted) => {
+            writeln!(
+                output,
+                "Program halted after {} instructions",
+                debugger.vm.instruction_count
+            )?;
+            return Ok(());
+        }
+        Some(StopReason::Fault(fault)) => writeln!(output, "{}", fault)?,
+        Some(StopReason::HistoryStart) => writeln!(output, "At the start of the history")?,
+    }
+    write_location(debugger, symbols, output)
+}
+
+/// Shows the instruction about to execute.
+fn write_location(
+    debugger: &Debugger,
+    symbols: &SymbolTable,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    write!(output, "=>")?;
+    write_instruction(debugger, symbols, debugger.pc(), output)
+}
+
+fn write_instruction(
+    debugger: &Debugger,
+    symbols: &SymbolTable,
+    address: u16,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let instr = debugger.vm.memory.read(address as usize).unwrap_or(0);
+    let marker = if debugger.breakpoints().any(|b| b == address) {
+        '*'
+    } else {
+        ' '
+    };
+    writeln!(
+        output,
+        "{}x{:04X}  {:<16} {}",
+        marker,
+        address,
+        symbols.label_at(address).unwrap_or(""),
+        disassemble_with(address, instr, symbols, debugger.vm.extension.as_deref())
+    )
+}
+
+fn write_registers(debugger: &Debugger, output: &mut impl Write) -> io::Result<()> {
+    let registers = &debugger.vm.registers_storage;
+    for row in 0..2 {
+        let line: Vec<String> = (row * 4..row * 4 + 4)
+            .map(|index| format!("R{} x{:04X}", index, registers[index]))
+            .collect();
+        writeln!(output, "{}", line.join("  "))?;
+    }
+    let cond = match registers[Registers::R_COND as usize] {
+        1 => "P",
+        2 => "Z",
+        _ => "N",
+    };
+    writeln!(
+        output,
+        "PC x{:04X}  COND {}  ({} instructions executed)",
+        registers[Registers::R_PC as usize],
+        cond,
+        debugger.vm.instruction_count
+    )
+}
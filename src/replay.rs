@@ -0,0 +1,231 @@
+//! Deterministic recording and replay of keyboard input.
+//!
+//! Every byte a program reads is logged with the instruction count at which
+//! it was consumed, as are KBSR polls that found no key waiting. Replaying
+//! the log hands the program the same input at the same instructions, so an
+//! interactive session reproduces exactly.
+//!
+//! The log is a text file with one event per line:
+//!
+//! ```text
+//! # LC-3 input log v1
+//! 1520 key 0x61
+//! 1600-1990 nokey 40
+//! ```
+//!
+//! `nokey` lines cover a run of consecutive empty polls: the first and last
+//! instruction count of the run and how many polls it held.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const HEADER: &str = "# LC-3 input log v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A byte was consumed at `count`.
+    Key { count: u64, byte: u8 },
+    /// `polls` KBSR reads between `first` and `last` found no key.
+    NoKey { first: u64, last: u64, polls: u64 },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub events: Vec<InputEvent>,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut log = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("line {}: cannot parse input event `{}`", index + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let event = match fields[..] {
+                [count, "key", byte] => InputEvent::Key {
+                    count: count.parse().map_err(|_| bad())?,
+                    byte: u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+                        .map_err(|_| bad())?,
+                },
+                [range, "nokey", polls] => {
+                    let (first, last) = range.split_once('-').ok_or_else(bad)?;
+                    InputEvent::NoKey {
+                        first: first.parse().map_err(|_| bad())?,
+                        last: last.parse().map_err(|_| bad())?,
+                        polls: polls.parse().map_err(|_| bad())?,
+                    }
+                }
+                _ => return Err(bad()),
+            };
+            log.events.push(event);
+        }
+        Ok(log)
+    }
+
+    pub fn push_key(&mut self, count: u64, byte: u8) {
+        self.events.push(InputEvent::Key { count, byte });
+    }
+
+    /// Logs an empty poll, extending the previous run of empty polls if there is one.
+    pub fn push_no_key(&mut self, count: u64) {
+        if let Some(InputEvent::NoKey { last, polls, .. }) = self.events.last_mut() {
+            *last = count;
+            *polls += 1;
+        } else {
+            self.events.push(InputEvent::NoKey {
+                first: count,
+                last: count,
+                polls: 1,
+            });
+        }
+    }
+}
+
+impl fmt::Display for InputLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for event in &self.events {
+            match *event {
+                InputEvent::Key { count, byte } => writeln!(f, "{} key 0x{:02x}", count, byte)?,
+                InputEvent::NoKey { first, last, polls } => {
+                    writeln!(f, "{}-{} nokey {}", first, last, polls)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the VM's keyboard input is being recorded, replayed, or neither.
+#[derive(Debug, Clone, Default)]
+pub enum InputTrace {
+    #[default]
+    Off,
+    Recording(InputLog),
+    Replaying {
+        log: InputLog,
+        /// Index of the next event to hand out.
+        next: usize,
+        /// Polls already consumed from the `NoKey` run at `next`.
+        polled: u64,
+        /// Set when the program asked for input the log cannot provide.
+        diverged: bool,
+    },
+}
+
+impl InputTrace {
+    pub fn replaying(log: InputLog) -> Self {
+        InputTrace::Replaying {
+            log,
+            next: 0,
+            polled: 0,
+            diverged: false,
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self, InputTrace::Replaying { .. })
+    }
+
+    /// The log being recorded or replayed.
+    pub fn log(&self) -> Option<&InputLog> {
+        match self {
+            InputTrace::Off => None,
+            InputTrace::Recording(log) | InputTrace::Replaying { log, .. } => Some(log),
+        }
+    }
+
+    /// Returns and clears the divergence flag.
+    pub(crate) fn take_divergence(&mut self) -> bool {
+        match self {
+            InputTrace::Replaying { diverged, .. } => std::mem::take(diverged),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn record_key(&mut self, count: u64, byte: u8) {
+        if let InputTrace::Recording(log) = self {
+            log.push_key(count, byte);
+        }
+    }
+
+    pub(crate) fn record_no_key(&mut self, count: u64) {
+        if let InputTrace::Recording(log) = self {
+            log.push_no_key(count);
+        }
+    }
+
+    /// Answers a KBSR poll at instruction `count` from the log.
+    pub(crate) fn replay_poll(&mut self, count: u64) -> Option<u8> {
+        let InputTrace::Replaying {
+            log,
+            next,
+            polled,
+            diverged,
+        } = self
+        else {
+            return None;
+        };
+        match log.events.get(*next) {
+            Some(&InputEvent::Key { count: at, byte }) if at == count => {
+                *next += 1;
+                Some(byte)
+            }
+            Some(&InputEvent::NoKey { first, last, polls })
+                if (first..=last).contains(&count) && *polled < polls =>
+            {
+                *polled += 1;
+                if *polled == polls {
+                    *next += 1;
+                    *polled = 0;
+                }
+                None
+            }
+            _ => {
+                *diverged = true;
+                None
+            }
+        }
+    }
+
+    /// Answers a blocking read at instruction `count` from the log.
+    pub(crate) fn replay_read(&mut self, count: u64) -> Option<u8> {
+        let InputTrace::Replaying {
+            log,
+            next,
+            diverged,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        match log.events.get(*next) {
+            Some(&InputEvent::Key { count: at, byte }) if at == count => {
+                *next += 1;
+                Some(byte)
+            }
+            _ => {
+                *diverged = true;
+                None
+            }
+        }
+    }
+}
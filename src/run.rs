@@ -1,11 +1,29 @@
-
+#![allow(non_camel_case_types)]
+
+use crate::blocks::{BlockCache, Engine};
+use crate::budget::{Budget, BudgetKind};
+use crate::callstack::{CallFrame, CallStack};
+use crate::console::Console;
+use crate::coverage::Coverage;
+use crate::decode::{decode, DecodeCache, DecodedInstr};
+use crate::extension::Extension;
+use crate::history::History;
+use crate::initcheck::{lc3_registers, lc3b_registers, InitChecker, InitMode, Uninitialized};
 use crate::input_buffering;
-
-use input_buffering::{check_key, restore_input_buffering, setup};
-use std::convert::TryFrom;
-use std::env::{self, args};
+#[cfg(feature = "jit")]
+use crate::jit::JitCache;
+use crate::lc3b;
+use crate::loader::{ImageFormat, ImageLoader};
+use crate::loops::LoopDetector;
+use crate::memory::{FlatMemory, Memory, MemoryError};
+use crate::profiler::Profiler;
+use crate::replay::InputTrace;
+use crate::stackcheck::StackChecker;
+use crate::trace::Tracer;
+
+use std::fmt;
 use std::fs::File;
-use std::io::{self, stdout, Read, Write};
+use std::io::{self, Read};
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u16)]
@@ -23,10 +41,8 @@ pub enum Registers {
     R_COUNT = 10,
 }
 
-const MR_KBSR: u16 = 0xFE00; // Keyboard Status Register
-const MR_KBDR: u16 = 0xFE02; // Keyboard Data Register
-
-const MEMORY_SIZE: usize = 1 << 16;
+pub(crate) const MR_KBSR: u16 = 0xFE00; // Keyboard Status Register
+pub(crate) const MR_KBDR: u16 = 0xFE02; // Keyboard Data Register
 
 //R_COND condition flags
 #[derive(Debug, Clone, Copy)]
@@ -35,29 +51,8 @@ enum R_COND {
     FL_ZRO = 1 << 1,
     FL_NEG = 1 << 2,
 }
-//16 bits each
-#[repr(u16)]
-enum Opcodes {
-    OP_BR = 0, /* branch */
-    OP_ADD,    /* add  */
-    OP_LD,     /* load */
-    OP_ST,     /* store */
-    OP_JSR,    /* jump register */
-    OP_AND,    /* bitwise and */
-    OP_LDR,    /* load register */
-    OP_STR,    /* store register */
-    OP_RTI,    /* unused */
-    OP_NOT,    /* bitwise not */
-    OP_LDI,    /* load indirect */
-    OP_STI,    /* store indirect */
-    OP_JMP,    /* jump */
-    OP_RES,    /* reserved (unused) */
-    OP_LEA,    /* load effective address */
-    OP_TRAP,   /* execute trap */
-}
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
-
 enum OP_TRAP {
     TRAP_GETC = 0x20,  /* get character from keyboard, not echoed onto the terminal */
     TRAP_OUT = 0x21,   /* output a character */
@@ -66,31 +61,6 @@ enum OP_TRAP {
     TRAP_PUTSP = 0x24, /* output a byte string */
     TRAP_HALT = 0x25,  /* halt the program */
 }
-impl TryFrom<u16> for Opcodes {
-    type Error = ();
-
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Opcodes::OP_BR),
-            1 => Ok(Opcodes::OP_ADD),
-            2 => Ok(Opcodes::OP_LD),
-            3 => Ok(Opcodes::OP_ST),
-            4 => Ok(Opcodes::OP_JSR),
-            5 => Ok(Opcodes::OP_AND),
-            6 => Ok(Opcodes::OP_NOT),
-            7 => Ok(Opcodes::OP_LDR),
-            8 => Ok(Opcodes::OP_STR),
-            9 => Ok(Opcodes::OP_RTI),
-            10 => Ok(Opcodes::OP_LDI),
-            11 => Ok(Opcodes::OP_STI),
-            12 => Ok(Opcodes::OP_JMP),
-            13 => Ok(Opcodes::OP_RES),
-            14 => Ok(Opcodes::OP_LEA),
-            15 => Ok(Opcodes::OP_TRAP),
-            _ => Err(()), // Return an error for invalid opcodes
-        }
-    }
-}
 impl OP_TRAP {
     pub fn from_u16(val: u16) -> Option<Self> {
         match val {
@@ -112,203 +82,639 @@ pub fn sign_extend(value: u16, bit_count: u16) -> u16 {
         value
     }
 }
+
+/// Which instruction set a `VM` decodes and executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    #[default]
+    Lc3,
+    /// The byte-addressable LC-3b; see `lc3b`.
+    Lc3b,
+}
+
+impl std::str::FromStr for Isa {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "lc3" => Ok(Isa::Lc3),
+            "lc3b" => Ok(Isa::Lc3b),
+            _ => Err(format!("unknown instruction set `{}`", name)),
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Isa::Lc3 => "LC-3",
+            Isa::Lc3b => "LC-3b",
+        })
+    }
+}
+
+/// Result of executing a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Halted,
+}
+
+/// Conditions that stop the machine before an instruction completes. The
+/// PC is left pointing at the faulting instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IllegalOpcode {
+        pc: u16,
+        instr: u16,
+    },
+    PrivilegeViolation {
+        pc: u16,
+    },
+    InvalidTrap {
+        pc: u16,
+        vector: u16,
+    },
+    EndOfInput {
+        pc: u16,
+    },
+    /// The input log being replayed has no input for this instruction.
+    ReplayDiverged {
+        pc: u16,
+        count: u64,
+    },
+    /// A limit of `VM::budget` ran out before the instruction at `pc`. The
+    /// most recent instructions ran between `low` and `high`.
+    BudgetExhausted {
+        kind: BudgetKind,
+        pc: u16,
+        count: u64,
+        low: u16,
+        high: u16,
+    },
+    /// `VM::loop_detector` found the machine back in a state it was in
+    /// `length` instructions earlier, with no memory writes or input since.
+    InfiniteLoop {
+        pc: u16,
+        length: u64,
+    },
+    /// The instruction at `pc` read or wrote `address`, outside the `size`
+    /// words of `VM::memory`.
+    MemoryOutOfRange {
+        pc: u16,
+        address: u16,
+        size: usize,
+    },
+    /// An LC-3b word access, or instruction fetch, at the odd byte address
+    /// `address`.
+    UnalignedAccess {
+        pc: u16,
+        address: u16,
+    },
+    /// Under `InitMode::Fault`, the instruction at `pc` read a location
+    /// nothing had set.
+    UninitializedRead {
+        pc: u16,
+        location: Uninitialized,
+    },
+}
+
+impl Fault {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Fault::IllegalOpcode { pc, .. }
+            | Fault::PrivilegeViolation { pc }
+            | Fault::InvalidTrap { pc, .. }
+            | Fault::EndOfInput { pc }
+            | Fault::ReplayDiverged { pc, .. }
+            | Fault::BudgetExhausted { pc, .. }
+            | Fault::InfiniteLoop { pc, .. }
+            | Fault::MemoryOutOfRange { pc, .. }
+            | Fault::UnalignedAccess { pc, .. }
+            | Fault::UninitializedRead { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Fault::IllegalOpcode { pc, instr } => {
+                write!(
+                    f,
+                    "Invalid opcode encountered: {:#06X} at x{:04X}",
+                    instr, pc
+                )
+            }
+            Fault::PrivilegeViolation { pc } => {
+                write!(f, "RTI executed in user mode at x{:04X}", pc)
+            }
+            Fault::InvalidTrap { pc, vector } => {
+                write!(f, "Invalid TRAP vector: x{:02X} at x{:04X}", vector, pc)
+            }
+            Fault::EndOfInput { pc } => {
+                write!(
+                    f,
+                    "Input ended while waiting for a character at x{:04X}",
+                    pc
+                )
+            }
+            Fault::ReplayDiverged { pc, count } => {
+                write!(
+                    f,
+                    "Input replay diverged at instruction {} (x{:04X}): the log has no matching input",
+                    count, pc
+                )
+            }
+            Fault::BudgetExhausted {
+                kind,
+                pc,
+                count,
+                low,
+                high,
+            } => {
+                write!(
+                    f,
+                    "{} reached after {} instructions at x{:04X} (recently executing x{:04X}-x{:04X}; is it stuck in a loop?)",
+                    kind, count, pc, low, high
+                )
+            }
+            Fault::InfiniteLoop { pc, length: 1 } => {
+                write!(
+                    f,
+                    "Infinite loop at x{:04X}: the instruction jumps to itself",
+                    pc
+                )
+            }
+            Fault::InfiniteLoop { pc, length } => {
+                write!(
+                    f,
+                    "Infinite loop at x{:04X}: the machine state repeats every {} instructions with no memory writes or input",
+                    pc, length
+                )
+            }
+            Fault::MemoryOutOfRange { pc, address, size } => {
+                write!(
+                    f,
+                    "Memory access at x{:04X} is outside memory (x{:04X} words) at x{:04X}",
+                    address, size, pc
+                )
+            }
+            Fault::UnalignedAccess { pc, address } => {
+                write!(
+                    f,
+                    "Word access at odd address x{:04X} at x{:04X}",
+                    address, pc
+                )
+            }
+            Fault::UninitializedRead {
+                pc,
+                location: Uninitialized::Instruction,
+            } => {
+                write!(f, "Executing uninitialized memory at x{:04X}", pc)
+            }
+            Fault::UninitializedRead { pc, location } => {
+                write!(f, "Read of uninitialized {} at x{:04X}", location, pc)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VM {
-    pub memory: [u16; 1 << 16],
+    /// A `FlatMemory` of the whole address space unless replaced.
+    pub memory: Box<dyn Memory>,
+    /// The first access outside memory by the instruction executing.
+    memory_error: Option<MemoryError>,
+    /// The first uninitialized location read by the instruction executing.
+    uninitialized: Option<Uninitialized>,
     pub registers_storage: [u16; Registers::R_COUNT as usize],
+    /// The instruction set programs are decoded and executed as.
+    pub isa: Isa,
+    pub console: Console,
+    /// Instructions completed so far.
+    pub instruction_count: u64,
+    /// Undo log for reverse execution; `None` disables recording.
+    pub history: Option<History>,
+    /// Recording or replaying of keyboard input.
+    pub input_trace: InputTrace,
+    /// Execution counts for the profiler; `None` disables profiling.
+    pub profiler: Option<Profiler>,
+    /// Executed addresses and branch directions; `None` disables coverage.
+    pub coverage: Option<Coverage>,
+    /// Shadow call stack and call graph; `None` disables tracking.
+    pub call_stack: Option<CallStack>,
+    /// Decoded instructions, cached per address.
+    pub decode_cache: DecodeCache,
+    /// Which engine `execute` runs programs with.
+    pub engine: Engine,
+    /// Basic blocks for the threaded engine.
+    pub blocks: BlockCache,
+    /// Native code for the JIT engine.
+    #[cfg(feature = "jit")]
+    pub jit: JitCache,
+    /// Limits for `execute`; `None` runs until the program halts or faults.
+    pub budget: Option<Budget>,
+    /// Infinite-loop detection; `None` disables it.
+    pub loop_detector: Option<LoopDetector>,
+    /// Instruction trace; `None` disables tracing.
+    pub tracer: Option<Tracer>,
+    /// Stack and calling-convention checks; `None` disables them.
+    pub stack_checker: Option<StackChecker>,
+    /// Uninitialized read detection; `None` disables it.
+    pub init_checker: Option<InitChecker>,
+    /// Semantics for the reserved opcode; `None` makes it illegal.
+    pub extension: Option<Box<dyn Extension>>,
+    /// Suppresses the "Halting the program..." message.
+    pub quiet: bool,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new() -> Self {
+        let mut registers_storage = [0; Registers::R_COUNT as usize];
+        registers_storage[Registers::R_COND as usize] = R_COND::FL_ZRO as u16;
         Self {
-            memory: [0; MEMORY_SIZE],
-            registers_storage: [0; Registers::R_COUNT as usize],
+            memory: Box::new(FlatMemory::default()),
+            memory_error: None,
+            uninitialized: None,
+            registers_storage,
+            isa: Isa::Lc3,
+            console: Console::default(),
+            instruction_count: 0,
+            history: None,
+            input_trace: InputTrace::Off,
+            profiler: None,
+            coverage: None,
+            call_stack: None,
+            decode_cache: DecodeCache::new(),
+            engine: Engine::Interpreter,
+            blocks: BlockCache::new(),
+            #[cfg(feature = "jit")]
+            jit: JitCache::new(),
+            budget: None,
+            loop_detector: None,
+            tracer: None,
+            stack_checker: None,
+            init_checker: None,
+            extension: None,
+            quiet: false,
+        }
+    }
+    /// Runs like `execute`, but also stops when Ctrl+C is pressed.
+    pub(crate) fn execute_until_interrupted(&mut self) -> Result<(), Fault> {
+        if let Some(budget) = self.budget {
+            return self.execute_budgeted(budget, input_buffering::interrupted);
+        }
+        while !input_buffering::interrupted() {
+            if self.advance()? == Step::Halted {
+                break;
+            }
         }
+        Ok(())
     }
-    fn load_arguments(&mut self) {
-        let args: Vec<String> = env::args().collect();
 
-        // Check if at least one image file is passed
-        if args.len() < 2 {
-            eprintln!("Usage: lc3 [image-file1] ...");
-            std::process::exit(2);
+    /// Runs from the current PC until the program halts or faults, using the
+    /// selected engine. With a `budget` installed, running out of it stops
+    /// the program with `Fault::BudgetExhausted`.
+    pub fn execute(&mut self) -> Result<(), Fault> {
+        if let Some(budget) = self.budget {
+            return self.execute_budgeted(budget, || false);
         }
+        while self.advance()? == Step::Continue {}
+        Ok(())
+    }
 
-        for filename in &args[1..] {
-            match File::open(filename) {
-                Ok(file) => {
-                    if let Err(e) = self.read_image_file(file) {
-                        eprintln!("Failed to load image: {} ({})", filename, e);
-                        std::process::exit(1);
-                    }
-                }
-                Err(_) => {
-                    eprintln!("Could not open file: {}", filename);
-                    std::process::exit(1);
+    /// Fetches and executes the instruction at PC. On a fault PC and R7 are
+    /// restored so the faulting instruction can be inspected or retried.
+    pub fn step(&mut self) -> Result<Step, Fault> {
+        let pc = self.registers_storage[Registers::R_PC as usize];
+        let r7 = self.registers_storage[Registers::R_R7 as usize];
+        let cond = self.registers_storage[Registers::R_COND as usize];
+        let instr = self.fetch(pc)?;
+        let registers = self.registers_storage;
+        self.memory_error = None;
+        let mut writes = 0;
+        if let Some(checker) = self.init_checker.as_ref() {
+            let (reads, written) = match self.isa {
+                Isa::Lc3 => lc3_registers(decode(pc, instr)),
+                Isa::Lc3b => lc3b_registers(lc3b::decode(pc, instr)),
+            };
+            let address = match self.isa {
+                Isa::Lc3 => pc,
+                Isa::Lc3b => lc3b::word_address(pc),
+            };
+            let uninitialized = checker.check(address, reads);
+            if let (InitMode::Fault, Some(location)) = (checker.mode, uninitialized) {
+                return Err(Fault::UninitializedRead { pc, location });
+            }
+            writes = written;
+            self.uninitialized = uninitialized;
+        }
+        if let Some(detector) = self.loop_detector.as_mut() {
+            if let Some(length) = detector.observe(&self.registers_storage) {
+                return Err(Fault::InfiniteLoop { pc, length });
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.begin(self.instruction_count, pc, instr, self.registers_storage);
+        }
+        let mut result = match self.isa {
+            Isa::Lc3 => self.execute_instruction(pc),
+            Isa::Lc3b => self.execute_lc3b(pc, instr),
+        };
+        if self.input_trace.take_divergence() {
+            result = Err(Fault::ReplayDiverged {
+                pc,
+                count: self.instruction_count,
+            });
+        }
+        if let (Some(location), Some(checker)) =
+            (self.uninitialized.take(), self.init_checker.as_mut())
+        {
+            match checker.mode {
+                InitMode::Warn => checker.flag(pc, location),
+                InitMode::Fault => {
+                    self.registers_storage = registers;
+                    result = Err(Fault::UninitializedRead { pc, location });
                 }
             }
         }
+        if let Some(e) = self.memory_error.take() {
+            // Undo whatever a load out of range put in a register
+            self.registers_storage = registers;
+            result = Err(self.out_of_range(pc, e));
+        }
+        if result.is_err() {
+            self.registers_storage[Registers::R_PC as usize] = pc;
+            self.registers_storage[Registers::R_R7 as usize] = r7;
+            if let Some(history) = self.history.as_mut() {
+                history.abort();
+            }
+        } else {
+            self.instruction_count += 1;
+            if let Some(history) = self.history.as_mut() {
+                history.commit();
+            }
+            if let Some(profiler) = self.profiler.as_mut() {
+                let next_pc = self.registers_storage[Registers::R_PC as usize];
+                profiler.record(pc, instr, next_pc);
+            }
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(pc, instr, cond);
+            }
+            if let Some(checker) = self.stack_checker.as_mut() {
+                let sp = Registers::R_R6 as usize;
+                checker.moved(pc, registers[sp], self.registers_storage[sp]);
+            }
+            if let Some(checker) = self.init_checker.as_mut() {
+                checker.written(writes, &registers, &self.registers_storage);
+            }
+            if let Some(tracer) = self.tracer.as_mut() {
+                let extension = self.extension.as_deref();
+                tracer.record(
+                    self.isa,
+                    extension,
+                    pc,
+                    instr,
+                    &registers,
+                    &self.registers_storage,
+                );
+            }
+        }
+        result
     }
 
-    pub fn run(&mut self) {
-        setup();
-
-        self.load_arguments();
-
-        self.registers_storage[Registers::R_COND as usize] = R_COND::FL_ZRO as u16; //
-        let pc_start: u16 = 0x3000;
-        self.registers_storage[Registers::R_PC as usize] = pc_start; //.
+    /// The instruction word at `pc`, read without side effects.
+    pub(crate) fn fetch(&self, pc: u16) -> Result<u16, Fault> {
+        match self.isa {
+            Isa::Lc3 => self
+                .memory
+                .read(pc as usize)
+                .map_err(|e| self.out_of_range(pc, e)),
+            Isa::Lc3b => self.fetch_lc3b(pc),
+        }
+    }
 
-        let mut running = true;
-        while running {
-            let instr = self.memory_read(self.registers_storage[Registers::R_PC as usize]);
-            println!("Instruction: {:#X}", instr);
-            self.registers_storage[Registers::R_PC as usize] += 1;
+    /// Undoes the most recently executed instruction using the history log.
+    /// Returns false when there is nothing left to undo. Input the
+    /// instruction consumed is handed back to a buffered console; output
+    /// already written cannot be taken back.
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        for write in record.writes.iter().rev() {
+            self.memory[write.address as usize] = write.old;
+        }
+        self.registers_storage = record.registers;
+        self.instruction_count = record.count;
+        self.console.unread(&record.input);
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            if record.called {
+                call_stack.undo_call();
+            }
+            call_stack.undo_ret(&record.returned);
+        }
+        true
+    }
 
-            let opcode = instr >> 12;
-            match Opcodes::try_from(opcode) {
-                Ok(Opcodes::OP_ADD) => {
-                    self.add(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_AND) => {
-                    self.and(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_NOT) => {
-                    self.not(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_BR) => {
-                    self.branch(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_LD) => {
-                    self.load(instr);
-                    break;
-                }
+    fn execute_instruction(&mut self, pc: u16) -> Result<Step, Fault> {
+        let instr = self.memory_read(pc);
+        self.registers_storage[Registers::R_PC as usize] = pc.wrapping_add(1);
+        let decoded = self.decode_cache.get(pc, instr);
+        self.execute_decoded(pc, decoded)
+    }
 
-                Ok(Opcodes::OP_ST) => {
-                    self.store(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_JSR) => {
-                    self.jump_register(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_LDR) => {
-                    self.load_register(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_STR) => {
-                    self.store_register(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_RTI) => {
-                    break;
-                }
-                Ok(Opcodes::OP_LDI) => {
-                    self.ldi(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_STI) => {
-                    self.store_indirect(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_JMP) => {
-                    self.jump(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_RES) => {
-                    break;
-                }
-                Ok(Opcodes::OP_LEA) => {
-                    self.lea(instr);
-                    break;
-                }
-                Ok(Opcodes::OP_TRAP) => {
-                    self.registers_storage[Registers::R_R7 as usize] =
-                        self.registers_storage[Registers::R_PC as usize];
-                    let instr = instr & 0xFF;
-                    if let Some(trap_vector) = OP_TRAP::from_u16(instr) {
-                        match trap_vector {
-                            OP_TRAP::TRAP_GETC => {
-                                self.trap_getc(None);
-                            }
-                            OP_TRAP::TRAP_OUT => {
-                                self.trap_out();
-                            }
-                            OP_TRAP::TRAP_PUTS => {
-                                self.trap_puts();
-                            }
-                            OP_TRAP::TRAP_IN => {
-                                self.trap_in();
-                            }
-                            OP_TRAP::TRAP_PUTSP => {
-                                self.trap_putsp();
-                            }
-                            OP_TRAP::TRAP_HALT => {
-                                println!("Halting the program...");
-                                io::stdout().flush().unwrap();
-                                running = false;
-                            }
-                        }
-                    } else {
-                        println!("Invalid TRAP vector: {}", instr);
-                    }
-                    break;
+    /// Executes an instruction fetched from `pc`. PC must already point past it.
+    pub(crate) fn execute_decoded(
+        &mut self,
+        pc: u16,
+        decoded: DecodedInstr,
+    ) -> Result<Step, Fault> {
+        let r = |index: u8| index as usize;
+        match decoded {
+            DecodedInstr::Add { dr, sr1, sr2 } => {
+                self.registers_storage[r(dr)] =
+                    self.registers_storage[r(sr1)].wrapping_add(self.registers_storage[r(sr2)]);
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::AddImm { dr, sr1, imm } => {
+                self.registers_storage[r(dr)] = self.registers_storage[r(sr1)].wrapping_add(imm);
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::And { dr, sr1, sr2 } => {
+                self.registers_storage[r(dr)] =
+                    self.registers_storage[r(sr1)] & self.registers_storage[r(sr2)];
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::AndImm { dr, sr1, imm } => {
+                self.registers_storage[r(dr)] = self.registers_storage[r(sr1)] & imm;
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::Not { dr, sr } => {
+                self.registers_storage[r(dr)] = !self.registers_storage[r(sr)];
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::Br { nzp, target } => {
+                if nzp & self.registers_storage[Registers::R_COND as usize] != 0 {
+                    self.registers_storage[Registers::R_PC as usize] = target;
                 }
-                Err(_) => {
-                    eprintln!("Invalid opcode encountered: {:#X}", opcode);
-                    break;
+            }
+            DecodedInstr::Jmp { base } => {
+                let target = self.registers_storage[r(base)];
+                self.registers_storage[Registers::R_PC as usize] = target;
+                // RET
+                if base == Registers::R_R7 as u8 {
+                    self.track_return(pc, target);
                 }
             }
+            DecodedInstr::Jsr { target } => self.call(pc, target),
+            DecodedInstr::Jsrr { base } => {
+                // Read the base register before R7 is overwritten (JSRR R7)
+                self.call(pc, self.registers_storage[r(base)]);
+            }
+            DecodedInstr::Ld { dr, address } => {
+                self.registers_storage[r(dr)] = self.memory_read(address);
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::Ldi { dr, address } => {
+                let address = self.memory_read(address);
+                self.registers_storage[r(dr)] = self.memory_read(address);
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::Ldr { dr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                self.registers_storage[r(dr)] = self.memory_read(address);
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::Lea { dr, address } => {
+                self.registers_storage[r(dr)] = address;
+                self.update_flags(dr as u16);
+            }
+            DecodedInstr::St { sr, address } => {
+                self.mem_write(address, self.registers_storage[r(sr)]);
+            }
+            DecodedInstr::Sti { sr, address } => {
+                let address = self.memory_read(address);
+                self.mem_write(address, self.registers_storage[r(sr)]);
+            }
+            DecodedInstr::Str { sr, base, offset } => {
+                let address = self.registers_storage[r(base)].wrapping_add(offset);
+                self.mem_write(address, self.registers_storage[r(sr)]);
+            }
+            DecodedInstr::Trap { vector } => return self.trap(pc, vector),
+            DecodedInstr::Rti => return Err(Fault::PrivilegeViolation { pc }),
+            DecodedInstr::Illegal { instr } => return self.execute_extension(pc, instr),
         }
-        restore_input_buffering();
+        Ok(Step::Continue)
     }
-    pub fn swap16(x: u16) -> u16 {
-        x << 8 | x >> 8
+
+    /// Executes the operand fields of `instruction` as `opcode`, as if it had
+    /// just been fetched, i.e. with PC already pointing past it.
+    fn execute_handler(&mut self, opcode: u16, instruction: u16) {
+        let pc = self.registers_storage[Registers::R_PC as usize].wrapping_sub(1);
+        let instruction = (opcode << 12) | (instruction & 0x0FFF);
+        // Only TRAP, RTI and the reserved opcode can fail, and those never come through here
+        let _ = self.execute_decoded(pc, decode(pc, instruction));
     }
 
-    pub fn read_image(&mut self, filename: &str) -> io::Result<()> {
-        let file = File::open(filename)?;
-        self.read_image_file(file)
+    /// JSR/JSRR at `call_site`: saves the return address in R7 and jumps to
+    /// `target`.
+    pub(crate) fn call(&mut self, call_site: u16, target: u16) {
+        let pc = self.registers_storage[Registers::R_PC as usize];
+        self.registers_storage[Registers::R_R7 as usize] = pc;
+        self.registers_storage[Registers::R_PC as usize] = target;
+        if let Some(checker) = self.stack_checker.as_mut() {
+            let sp = self.registers_storage[Registers::R_R6 as usize];
+            checker.call(call_site, target, pc, sp);
+        }
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            call_stack.call(CallFrame {
+                call_site,
+                entry: target,
+                return_address: pc,
+            });
+            if let Some(history) = self.history.as_mut() {
+                history.record_call();
+            }
+        }
     }
-    pub fn read_image_file(&mut self, mut file: File) -> io::Result<()> {
-        // Read origin (2 bytes)
-        let mut origin_buf = [0u8; 2];
-        file.read_exact(&mut origin_buf)?;
-        let origin = u16::from_be_bytes(origin_buf) as usize;
-        println!("origin {:}", origin);
 
-        // Read the rest of the image into memory starting at `origin`
-        let max_read = MEMORY_SIZE - origin;
-        let mut buffer = vec![0u8; max_read * 2]; // Each word is 2 bytes
-        let read_bytes = file.read(&mut buffer)?;
+    /// RET at `pc` to `target`.
+    pub(crate) fn track_return(&mut self, pc: u16, target: u16) {
+        if let Some(checker) = self.stack_checker.as_mut() {
+            checker.ret(pc, target, self.registers_storage[Registers::R_R6 as usize]);
+        }
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            let frames = call_stack.ret(target);
+            if let Some(history) = self.history.as_mut() {
+                history.record_return(frames);
+            }
+        }
+    }
 
-        // Convert bytes to u16 and store in memory
-        for i in 0..(read_bytes / 2) {
-            let byte_pair = [buffer[i * 2], buffer[i * 2 + 1]];
-            self.memory[origin + i] = u16::from_be_bytes(byte_pair);
+    pub(crate) fn trap(&mut self, pc: u16, vector: u16) -> Result<Step, Fault> {
+        self.registers_storage[Registers::R_R7 as usize] =
+            self.registers_storage[Registers::R_PC as usize];
+        match OP_TRAP::from_u16(vector) {
+            Some(OP_TRAP::TRAP_GETC) => self.trap_getc(pc, None)?,
+            Some(OP_TRAP::TRAP_OUT) => self.trap_out(),
+            Some(OP_TRAP::TRAP_PUTS) => self.trap_puts(),
+            Some(OP_TRAP::TRAP_IN) => self.trap_in(pc)?,
+            Some(OP_TRAP::TRAP_PUTSP) => self.trap_putsp(),
+            Some(OP_TRAP::TRAP_HALT) => {
+                if !self.quiet {
+                    self.console.write(b"Halting the program...\n");
+                }
+                return Ok(Step::Halted);
+            }
+            None => return Err(Fault::InvalidTrap { pc, vector }),
         }
+        Ok(Step::Continue)
+    }
+    pub fn swap16(x: u16) -> u16 {
+        x.rotate_left(8)
+    }
 
-        Ok(())
+    /// Loads an `.obj` image, or an lc3tools `.hex` or `.bin` text image,
+    /// and returns its origin.
+    pub fn read_image(&mut self, filename: &str) -> io::Result<u16> {
+        let image = ImageLoader::default().load(self, filename)?;
+        Ok(image.origin)
+    }
+    /// Loads an LC-3 image and returns the origin it was placed at. Text
+    /// images are recognised by their content. Problems such as a trailing
+    /// odd byte are tolerated; use an `ImageLoader` to have them reported.
+    pub fn read_image_file(&mut self, mut file: File) -> io::Result<u16> {
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let format = ImageFormat::sniff(&bytes);
+        let image = ImageLoader::default().load_format(self, "image", &bytes, format)?;
+        Ok(image.origin)
     }
     pub fn trap_puts(&mut self) {
         let mut address = self.registers_storage[Registers::R_R0 as usize];
+        let mut text = Vec::new();
         loop {
             let c = self.memory_read(address);
             if c == 0 {
                 break;
             }
-            print!("{}", c as u8 as char);
+            text.push(c as u8);
 
-            address += 1;
+            address = address.wrapping_add(1);
         }
-        stdout().flush().unwrap();
+        self.console.write(&text);
     }
     pub fn trap_putsp(&mut self) {
         let mut address = self.registers_storage[Registers::R_R0 as usize];
+        let mut text = Vec::new();
 
         loop {
             let word = self.memory_read(address);
@@ -317,229 +723,206 @@ impl VM {
             }
 
             // Extract lower 8 bits (char1) and upper 8 bits (char2)
-            let char1 = (word & 0xFF) as u8 as char;
-            print!("{}", char1);
+            let char1 = (word & 0xFF) as u8;
+            text.push(char1);
 
             let char2 = (word >> 8) as u8;
             if char2 != 0 {
-                print!("{}", char2 as char);
+                text.push(char2);
             }
 
-            address += 1;
+            address = address.wrapping_add(1);
         }
 
-        io::stdout().flush().unwrap();
+        self.console.write(&text);
     }
     // if else block and input param is so I can test the funnction independently,
-    pub fn trap_getc(&mut self, input: Option<u16>) {
+    // `pc` is the TRAP's address, for the fault when input has run out
+    pub fn trap_getc(&mut self, pc: u16, input: Option<u16>) -> Result<(), Fault> {
         if let Some(value) = input {
             self.registers_storage[Registers::R_R0 as usize] = value;
         } else {
-            self.registers_storage[Registers::R_R0 as usize] = self.get_char();
+            self.registers_storage[Registers::R_R0 as usize] =
+                self.get_char().ok_or(Fault::EndOfInput { pc })?;
         }
         self.update_flags(Registers::R_R0 as u16);
+        Ok(())
     }
     pub fn trap_out(&mut self) {
-        let c = self.registers_storage[Registers::R_R0 as usize] as u8 as char;
-        print!("{}", c);
-        io::stdout().flush().unwrap();
+        let c = self.registers_storage[Registers::R_R0 as usize] as u8;
+        self.console.write(&[c]);
     }
-    pub fn trap_in(&mut self) {
-        print!("Enter a character: ");
-        io::stdout().flush().unwrap(); 
-
-        let mut buffer = [0u8; 1];
-        io::stdin().read_exact(&mut buffer).unwrap();
+    /// Prompts for, reads and echoes one character. `pc` is the TRAP's
+    /// address, for the fault when input has run out.
+    pub fn trap_in(&mut self, pc: u16) -> Result<(), Fault> {
+        self.console.write(b"Enter a character: ");
 
-        let c = buffer[0] as u16;
+        let c = self.get_char().ok_or(Fault::EndOfInput { pc })?;
 
         // Echo the character back to the console
-        print!("{}", c as u8 as char);
-        io::stdout().flush().unwrap();
+        self.console.write(&[c as u8]);
 
         // Store in R0
         self.registers_storage[Registers::R_R0 as usize] = c;
 
         // Update condition flags based on the character value
         self.update_flags(Registers::R_R0 as u16);
+        Ok(())
     }
     pub fn update_flags(&mut self, r: u16) -> u16 {
-        println!("r  {:?}", r);
-        let mut condition_flag = self.registers_storage[Registers::R_COND as usize];
-
         let content_at_r = self.registers_storage[r as usize];
 
-        println!("content at r {:}", content_at_r);
-        if content_at_r == 0 {
-            condition_flag = R_COND::FL_ZRO as u16
+        let condition_flag = if content_at_r == 0 {
+            R_COND::FL_ZRO as u16
         } else if content_at_r >> 15 == 1 {
-            condition_flag = R_COND::FL_NEG as u16
+            R_COND::FL_NEG as u16
         } else {
-            condition_flag = R_COND::FL_POS as u16
-        }
-        println!("condition flag {:}", condition_flag);
+            R_COND::FL_POS as u16
+        };
+        self.registers_storage[Registers::R_COND as usize] = condition_flag;
         condition_flag
     }
 
     pub fn not(&mut self, instruction: u16) {
-        //destination register (DR)
-        let dr = (instruction >> 9) & 0x7;
-        //first source register (SR1)
-        let r1 = (instruction >> 6) & 0x7;
-
-        self.registers_storage[dr as usize] = !self.registers_storage[r1 as usize];
-        self.update_flags(dr);
+        self.execute_handler(0x9, instruction);
     }
-
     pub fn jump(&mut self, instruction: u16) {
-        let r1 = (instruction >> 6) & 0x7;
-
-        self.registers_storage[Registers::R_PC as usize] = self.registers_storage[r1 as usize];
+        self.execute_handler(0xC, instruction);
     }
     pub fn jump_register(&mut self, instruction: u16) {
-        let long_flag = (instruction >> 11) & 0x1;
-        self.registers_storage[Registers::R_R7 as usize] =
-            self.registers_storage[Registers::R_PC as usize];
-        if long_flag == 1 {
-            let pc_offset = instruction & 0x7FF;
-            let pc_offset = sign_extend(pc_offset, 11);
-            self.registers_storage[Registers::R_PC as usize] += pc_offset;
-        } else {
-            let r1 = (instruction >> 6) & 0x7;
-            self.registers_storage[Registers::R_PC as usize] = self.registers_storage[r1 as usize];
-        }
+        self.execute_handler(0x4, instruction);
     }
-
     pub fn branch(&mut self, instruction: u16) {
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-        self.registers_storage[Registers::R_PC as usize] += pc_offset;
+        self.execute_handler(0x0, instruction);
     }
-
     pub fn ldi(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0x7;
-
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-
-        let address =
-            self.memory_read(self.registers_storage[Registers::R_PC as usize] + pc_offset);
-
-        self.registers_storage[dr as usize] = self.memory_read(address);
-
-        self.update_flags(dr);
+        self.execute_handler(0xA, instruction);
     }
-
     pub fn add(&mut self, instruction: u16) {
-        let r0 = (instruction >> 9) & 0x7;
-        let r1 = (instruction >> 6) & 0x7;
-
-        if (instruction >> 5) & 0x1 == 1 {
-            let imm5 = instruction & 0x1F;
-            let imm5 = sign_extend(imm5, 5);
-            self.registers_storage[r0 as usize] = self.registers_storage[r1 as usize] + imm5;
-        } else {
-            let r2 = instruction & 0x7;
-            self.registers_storage[r0 as usize] =
-                self.registers_storage[r1 as usize] + self.registers_storage[r2 as usize];
-        }
-        self.update_flags(r0);
+        self.execute_handler(0x1, instruction);
     }
-
     pub fn and(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0x7;
-        let r1 = (instruction >> 6) & 0x7;
-
-        if (instruction >> 5) & 0x1 == 1 {
-            let imm5 = instruction & 0x1F;
-            let imm5 = sign_extend(imm5, 5);
-            self.registers_storage[dr as usize] = self.registers_storage[r1 as usize] & imm5;
-        } else {
-            let r2 = instruction & 0x7;
-            self.registers_storage[dr as usize] =
-                self.registers_storage[r1 as usize] & self.registers_storage[r2 as usize];
-        }
-        self.update_flags(dr);
+        self.execute_handler(0x5, instruction);
     }
     pub fn load(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0x7;
-
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-        self.registers_storage[dr as usize] =
-            self.memory_read(self.registers_storage[Registers::R_PC as usize] + pc_offset);
-        self.update_flags(dr);
+        self.execute_handler(0x2, instruction);
     }
     pub fn load_register(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0x7;
-        let r1 = (instruction >> 6) & 0x7;
-
-        let offset = instruction & 0x3F;
-        let offset = sign_extend(offset, 6);
-        self.registers_storage[dr as usize] =
-            self.memory_read(self.registers_storage[r1 as usize] + offset);
-        self.update_flags(dr);
+        self.execute_handler(0x6, instruction);
     }
     pub fn lea(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0x7;
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-        self.registers_storage[dr as usize] =
-            self.registers_storage[Registers::R_PC as usize] + pc_offset;
-        self.update_flags(dr);
+        self.execute_handler(0xE, instruction);
     }
     pub fn store(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0x7;
-
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-        self.mem_write(
-            self.registers_storage[Registers::R_PC as usize] + pc_offset,
-            self.registers_storage[sr as usize],
-        );
+        self.execute_handler(0x3, instruction);
     }
     pub fn store_indirect(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0x7;
-
-        let pc_offset = instruction & 0x1FF;
-        let pc_offset = sign_extend(pc_offset, 9);
-        let address =
-            self.memory_read(self.registers_storage[Registers::R_PC as usize] + pc_offset);
-        self.mem_write(address, self.registers_storage[sr as usize]);
+        self.execute_handler(0xB, instruction);
     }
     pub fn store_register(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0x7;
-        let r1 = (instruction >> 6) & 0x7;
-        println!("sr {:}", sr);
-        println!("r1 {:}", r1);
-
-        let offset = instruction & 0x3F;
-        let offset = sign_extend(offset, 6);
-        self.mem_write(
-            self.registers_storage[r1 as usize] + offset,
-            self.registers_storage[sr as usize],
-        );
+        self.execute_handler(0x7, instruction);
     }
 
+    /// Writes `val` to memory. A write outside memory changes nothing and
+    /// faults the instruction making it, as does one after an uninitialized
+    /// read under `InitMode::Fault`.
     pub fn mem_write(&mut self, address: u16, val: u16) {
-        self.memory[address as usize] = val;
+        if let Some(checker) = &self.init_checker {
+            if self.uninitialized.is_some() && checker.mode == InitMode::Fault {
+                return;
+            }
+        }
+        let old = match self.memory.read(address as usize) {
+            Ok(old) => old,
+            Err(e) => {
+                self.memory_error.get_or_insert(e);
+                return;
+            }
+        };
+        self.decode_cache.invalidate(address);
+        self.blocks.invalidate(address);
+        if let Some(history) = self.history.as_mut() {
+            history.record_write(address, old, val);
+        }
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.reset();
+        }
+        let _ = self.memory.write(address as usize, val);
+        if let Some(checker) = self.init_checker.as_mut() {
+            checker.initialize(address as usize..address as usize + 1);
+        }
     }
 
     pub fn memory_read(&mut self, address: u16) -> u16 {
         if address == MR_KBSR {
-            if check_key() {
-                self.memory[MR_KBSR as usize] = 1 << 15;
-                self.memory[MR_KBDR as usize] = self.get_char();
+            if let Some(c) = self.poll_char() {
+                self.mem_write(MR_KBSR, 1 << 15);
+                self.mem_write(MR_KBDR, c);
+            } else {
+                self.mem_write(MR_KBSR, 0);
+            }
+        }
+        if let Some(checker) = self.init_checker.as_ref() {
+            if !checker.is_initialized(address) {
+                self.uninitialized
+                    .get_or_insert(Uninitialized::Memory(address));
+            }
+        }
+        match self.memory.read(address as usize) {
+            Ok(word) => word,
+            Err(e) => {
+                self.memory_error.get_or_insert(e);
+                0
+            }
+        }
+    }
+
+    pub(crate) fn out_of_range(&self, pc: u16, e: MemoryError) -> Fault {
+        Fault::MemoryOutOfRange {
+            pc,
+            address: e.address as u16,
+            size: e.size,
+        }
+    }
+
+    /// Reads a key for KBSR without blocking.
+    fn poll_char(&mut self) -> Option<u16> {
+        let count = self.instruction_count;
+        let c = if self.input_trace.is_replaying() {
+            self.input_trace.replay_poll(count)
+        } else {
+            let c = if self.console.poll_key() {
+                self.console.read_byte()
             } else {
-                self.memory[MR_KBSR as usize] = 0;
+                None
+            };
+            match c {
+                Some(c) => self.input_trace.record_key(count, c),
+                None => self.input_trace.record_no_key(count),
             }
+            c
+        }?;
+        if let Some(history) = self.history.as_mut() {
+            history.record_input(c);
         }
-        self.memory[address as usize]
+        Some(u16::from(c))
     }
 
-    fn get_char(&self) -> u16 {
-        let mut buffer = [0; 1];
-        io::stdin().read_exact(&mut buffer).unwrap();
-        buffer[0] as u16
+    fn get_char(&mut self) -> Option<u16> {
+        let count = self.instruction_count;
+        let c = if self.input_trace.is_replaying() {
+            self.input_trace.replay_read(count)?
+        } else {
+            let c = self.console.read_byte()?;
+            self.input_trace.record_key(count, c);
+            c
+        };
+        if let Some(history) = self.history.as_mut() {
+            history.record_input(c);
+        }
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.reset();
+        }
+        Some(u16::from(c))
     }
 }
@@ -0,0 +1,187 @@
+//! Saving and restoring the complete machine state.
+//!
+//! A snapshot is a big-endian binary file:
+//!
+//! | Field             | Size                                   |
+//! | ----------------- | -------------------------------------- |
+//! | magic `LC3SNAP\0` | 8 bytes                                |
+//! | version           | u16                                    |
+//! | instruction set   | u16: 0 for LC-3, 1 for LC-3b           |
+//! | memory size       | u64 words                              |
+//! | word width        | u16 bits                               |
+//! | R0-R7, PC         | 9 × u16                                |
+//! | PSR               | u16                                    |
+//! | instruction count | u64                                    |
+//! | KBSR, KBDR        | 2 × u16                                |
+//! | pending input     | u32 length, then that many bytes       |
+//! | memory            | 65536 × u16                            |
+//!
+//! A snapshot only loads into a VM with the same instruction set, memory
+//! size and word width.
+
+use crate::callstack::CallStack;
+use crate::memory::ADDRESS_SPACE;
+use crate::run::{Isa, Registers, MR_KBDR, MR_KBSR, VM};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// PSR bit 15: the VM always runs programs in user mode.
+const PSR_USER: u16 = 1 << 15;
+
+impl VM {
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Replaces the machine state with the one saved at `path`. The undo
+    /// history and call stack are cleared, since they no longer match the
+    /// restored state.
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        self.read_snapshot(&mut reader)
+    }
+
+    pub fn write_snapshot(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+        writer.write_all(&isa_code(self.isa).to_be_bytes())?;
+        writer.write_all(&(self.memory.size() as u64).to_be_bytes())?;
+        writer.write_all(&(self.memory.width() as u16).to_be_bytes())?;
+        for register in &self.registers_storage[..=Registers::R_PC as usize] {
+            writer.write_all(&register.to_be_bytes())?;
+        }
+        writer.write_all(&self.psr().to_be_bytes())?;
+        writer.write_all(&self.instruction_count.to_be_bytes())?;
+
+        let memory = self.memory.contents();
+        writer.write_all(&memory[MR_KBSR as usize].to_be_bytes())?;
+        writer.write_all(&memory[MR_KBDR as usize].to_be_bytes())?;
+        let pending = self.console.pending_input();
+        writer.write_all(&(pending.len() as u32).to_be_bytes())?;
+        writer.write_all(&pending)?;
+
+        for word in memory.iter() {
+            writer.write_all(&word.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_snapshot(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a VM snapshot"));
+        }
+        let version = read_u16(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(&format!(
+                "unsupported snapshot version {} (expected {})",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+        let isa = match read_u16(reader)? {
+            0 => Isa::Lc3,
+            1 => Isa::Lc3b,
+            code => return Err(invalid(&format!("unknown instruction set {}", code))),
+        };
+        if isa != self.isa {
+            return Err(invalid(&format!(
+                "the snapshot is of an {} machine, but this VM runs {}",
+                isa, self.isa
+            )));
+        }
+        let mut size = [0u8; 8];
+        reader.read_exact(&mut size)?;
+        let size = u64::from_be_bytes(size);
+        let width = read_u16(reader)?;
+        if size != self.memory.size() as u64 || u32::from(width) != self.memory.width() {
+            return Err(invalid(&format!(
+                "the snapshot has x{:04X} words of {} bits, but this VM's memory has x{:04X} of {}",
+                size,
+                width,
+                self.memory.size(),
+                self.memory.width()
+            )));
+        }
+
+        let mut registers = [0u16; Registers::R_COUNT as usize];
+        for register in registers[..=Registers::R_PC as usize].iter_mut() {
+            *register = read_u16(reader)?;
+        }
+        let psr = read_u16(reader)?;
+        registers[Registers::R_COND as usize] = psr & 0x7;
+        let mut count = [0u8; 8];
+        reader.read_exact(&mut count)?;
+
+        let kbsr = read_u16(reader)?;
+        let kbdr = read_u16(reader)?;
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+        let mut pending = vec![0u8; u32::from_be_bytes(length) as usize];
+        reader.read_exact(&mut pending)?;
+
+        let mut bytes = vec![0u8; ADDRESS_SPACE * 2];
+        reader.read_exact(&mut bytes)?;
+        let mut memory: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        memory[MR_KBSR as usize] = kbsr;
+        memory[MR_KBDR as usize] = kbdr;
+        // Words past the end of a smaller memory must be unused
+        let size = self.memory.size().min(ADDRESS_SPACE);
+        if let Some(address) = memory[size..].iter().position(|&word| word != 0) {
+            return Err(invalid(&format!(
+                "x{:04X} is outside this VM's memory",
+                size + address
+            )));
+        }
+        // Only changed words, so a sparse memory stores just the pages in use
+        for (address, &word) in memory[..size].iter().enumerate() {
+            if self.memory.get(address) != Some(&word) {
+                self.memory
+                    .write(address, word)
+                    .map_err(|e| invalid(&e.to_string()))?;
+            }
+        }
+        self.registers_storage = registers;
+        self.instruction_count = u64::from_be_bytes(count);
+        self.console.replace_input(&pending);
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            *call_stack = CallStack::new(registers[Registers::R_PC as usize]);
+        }
+        Ok(())
+    }
+
+    /// Processor status register: privilege bit and condition codes.
+    pub fn psr(&self) -> u16 {
+        PSR_USER | (self.registers_storage[Registers::R_COND as usize] & 0x7)
+    }
+}
+
+fn isa_code(isa: Isa) -> u16 {
+    match isa {
+        Isa::Lc3 => 0,
+        Isa::Lc3b => 1,
+    }
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
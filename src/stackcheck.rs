@@ -0,0 +1,231 @@
+//! Checks of the stack and calling conventions.
+//!
+//! LC-3 programs conventionally keep a stack in R6 and the return address
+//! in R7. With a `StackChecker` in `VM::stack_checker` the VM reports:
+//!
+//! - a RET with R6 different from what it was at the JSR, from unbalanced
+//!   pushes and pops;
+//! - a RET to an address no active call returns to, usually because R7 was
+//!   overwritten (by a nested JSR or a TRAP) without being saved;
+//! - R6 moving into code or data, the regions given to `protect`.
+//!
+//! Only R6 moving by at most `MAX_STEP` words counts as running into a
+//! region, as pushes, pops and frame allocation do. Setting R6 outright,
+//! say with LD, to a stack inside an image is not a problem. Problems don't
+//! stop the program; each instruction is reported once.
+
+use crate::symbols::SymbolTable;
+
+use std::fmt::Write;
+use std::mem;
+
+/// Most words R6 can move by in one instruction and still be running into a
+/// region rather than being set.
+pub const MAX_STEP: u16 = 32;
+
+/// A problem found by the `StackChecker`. `pc` is the instruction at fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackProblem {
+    /// RET with R6 at `sp`, where it was `expected` when `entry` was called
+    /// from `call_site`.
+    Unbalanced {
+        pc: u16,
+        sp: u16,
+        expected: u16,
+        entry: u16,
+        call_site: u16,
+    },
+    /// RET to `target`, which no active call returns to.
+    BadReturn { pc: u16, target: u16 },
+    /// R6 moved to `sp`, inside the region `start..=end`.
+    IntoProtected {
+        pc: u16,
+        sp: u16,
+        start: u16,
+        end: u16,
+    },
+}
+
+impl StackProblem {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            StackProblem::Unbalanced { pc, .. }
+            | StackProblem::BadReturn { pc, .. }
+            | StackProblem::IntoProtected { pc, .. } => pc,
+        }
+    }
+
+    /// Describes the problem, naming addresses with `symbols`.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let at = |address: u16| match label(address, symbols) {
+            Some(label) => format!("x{:04X} ({})", address, label),
+            None => format!("x{:04X}", address),
+        };
+        match *self {
+            StackProblem::Unbalanced {
+                sp,
+                expected,
+                entry,
+                call_site,
+                ..
+            } => {
+                let words = expected.wrapping_sub(sp) as i16;
+                let count = match words.unsigned_abs() {
+                    1 => "1 word".to_string(),
+                    n => format!("{} words", n),
+                };
+                let imbalance = if words > 0 {
+                    format!("{} pushed and not popped", count)
+                } else {
+                    format!("{} popped that were not pushed", count)
+                };
+                format!(
+                    "RET with R6 = x{:04X}, but it was x{:04X} when {} was called from {}: {}",
+                    sp,
+                    expected,
+                    symbols.describe(entry),
+                    at(call_site),
+                    imbalance
+                )
+            }
+            StackProblem::BadReturn { target, .. } => format!(
+                "RET to {}, which is not after an active call; was R7 overwritten?",
+                at(target)
+            ),
+            StackProblem::IntoProtected { sp, start, end, .. } => format!(
+                "R6 moved to {}, into the code or data at x{:04X}-x{:04X}",
+                at(sp),
+                start,
+                end
+            ),
+        }
+    }
+}
+
+/// `address` as `LABEL` or `LABEL+n`, when a label precedes it.
+fn label(address: u16, symbols: &SymbolTable) -> Option<String> {
+    symbols.nearest(address).map(|_| symbols.describe(address))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    call_site: u16,
+    entry: u16,
+    return_address: u16,
+    /// R6 at the call.
+    sp: u16,
+}
+
+/// Stack and calling-convention checker. Install one in
+/// `VM::stack_checker` before running.
+#[derive(Debug, Clone, Default)]
+pub struct StackChecker {
+    frames: Vec<Frame>,
+    /// Inclusive ranges R6 must stay out of.
+    protected: Vec<(u16, u16)>,
+    problems: Vec<StackProblem>,
+}
+
+impl StackChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `start..=end` as code or data the stack must stay out of.
+    pub fn protect(&mut self, start: u16, end: u16) {
+        self.protected.push((start, end));
+    }
+
+    /// Problems found so far, in the order they happened.
+    pub fn problems(&self) -> &[StackProblem] {
+        &self.problems
+    }
+
+    /// A JSR or JSRR at `call_site` to `entry`, with R6 at `sp`.
+    pub(crate) fn call(&mut self, call_site: u16, entry: u16, return_address: u16, sp: u16) {
+        self.frames.push(Frame {
+            call_site,
+            entry,
+            return_address,
+            sp,
+        });
+    }
+
+    /// A RET at `pc` to `target`, with R6 at `sp`. The innermost call
+    /// returning to `target` ends, along with any calls it made that never
+    /// returned.
+    pub(crate) fn ret(&mut self, pc: u16, target: u16, sp: u16) {
+        let Some(index) = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == target)
+        else {
+            self.flag(StackProblem::BadReturn { pc, target });
+            return;
+        };
+        let frame = self.frames[index];
+        self.frames.truncate(index);
+        if sp != frame.sp {
+            self.flag(StackProblem::Unbalanced {
+                pc,
+                sp,
+                expected: frame.sp,
+                entry: frame.entry,
+                call_site: frame.call_site,
+            });
+        }
+    }
+
+    /// Checks R6 after the instruction at `pc` changed it from `before` to
+    /// `after`.
+    pub(crate) fn moved(&mut self, pc: u16, before: u16, after: u16) {
+        let step = after.wrapping_sub(before).min(before.wrapping_sub(after));
+        if before == after || step > MAX_STEP {
+            return;
+        }
+        let region = |sp: u16| {
+            self.protected
+                .iter()
+                .copied()
+                .find(|&(start, end)| (start..=end).contains(&sp))
+        };
+        if let (None, Some((start, end))) = (region(before), region(after)) {
+            self.flag(StackProblem::IntoProtected {
+                pc,
+                sp: after,
+                start,
+                end,
+            });
+        }
+    }
+
+    fn flag(&mut self, problem: StackProblem) {
+        let seen = self.problems.iter().any(|seen| {
+            seen.pc() == problem.pc() && mem::discriminant(seen) == mem::discriminant(&problem)
+        });
+        if !seen {
+            self.problems.push(problem);
+        }
+    }
+
+    /// Lists the problems found, one per line, or nothing when there are
+    /// none.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        if self.problems.is_empty() {
+            return out;
+        }
+        writeln!(out, "Stack problems:").unwrap();
+        for problem in &self.problems {
+            writeln!(
+                out,
+                "  x{:04X}  {:<16} {}",
+                problem.pc(),
+                label(problem.pc(), symbols).unwrap_or_default(),
+                problem.describe(symbols)
+            )
+            .unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,363 @@
+use crate::extension::Extension;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Labels and their addresses, as produced by `lc3as` in a `.sym` file:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    START             3000
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    by_name: BTreeMap<String, u16>,
+    by_address: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses `.sym` text. Comment markers are ignored and any line that is
+    /// not a `NAME ADDRESS` pair (headers, separators) is skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches('/');
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let [name, address] = fields[..] {
+                let digits = address.trim_start_matches(['x', 'X']);
+                if let Ok(address) = u16::from_str_radix(digits, 16) {
+                    if is_identifier(name) {
+                        table.insert(name, address);
+                    }
+                }
+            }
+        }
+        table
+    }
+
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.by_name.insert(name.to_string(), address);
+        self.by_address
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied().or_else(|| {
+            self.by_name
+                .iter()
+                .find(|(label, _)| label.eq_ignore_ascii_case(name))
+                .map(|(_, &address)| address)
+        })
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    /// The closest label at or below `address`, with the distance to it.
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .map(|(&base, name)| (name.as_str(), address - base))
+    }
+
+    /// Formats an address as `LABEL`, `LABEL+n` or `x3000` when no label precedes it.
+    pub fn describe(&self, address: u16) -> String {
+        match self.nearest(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("x{:04X}", address),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.by_name
+            .iter()
+            .map(|(name, &address)| (name.as_str(), address))
+    }
+}
+
+/// One source line that occupies memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine {
+    /// 1-based line number in the assembly source.
+    pub line: usize,
+    pub address: u16,
+    pub words: u16,
+    /// False for `.FILL`, `.BLKW` and `.STRINGZ` data.
+    pub is_instruction: bool,
+}
+
+/// Maps assembly source lines to the addresses they were assembled to.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    lines: Vec<SourceLine>,
+    by_line: BTreeMap<usize, usize>,
+    by_address: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, entry: SourceLine) {
+        let index = self.lines.len();
+        self.lines.push(entry);
+        self.by_line.insert(entry.line, index);
+        self.by_address.insert(entry.address, index);
+    }
+
+    pub fn lines(&self) -> &[SourceLine] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The first instruction at or after `line`, so a breakpoint placed on a
+    /// comment or label-only line lands on the next real instruction.
+    pub fn resolve_line(&self, line: usize) -> Option<SourceLine> {
+        self.by_line
+            .range(line..)
+            .map(|(_, &index)| self.lines[index])
+            .find(|entry| entry.is_instruction)
+    }
+
+    pub fn line_at(&self, address: u16) -> Option<SourceLine> {
+        let (_, &index) = self.by_address.range(..=address).next_back()?;
+        let entry = self.lines[index];
+        (address - entry.address < entry.words).then_some(entry)
+    }
+}
+
+/// Lays out an assembly source file without encoding it, recording where
+/// each line and label ends up in memory.
+pub fn scan_asm(source: &str) -> Result<(SourceMap, SymbolTable), String> {
+    scan_asm_with(source, None)
+}
+
+/// Like `scan_asm`, but `extension`'s mnemonics are instructions too.
+pub fn scan_asm_with(
+    source: &str,
+    extension: Option<&dyn Extension>,
+) -> Result<(SourceMap, SymbolTable), String> {
+    let is_instruction = |token: &str| {
+        is_mnemonic(token) || extension.is_some_and(|extension| extension.is_mnemonic(token))
+    };
+    let mut map = SourceMap::new();
+    let mut symbols = SymbolTable::new();
+    let mut address: Option<u16> = None;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let tokens = tokenize(text);
+        let mut tokens = tokens.iter().map(String::as_str);
+        let Some(mut first) = tokens.next() else {
+            continue;
+        };
+
+        if !is_directive(first) && !is_instruction(first) {
+            let label = first.trim_end_matches(':');
+            let here = address.ok_or_else(|| format!("line {}: label before .ORIG", line))?;
+            symbols.insert(label, here);
+            match tokens.next() {
+                Some(next) => first = next,
+                None => continue,
+            }
+        }
+
+        let upper = first.to_ascii_uppercase();
+        let words = match upper.as_str() {
+            ".ORIG" => {
+                let operand = tokens
+                    .next()
+                    .ok_or_else(|| format!("line {}: .ORIG needs an address", line))?;
+                let origin = parse_number(operand)
+                    .ok_or_else(|| format!("line {}: bad .ORIG address {}", line, operand))?;
+                address = Some(origin as u16);
+                continue;
+            }
+            ".END" => {
+                address = None;
+                continue;
+            }
+            ".FILL" => 1,
+            ".BLKW" => {
+                let operand = tokens
+                    .next()
+                    .ok_or_else(|| format!("line {}: .BLKW needs a count", line))?;
+                parse_number(operand)
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("line {}: bad .BLKW count {}", line, operand))?
+                    as u16
+            }
+            ".STRINGZ" => {
+                let operand = tokens
+                    .next()
+                    .ok_or_else(|| format!("line {}: .STRINGZ needs a string", line))?;
+                let text = parse_string(operand)
+                    .ok_or_else(|| format!("line {}: bad string {}", line, operand))?;
+                text.len() as u16 + 1
+            }
+            _ if is_instruction(first) => 1,
+            _ => return Err(format!("line {}: unknown directive {}", line, first)),
+        };
+
+        let here = address.ok_or_else(|| format!("line {}: code outside .ORIG/.END", line))?;
+        map.insert(SourceLine {
+            line,
+            address: here,
+            words,
+            is_instruction: !upper.starts_with('.'),
+        });
+        address = Some(here.wrapping_add(words));
+    }
+
+    Ok((map, symbols))
+}
+
+/// Parses an LC-3 numeric literal: `#-5`, `x3000`, `0x3000`, `b101` or plain decimal.
+pub fn parse_number(token: &str) -> Option<i32> {
+    let token = token.trim();
+    let token = token.strip_prefix('#').unwrap_or(token);
+    let (negative, body) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = body
+        .strip_prefix("0x")
+        .or_else(|| body.strip_prefix("0X"))
+        .or_else(|| body.strip_prefix('x'))
+        .or_else(|| body.strip_prefix('X'))
+    {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = body.strip_prefix('b').or_else(|| body.strip_prefix('B')) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        body.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Decodes a double-quoted `.STRINGZ` operand, handling the usual escapes.
+pub fn parse_string(token: &str) -> Option<Vec<u8>> {
+    let inner = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                'e' => '\x1b',
+                other => other,
+            }
+        } else {
+            c
+        };
+        bytes.push(c as u8);
+    }
+    Some(bytes)
+}
+
+/// Splits a source line into tokens, dropping `;` comments and keeping
+/// quoted strings intact.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '"' => {
+                current.push(c);
+                while let Some(c) = chars.next() {
+                    current.push(c);
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            current.push(escaped);
+                        }
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() || c == ',' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+pub fn is_directive(token: &str) -> bool {
+    token.starts_with('.')
+}
+
+pub fn is_mnemonic(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    if let Some(flags) = upper.strip_prefix("BR") {
+        return flags.chars().all(|c| "NZP".contains(c)) && flags.len() <= 3;
+    }
+    matches!(
+        upper.as_str(),
+        "ADD"
+            | "AND"
+            | "NOT"
+            | "JMP"
+            | "JSR"
+            | "JSRR"
+            | "LD"
+            | "LDI"
+            | "LDR"
+            | "LEA"
+            | "RET"
+            | "RTI"
+            | "NOP"
+            | "ST"
+            | "STI"
+            | "STR"
+            | "TRAP"
+            | "GETC"
+            | "OUT"
+            | "PUTS"
+            | "IN"
+            | "PUTSP"
+            | "HALT"
+    )
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,121 @@
+//! Instruction tracing: one line per executed instruction with its address,
+//! encoding, disassembly and the registers it changed.
+//!
+//! ```text
+//! x3000  1261  ADD R1,R1,#1            R1=x0001 COND=P
+//! x3001  0FFE  BRnzp LOOP
+//! ```
+
+use crate::disasm::disassemble_with;
+use crate::extension::Extension;
+use crate::lc3b;
+use crate::run::{Isa, Registers};
+use crate::symbols::SymbolTable;
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Bytes of trace kept before they are written out.
+const BUFFER_SIZE: usize = 1 << 16;
+
+type State = [u16; Registers::R_COUNT as usize];
+
+/// Where trace lines go.
+#[derive(Debug, Clone)]
+pub enum TraceSink {
+    Stderr,
+    File(Arc<File>),
+    /// Kept in memory, for tests and embedding.
+    Buffered(Vec<u8>),
+}
+
+/// Writes a trace of every instruction executed. Install one in `VM::tracer`.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    sink: TraceSink,
+    symbols: SymbolTable,
+    pending: String,
+}
+
+impl Tracer {
+    pub fn new(sink: TraceSink, symbols: SymbolTable) -> Self {
+        Self {
+            sink,
+            symbols,
+            pending: String::new(),
+        }
+    }
+
+    /// A tracer writing to `path`, or to stderr when `path` is `-`.
+    pub fn create(path: &str, symbols: SymbolTable) -> io::Result<Self> {
+        let sink = match path {
+            "-" => TraceSink::Stderr,
+            path => TraceSink::File(Arc::new(File::create(path)?)),
+        };
+        Ok(Self::new(sink, symbols))
+    }
+
+    /// Records the `isa` instruction `instr` at `pc`, which changed the
+    /// registers from `before` to `after`. `extension` disassembles the
+    /// reserved opcode.
+    pub fn record(
+        &mut self,
+        isa: Isa,
+        extension: Option<&dyn Extension>,
+        pc: u16,
+        instr: u16,
+        before: &State,
+        after: &State,
+    ) {
+        let text = match isa {
+            Isa::Lc3 => disassemble_with(pc, instr, &self.symbols, extension),
+            Isa::Lc3b => lc3b::disassemble(pc, instr, &self.symbols),
+        };
+        let mut line = format!("x{:04X}  {:04X}  {:<22}", pc, instr, text);
+        for index in 0..8 {
+            if before[index] != after[index] {
+                write!(line, "  R{}=x{:04X}", index, after[index]).unwrap();
+            }
+        }
+        let cond = Registers::R_COND as usize;
+        if before[cond] != after[cond] {
+            let flag = match after[cond] {
+                1 => "P",
+                2 => "Z",
+                _ => "N",
+            };
+            write!(line, "  COND={}", flag).unwrap();
+        }
+        self.pending.push_str(line.trim_end());
+        self.pending.push('\n');
+        if self.pending.len() >= BUFFER_SIZE || matches!(self.sink, TraceSink::Stderr) {
+            self.flush();
+        }
+    }
+
+    /// Writes out any buffered trace.
+    pub fn flush(&mut self) {
+        let text = std::mem::take(&mut self.pending);
+        let result = match &mut self.sink {
+            TraceSink::Stderr => io::stderr().write_all(text.as_bytes()),
+            TraceSink::File(file) => file.as_ref().write_all(text.as_bytes()),
+            TraceSink::Buffered(output) => {
+                output.extend_from_slice(text.as_bytes());
+                Ok(())
+            }
+        };
+        // A failing trace should not stop the program being traced
+        let _ = result;
+    }
+
+    /// Drains the trace written so far. Always empty unless buffered.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.flush();
+        match &mut self.sink {
+            TraceSink::Buffered(output) => std::mem::take(output),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::asm::{assemble, format_symbols, Section};
+    use virtual_vm::console::Console;
+    use virtual_vm::run::VM;
+    use virtual_vm::symbols::SymbolTable;
+
+    #[test]
+    fn test_encodes_every_instruction() {
+        let source = "\
+        .ORIG x3000
+START   ADD R1, R2, R3
+        add r1, r2, #-16
+        AND R0, R0, #0
+        AND R4, R5, R6
+        NOT R7, R1
+        BR START
+        BRnz START
+        BRp #0
+        LD R0, DATA
+        LDI R1, DATA
+        LEA R2, DATA
+        ST R3, DATA
+        STI R4, DATA
+        LDR R5, R6, #-32
+        STR R7, R0, #31
+        JMP R3
+        RET
+        JSR START
+        JSRR R4
+        RTI
+        NOP
+        TRAP x25
+        GETC
+        OUT
+        PUTS
+        IN
+        PUTSP
+        HALT
+DATA    .FILL START
+        .FILL #-1
+        .BLKW 2
+        .STRINGZ \"ok\"
+        .END
+";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.sections,
+            [Section {
+                origin: 0x3000,
+                words: vec![
+                    0x1283,
+                    0x12B0,
+                    0x5020,
+                    0x5946,
+                    0x9E7F,
+                    0x0FFA,
+                    0x0DF9,
+                    0x0200,
+                    0x2013,
+                    0xA212,
+                    0xE411,
+                    0x3610,
+                    0xB80F,
+                    0x6BA0,
+                    0x7E1F,
+                    0xC0C0,
+                    0xC1C0,
+                    0x4FEE,
+                    0x4100,
+                    0x8000,
+                    0x0000,
+                    0xF025,
+                    0xF020,
+                    0xF021,
+                    0xF022,
+                    0xF023,
+                    0xF024,
+                    0xF025,
+                    0x3000,
+                    0xFFFF,
+                    0,
+                    0,
+                    b'o' as u16,
+                    b'k' as u16,
+                    0,
+                ],
+            }]
+        );
+        assert_eq!(assembly.symbols.address_of("DATA"), Some(0x301C));
+        assert_eq!(
+            assembly.map.line_at(0x3001).map(|entry| entry.line),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        let errors = [
+            (
+                ".ORIG x3000\nADD R1, R1, R9\n.END",
+                "line 2: expected a register, found R9",
+            ),
+            (
+                ".ORIG x3000\nADD R1, R1, #16\n.END",
+                "line 2: #16 does not fit in 5 bits",
+            ),
+            (
+                ".ORIG x3000\nLD R0, NOWHERE\n.END",
+                "line 2: undefined label NOWHERE",
+            ),
+            (
+                ".ORIG x3000\nNOT R0, R1, R2\n.END",
+                "line 2: unexpected operand R2",
+            ),
+            (
+                ".ORIG x3000\nTRAP x100\n.END",
+                "line 2: bad trap vector x100",
+            ),
+            (
+                ".ORIG x3000\nBR FAR\n.BLKW 300\nFAR HALT\n.END",
+                "line 2: FAR is too far away (300 words)",
+            ),
+            ("; nothing here\n", "no .ORIG directive"),
+        ];
+        for (source, expected) in errors {
+            assert_eq!(
+                assemble(source).map(|_| ()),
+                Err(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_symbol_file_round_trips() {
+        let assembly = assemble(".ORIG x3000\nMAIN HALT\nDATA .FILL 1\n.END\n").unwrap();
+        let text = format_symbols(&assembly.symbols);
+        let parsed = SymbolTable::parse(&text);
+        assert_eq!(parsed.address_of("MAIN"), Some(0x3000));
+        assert_eq!(parsed.address_of("DATA"), Some(0x3001));
+    }
+
+    #[test]
+    fn test_each_orig_block_is_a_section() {
+        let assembly =
+            assemble(".ORIG x3000\nHALT\n.END\n.ORIG x4000\n.FILL x1234\n.END\n").unwrap();
+        assert_eq!(assembly.sections.len(), 2);
+        assert_eq!(assembly.sections[1].to_obj(), [0x40, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_assembled_image_runs() {
+        let assembly =
+            assemble(".ORIG x3000\nLEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"Hello\\n\"\n.END\n")
+                .unwrap();
+        let path = std::env::temp_dir().join(format!("lc3-asm-{}.obj", std::process::id()));
+        std::fs::write(&path, assembly.sections[0].to_obj()).unwrap();
+
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        let origin = vm.read_image(path.to_str().unwrap()).unwrap();
+        vm.registers_storage[8] = origin;
+        vm.execute().unwrap();
+        assert_eq!(vm.console.take_output(), b"Hello\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::budget::{Budget, BudgetKind};
+    use virtual_vm::console::Console;
+    use virtual_vm::run::{Fault, Registers, VM};
+
+    // LOOP ADD R1,R1,#1 ; ADD R2,R2,#-1 ; BRnzp LOOP
+    const SPIN: [u16; 3] = [0x1261, 0x14BF, 0x0FFD];
+
+    fn load(program: &[u16], budget: Budget) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.budget = Some(budget);
+        vm
+    }
+
+    fn instructions(max: u64) -> Budget {
+        Budget {
+            max_instructions: Some(max),
+            ..Budget::default()
+        }
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_infinite_loop() {
+        let mut vm = load(&SPIN, instructions(1000));
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::BudgetExhausted {
+                kind: BudgetKind::Instructions,
+                pc: 0x3001,
+                count: 1000,
+                low: 0x3000,
+                high: 0x3002,
+            }
+        );
+        assert_eq!(vm.instruction_count, 1000);
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 334);
+        assert!(fault
+            .to_string()
+            .contains("after 1000 instructions at x3001 (recently executing x3000-x3002"));
+    }
+
+    #[test]
+    fn test_instruction_limit_is_exact_for_every_engine() {
+        let engines = [
+            Engine::Threaded,
+            #[cfg(feature = "jit")]
+            Engine::Jit,
+        ];
+        for engine in engines {
+            for max in [1, 63, 64, 65, 1000, 100_000] {
+                let mut vm = load(&SPIN, instructions(max));
+                vm.engine = engine;
+                let fault = vm.execute().unwrap_err();
+                assert!(matches!(fault, Fault::BudgetExhausted { count, .. } if count == max));
+                assert_eq!(vm.instruction_count, max, "{:?}", engine);
+            }
+        }
+    }
+
+    #[test]
+    fn test_program_finishing_within_budget_halts() {
+        // ADD R1,R1,#5 ; HALT
+        let mut vm = load(&[0x1265, 0xF025], instructions(2));
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 5);
+    }
+
+    #[test]
+    fn test_time_limit_stops_infinite_loop() {
+        let mut vm = load(
+            &SPIN,
+            Budget {
+                max_time: Some(Duration::from_millis(50)),
+                ..Budget::default()
+            },
+        );
+        vm.engine = Engine::Threaded;
+        let fault = vm.execute().unwrap_err();
+        assert!(matches!(
+            fault,
+            Fault::BudgetExhausted {
+                kind: BudgetKind::Time,
+                low: 0x3000,
+                ..
+            }
+        ));
+        assert!(fault.to_string().starts_with("Time limit reached after"));
+    }
+}
@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::callstack::{CallFrame, CallStack};
+    use virtual_vm::console::Console;
+    use virtual_vm::history::History;
+    use virtual_vm::run::{Fault, Registers, VM};
+    use virtual_vm::symbols::SymbolTable;
+
+    // MAIN  JSR A ; HALT
+    // A     ADD R5,R7,#0 ; JSR B ; ADD R7,R5,#0 ; RET
+    // B     ADD R1,R1,#1 ; RET
+    const PROGRAM: [u16; 8] = [
+        0x4801, 0xF025, 0x1BE0, 0x4802, 0x1F60, 0xC1C0, 0x1261, 0xC1C0,
+    ];
+
+    fn load() -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.call_stack = Some(CallStack::new(0x3000));
+        vm
+    }
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        symbols.insert("A", 0x3002);
+        symbols.insert("B", 0x3006);
+        symbols
+    }
+
+    #[test]
+    fn test_backtrace_on_fault() {
+        let mut vm = load();
+        vm.memory[0x3007] = 0xD000;
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::IllegalOpcode {
+                pc: 0x3007,
+                instr: 0xD000
+            }
+        );
+        let call_stack = vm.call_stack.as_ref().unwrap();
+        assert_eq!(call_stack.depth(), 2);
+        assert_eq!(
+            call_stack.backtrace(0x3007, &symbols()),
+            "Backtrace:\n  #0  x3007  B+1\n  \
+             #1  x3003  A+1  (calls B, returns to x3004)\n  \
+             #2  x3000  MAIN  (calls A, returns to x3001)\n"
+        );
+    }
+    #[test]
+    fn test_call_graph() {
+        let mut vm = load();
+        vm.execute().unwrap();
+        let call_stack = vm.call_stack.as_ref().unwrap();
+        assert_eq!(call_stack.depth(), 0);
+        let dot = call_stack.to_dot(&symbols());
+        assert!(dot.starts_with("digraph calls {\n"));
+        assert!(dot.contains("    \"MAIN\" -> \"A\" [label=\"1\"];\n"));
+        assert!(dot.contains("    \"A\" -> \"B\" [label=\"1\"];\n"));
+    }
+    #[test]
+    fn test_step_back_restores_frames() {
+        let mut vm = load();
+        vm.history = Some(History::new(100));
+        // Into B, then back out to MAIN
+        for _ in 0..7 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.call_stack.as_ref().unwrap().depth(), 0);
+        // Undo A's RET and B's RET
+        for _ in 0..3 {
+            vm.step_back();
+        }
+        let frames = vm.call_stack.as_ref().unwrap().frames().to_vec();
+        assert_eq!(
+            frames.iter().map(|frame| frame.entry).collect::<Vec<_>>(),
+            vec![0x3002, 0x3006]
+        );
+        // Undo the JSR into B
+        vm.step_back();
+        vm.step_back();
+        assert_eq!(vm.call_stack.as_ref().unwrap().depth(), 1);
+    }
+    #[test]
+    fn test_unmatched_return_keeps_stack() {
+        let mut vm = load();
+        vm.step().unwrap();
+        // Clobber R7 so B's RET does not go back to a call site
+        vm.registers_storage[Registers::R_R7 as usize] = 0x4000;
+        vm.registers_storage[Registers::R_PC as usize] = 0x3007;
+        vm.step().unwrap();
+        assert_eq!(
+            vm.call_stack.as_ref().unwrap().frames(),
+            &[CallFrame {
+                call_site: 0x3000,
+                entry: 0x3002,
+                return_address: 0x3001
+            }]
+        );
+    }
+}
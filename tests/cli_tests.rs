@@ -0,0 +1,346 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use virtual_vm::cli::{RunOptions, EXIT_FAULT, EXIT_HALTED, EXIT_TIMEOUT, EXIT_USAGE};
+    use virtual_vm::console::Console;
+    use virtual_vm::debugger::Debugger;
+    use virtual_vm::repl::repl;
+    use virtual_vm::run::VM;
+    use virtual_vm::symbols::SymbolTable;
+    use virtual_vm::trace::{TraceSink, Tracer};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lc3-cli-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_image(dir: &Path, name: &str, words: &[u16]) -> PathBuf {
+        let path = dir.join(name);
+        let bytes: Vec<u8> = std::iter::once(0x3000u16)
+            .chain(words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn lc3(args: &[&str]) -> (i32, String, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_run_options() {
+        let args = [
+            "--pc",
+            "origin",
+            "-q",
+            "--trace",
+            "-",
+            "--max-instructions",
+            "50",
+            "a.obj",
+        ];
+        let options = RunOptions::parse(args.map(String::from)).unwrap();
+        assert_eq!(options.pc.as_deref(), Some("origin"));
+        assert!(options.quiet);
+        assert_eq!(options.trace.as_deref(), Some("-"));
+        assert_eq!(options.budget.max_instructions, Some(50));
+        assert_eq!(options.images, ["a.obj"]);
+
+        let errors = [
+            (vec!["--frobnicate", "a.obj"], "unknown option --frobnicate"),
+            (vec!["a.obj", "--pc"], "--pc needs a value"),
+            (
+                vec!["--max-instructions", "lots", "a.obj"],
+                "bad instruction count lots",
+            ),
+            (vec!["-q"], "no image to run"),
+        ];
+        for (args, expected) in errors {
+            let args = args.into_iter().map(String::from);
+            assert_eq!(RunOptions::parse(args), Err(expected.to_string()));
+        }
+
+        // The JIT is only offered when it was built in
+        let (code, usage, _) = lc3(&["run", "--help"]);
+        assert_eq!(code, EXIT_HALTED);
+        let engines = if cfg!(feature = "jit") {
+            "--engine interpreter|threaded|jit\n"
+        } else {
+            "--engine interpreter|threaded\n"
+        };
+        assert!(usage.contains(engines), "{}", usage);
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let dir = temp_dir("exit");
+        // LEA R0,#2 ; PUTS ; HALT ; "ok"
+        let halts = write_image(&dir, "halts.obj", &[0xE002, 0xF022, 0xF025, 0x6F, 0x6B, 0]);
+        let faults = write_image(&dir, "faults.obj", &[0xD000]);
+        // BRnzp #-1
+        let spins = write_image(&dir, "spins.obj", &[0x0FFF]);
+        let path = |image: &PathBuf| image.to_str().unwrap().to_string();
+
+        let (code, stdout, _) = lc3(&["run", "--quiet", &path(&halts)]);
+        assert_eq!((code, stdout.as_str()), (EXIT_HALTED, "ok"));
+        // Without a command the arguments are for `run`
+        let (code, stdout, _) = lc3(&[&path(&halts)]);
+        assert_eq!(code, EXIT_HALTED);
+        assert_eq!(stdout, "origin 12288\nokHalting the program...\n");
+
+        let (code, _, stderr) = lc3(&["-q", &path(&faults)]);
+        assert_eq!(code, EXIT_FAULT);
+        assert!(stderr.starts_with("Invalid opcode encountered: 0xD000 at x3000\n"));
+        let (code, _, _) = lc3(&["-q", "--detect-loops", &path(&spins)]);
+        assert_eq!(code, EXIT_FAULT);
+        let (code, _, _) = lc3(&["-q", "--max-instructions", "100", &path(&spins)]);
+        assert_eq!(code, EXIT_TIMEOUT);
+
+        let (code, _, stderr) = lc3(&["run", "--pc", "NOWHERE", &path(&halts)]);
+        assert_eq!(code, EXIT_USAGE);
+        assert!(stderr.contains("Bad start address: NOWHERE"));
+        let (code, _, stderr) = lc3(&["-q", &path(&faults), &path(&halts)]);
+        assert_eq!(code, EXIT_HALTED);
+        assert!(stderr.contains(&format!(
+            "Warning: {}: x3000-x3000 overwrites part of {}",
+            path(&halts),
+            path(&faults)
+        )));
+        let (code, _, _) = lc3(&["-q", "--strict-load", &path(&faults), &path(&halts)]);
+        assert_eq!(code, EXIT_USAGE);
+        let missing = dir.join("missing.obj");
+        assert_eq!(lc3(&[missing.to_str().unwrap()]).0, EXIT_USAGE);
+        assert_eq!(lc3(&["disasm"]).0, EXIT_USAGE);
+        let (code, _, usage) = lc3(&[]);
+        assert_eq!(code, EXIT_USAGE);
+        assert!(usage.contains("lc3 --dap"), "{}", usage);
+
+        // grade reports unreadable inputs and bad arguments like the rest
+        let spec = dir.join("halts.json");
+        fs::write(&spec, r#"{"cases": [{"name": "ok", "output": "ok"}]}"#).unwrap();
+        let (code, stdout, _) = lc3(&["grade", "--help"]);
+        assert_eq!(code, EXIT_HALTED);
+        assert!(stdout.starts_with("Usage: lc3 grade"), "{}", stdout);
+        assert_eq!(lc3(&["grade", &path(&halts)]).0, EXIT_USAGE);
+        assert_eq!(lc3(&["grade", &path(&halts), "--junit"]).0, EXIT_USAGE);
+        let (code, _, stderr) = lc3(&["grade", missing.to_str().unwrap(), &path(&spec)]);
+        assert_eq!(code, EXIT_USAGE);
+        assert!(stderr.starts_with("Failed to load image"), "{}", stderr);
+        assert_eq!(lc3(&["grade", &path(&halts), &path(&spec)]).0, EXIT_HALTED);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_asm_disasm_and_dump() {
+        let dir = temp_dir("tools");
+        let source = dir.join("hi.asm");
+        fs::write(
+            &source,
+            ".ORIG x3000\nLEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"Hi\"\n.END\n",
+        )
+        .unwrap();
+        let (code, _, _) = lc3(&["asm", source.to_str().unwrap()]);
+        assert_eq!(code, EXIT_HALTED);
+        let image = dir.join("hi.obj");
+        assert_eq!(
+            fs::read(&image).unwrap(),
+            [0x30, 0x00, 0xE0, 0x02, 0xF0, 0x22, 0xF0, 0x25, 0x00, 0x48, 0x00, 0x69, 0, 0]
+        );
+
+        let (code, stdout, _) = lc3(&["disasm", image.to_str().unwrap()]);
+        assert_eq!(code, EXIT_HALTED);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "x3000  E002                   LEA R0, MSG");
+        assert_eq!(lines[3], "x3003  0048  MSG              .FILL x0048");
+
+        let (_, stdout, _) = lc3(&["dump", "--from", "x3003", image.to_str().unwrap()]);
+        assert_eq!(
+            stdout,
+            "x3003  0048 0069 0000                           Hi.\n"
+        );
+
+        // lc3tools text images, chosen by the output's extension
+        let text = dir.join("hi.hex");
+        let (code, _, _) = lc3(&[
+            "asm",
+            "-o",
+            text.to_str().unwrap(),
+            source.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_HALTED);
+        assert!(fs::read_to_string(&text)
+            .unwrap()
+            .starts_with("3000\nE002\nF022\n"));
+        let (code, stdout, _) = lc3(&["-q", text.to_str().unwrap()]);
+        assert_eq!((code, stdout.as_str()), (EXIT_HALTED, "Hi"));
+        let bin = dir.join("hi.bin");
+        lc3(&["dump", "-o", bin.to_str().unwrap(), text.to_str().unwrap()]);
+        assert!(fs::read_to_string(&bin)
+            .unwrap()
+            .starts_with("0011000000000000\n"));
+        let (_, stdout, _) = lc3(&["disasm", bin.to_str().unwrap()]);
+        assert!(stdout.starts_with("x3000  E002"));
+
+        // Intel HEX and S-records for FPGA toolchains
+        for name in ["hi.ihex", "hi.srec"] {
+            let records = dir.join(name);
+            lc3(&[
+                "asm",
+                "-o",
+                records.to_str().unwrap(),
+                source.to_str().unwrap(),
+            ]);
+            let (code, stdout, _) = lc3(&["-q", records.to_str().unwrap()]);
+            assert_eq!((code, stdout.as_str()), (EXIT_HALTED, "Hi"), "{}", name);
+        }
+
+        let broken = dir.join("broken.asm");
+        fs::write(&broken, ".ORIG x3000\nLD R0, NOWHERE\n.END\n").unwrap();
+        let (code, _, stderr) = lc3(&["asm", broken.to_str().unwrap()]);
+        assert_eq!(code, EXIT_FAULT);
+        assert!(stderr.contains("line 2: undefined label NOWHERE"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dump_on_stop_and_diff() {
+        let dir = temp_dir("dump");
+        // ST R0,#1 ; HALT ; .FILL 0, with R0 set to x0041
+        let image = write_image(&dir, "store.obj", &[0x3001, 0xF025, 0]);
+        let dump = dir.join("after.txt");
+        let (code, _, _) = lc3(&[
+            "-q",
+            "--reg",
+            "R0=x41",
+            "--dump",
+            dump.to_str().unwrap(),
+            "--dump-range",
+            "x3000-x3002",
+            image.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_HALTED);
+        assert_eq!(
+            fs::read_to_string(&dump).unwrap(),
+            "x3000  3001 F025 0041                           ..A\n"
+        );
+
+        let (code, stdout, _) = lc3(&[
+            "dump",
+            "--diff",
+            image.to_str().unwrap(),
+            dump.to_str().unwrap(),
+        ]);
+        assert_eq!((code, stdout.as_str()), (1, "x3002  0000 -> 0041\n"));
+        let (code, _, _) = lc3(&[
+            "dump",
+            "--diff",
+            dump.to_str().unwrap(),
+            dump.to_str().unwrap(),
+        ]);
+        assert_eq!(code, 0);
+
+        // The default dump is all 65536 words
+        let full = dir.join("full.obj");
+        let (code, _, _) = lc3(&[
+            "-q",
+            "--reg",
+            "R0=x41",
+            "--dump",
+            full.to_str().unwrap(),
+            image.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_HALTED);
+        assert_eq!(fs::read(&full).unwrap().len(), 2 + 2 * 65536);
+        let (code, stdout, _) = lc3(&["dump", full.to_str().unwrap()]);
+        assert_eq!(code, EXIT_HALTED);
+        assert_eq!(
+            stdout,
+            "x0000  0000 0000 0000 0000 0000 0000 0000 0000  ........\n\
+             *\n\
+             x3000  3001 F025 0041 0000 0000 0000 0000 0000  ..A.....\n\
+             x3008  0000 0000 0000 0000 0000 0000 0000 0000  ........\n\
+             *\n\
+             xFFF8  0000 0000 0000 0000 0000 0000 0000 0000  ........\n"
+        );
+        let (code, stdout, _) = lc3(&[
+            "dump",
+            "--from",
+            "x3000",
+            "--to",
+            "x3002",
+            full.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_HALTED);
+        assert_eq!(
+            stdout,
+            "x3000  3001 F025 0041                           ..A\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace_shows_changed_registers() {
+        // AND R1,R1,#0 ; ADD R1,R1,#5 ; HALT
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3003].copy_from_slice(&[0x5260, 0x1265, 0xF025]);
+        vm.registers_storage[8] = 0x3000;
+        vm.tracer = Some(Tracer::new(
+            TraceSink::Buffered(Vec::new()),
+            SymbolTable::new(),
+        ));
+        vm.execute().unwrap();
+        let trace = vm.tracer.as_mut().unwrap().take_output();
+        assert_eq!(
+            String::from_utf8(trace).unwrap(),
+            "x3000  5260  AND R1, R1, #0\n\
+             x3001  1265  ADD R1, R1, #5          R1=x0005  COND=P\n\
+             x3002  F025  HALT                    R7=x3003\n"
+        );
+    }
+
+    #[test]
+    fn test_debugger_commands() {
+        // LOOP ADD R1,R1,#1 ; ADD R2,R1,#-3 ; BRn LOOP ; HALT
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.history = Some(virtual_vm::history::History::new(100));
+        vm.memory[0x3000..0x3004].copy_from_slice(&[0x1261, 0x147D, 0x09FD, 0xF025]);
+        vm.registers_storage[8] = 0x3000;
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3000);
+        let mut debugger = Debugger::new(vm);
+
+        let commands = "b x3002\nc\nc\nset R1 7\nback\nr\nbogus\nd x3002\nc\n";
+        let mut output = Vec::new();
+        repl(&mut debugger, &symbols, commands.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = "\
+=> x3000  LOOP             ADD R1, R1, #1
+(lc3) Breakpoint at x3002
+(lc3) Breakpoint at x3002
+=>*x3002                   BRn LOOP
+(lc3) Breakpoint at x3002
+=>*x3002                   BRn LOOP
+(lc3) (lc3) => x3001                   ADD R2, R1, #-3
+(lc3) R0 x0000  R1 x0002  R2 xFFFE  R3 x0000
+R4 x0000  R5 x0000  R6 x0000  R7 x0000
+PC x3001  COND P  (4 instructions executed)
+(lc3) unknown command bogus (try help)
+(lc3) (lc3) Program halted after 10 instructions
+(lc3) \n";
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::coverage::{BranchCounts, Coverage};
+    use virtual_vm::run::{Isa, Registers, VM};
+    use virtual_vm::symbols::{scan_asm, SymbolTable};
+
+    const SOURCE: &str = "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #3
+LOOP    JSR SUB
+        ADD R0, R0, #-1
+        BRp LOOP
+        HALT
+        BRz LOOP
+SUB     ADD R1, R1, #1
+        RET
+        .END
+";
+    const PROGRAM: [u16; 9] = [
+        0x5020, 0x1023, 0x4804, 0x103F, 0x03FD, 0xF025, 0x05FB, 0x1261, 0xC1C0,
+    ];
+
+    fn run() -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.coverage = Some(Coverage::new());
+        vm.execute().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_records_hits_and_branch_directions() {
+        let vm = run();
+        let coverage = vm.coverage.as_ref().unwrap();
+        assert_eq!(coverage.hits(0x3002), 3);
+        assert_eq!(coverage.hits(0x3006), 0);
+        assert_eq!(
+            coverage.branch(0x3004),
+            Some(BranchCounts {
+                taken: 2,
+                not_taken: 1
+            })
+        );
+        assert_eq!(coverage.branch(0x3006), None);
+        assert_eq!(coverage.executed().count(), 8);
+    }
+    #[test]
+    fn test_lcov_output() {
+        let vm = run();
+        let (map, _) = scan_asm(SOURCE).unwrap();
+        let lcov = vm
+            .coverage
+            .as_ref()
+            .unwrap()
+            .lcov(&vm.memory.contents(), &map, "loop.asm");
+        let expected = "TN:\nSF:loop.asm\n\
+            DA:2,1\nDA:3,1\nDA:4,3\nDA:5,3\n\
+            DA:6,3\nBRDA:6,0,0,2\nBRDA:6,0,1,1\n\
+            DA:7,1\n\
+            DA:8,0\nBRDA:8,0,0,-\nBRDA:8,0,1,-\n\
+            DA:9,3\nDA:10,3\n\
+            BRF:4\nBRH:2\nLF:9\nLH:8\nend_of_record\n";
+        assert_eq!(lcov, expected);
+    }
+    #[test]
+    fn test_address_report() {
+        let vm = run();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3002);
+        let report =
+            vm.coverage
+                .as_ref()
+                .unwrap()
+                .address_report(Isa::Lc3, &vm.memory.contents(), &symbols);
+        assert!(
+            report.starts_with("Coverage: 8 addresses executed, 2 of 2 branch directions taken\n")
+        );
+        assert!(report.contains("BRp LOOP  (taken 2, not taken 1)"));
+        assert!(!report.contains("x3006"));
+    }
+}
@@ -0,0 +1,205 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::io::{self, BufReader, Read, Write};
+    use std::path::PathBuf;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use virtual_vm::dap::{read_message, DapServer};
+
+    const SOURCE: &str = "        .ORIG x3000\n        LEA R0, MSG\n        PUTS\n        HALT\nMSG     .STRINGZ \"Hi\"\n        .END\n";
+    const IMAGE: [u16; 7] = [0x3000, 0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x0000];
+
+    /// Forwards everything the server writes to the test thread.
+    struct Pipe(Sender<Vec<u8>>);
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.send(buf.to_vec()).ok();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reassembles the byte chunks from `Pipe` into a readable stream.
+    struct ChunkReader {
+        chunks: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.chunks.recv() {
+                    Ok(chunk) => self.pending = chunk,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    struct Client {
+        requests: Sender<Value>,
+        responses: BufReader<ChunkReader>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (requests, incoming) = mpsc::channel();
+            let (chunks_out, chunks) = mpsc::channel();
+            thread::spawn(move || DapServer::new(Pipe(chunks_out)).serve(incoming));
+            Client {
+                requests,
+                responses: BufReader::new(ChunkReader {
+                    chunks,
+                    pending: Vec::new(),
+                }),
+                seq: 0,
+            }
+        }
+
+        fn send(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            self.requests
+                .send(json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments }))
+                .unwrap();
+            loop {
+                let message = self.next();
+                if message["type"] == "response" && message["request_seq"] == self.seq {
+                    return message;
+                }
+            }
+        }
+
+        fn next(&mut self) -> Value {
+            read_message(&mut self.responses).unwrap().unwrap()
+        }
+
+        fn wait_for(&mut self, event: &str) -> Value {
+            loop {
+                let message = self.next();
+                if message["event"] == event {
+                    return message;
+                }
+            }
+        }
+    }
+
+    fn write_program(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lc3-dap-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("hello.obj");
+        let bytes: Vec<u8> = IMAGE.iter().flat_map(|w| w.to_be_bytes()).collect();
+        fs::write(&program, bytes).unwrap();
+        let source = dir.join("hello.asm");
+        fs::write(&source, SOURCE).unwrap();
+        (program, source)
+    }
+
+    #[test]
+    fn test_breakpoint_session() {
+        let (program, source) = write_program("session");
+        let mut client = Client::start();
+
+        let init = client.send("initialize", json!({ "adapterID": "lc3" }));
+        assert_eq!(init["body"]["supportsConfigurationDoneRequest"], true);
+        assert_eq!(
+            client.send("launch", json!({ "program": program }))["success"],
+            true
+        );
+        client.wait_for("initialized");
+
+        let bps = client.send(
+            "setBreakpoints",
+            json!({ "source": { "path": source }, "breakpoints": [{ "line": 3 }, { "line": 1 }] }),
+        );
+        assert_eq!(bps["body"]["breakpoints"][0]["verified"], true);
+        // Line 1 is the .ORIG, which resolves to the first instruction on line 2
+        assert_eq!(bps["body"]["breakpoints"][1]["line"], 2);
+
+        client.send("configurationDone", Value::Null);
+        // A breakpoint on the entry point is hit before anything executes
+        let stopped = client.wait_for("stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        client.send("continue", json!({ "threadId": 1 }));
+        assert_eq!(client.wait_for("stopped")["body"]["reason"], "breakpoint");
+
+        let trace = client.send("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 3);
+        assert_eq!(
+            trace["body"]["stackFrames"][0]["instructionPointerReference"],
+            "0x3001"
+        );
+
+        let registers = client.send("variables", json!({ "variablesReference": 1 }));
+        assert_eq!(registers["body"]["variables"][0]["name"], "R0");
+        assert_eq!(registers["body"]["variables"][0]["value"], "x3003 (12291)");
+
+        client.send("continue", json!({ "threadId": 1 }));
+        let output = client.wait_for("output");
+        assert_eq!(output["body"]["output"], "HiHalting the program...\n");
+        client.wait_for("exited");
+        client.send("disconnect", Value::Null);
+    }
+    #[test]
+    fn test_stop_on_entry_and_step() {
+        let (program, _) = write_program("step");
+        let mut client = Client::start();
+        client.send("initialize", json!({}));
+        client.send("launch", json!({ "program": program, "stopOnEntry": true }));
+        client.send("configurationDone", Value::Null);
+        assert_eq!(client.wait_for("stopped")["body"]["reason"], "entry");
+
+        client.send("next", json!({ "threadId": 1 }));
+        assert_eq!(client.wait_for("stopped")["body"]["reason"], "step");
+        let trace = client.send("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["body"]["stackFrames"][0]["name"], "x3001");
+
+        let memory = client.send("evaluate", json!({ "expression": "MSG" }));
+        assert_eq!(memory["body"]["result"], "[x3003] = x0048 (72)");
+        client.send("disconnect", Value::Null);
+    }
+    #[test]
+    fn test_step_back() {
+        let (program, source) = write_program("back");
+        let mut client = Client::start();
+        let init = client.send("initialize", json!({}));
+        assert_eq!(init["body"]["supportsStepBack"], true);
+        client.send("launch", json!({ "program": program, "source": source }));
+        client.send(
+            "setBreakpoints",
+            json!({ "source": { "path": source }, "breakpoints": [{ "line": 4 }] }),
+        );
+        client.send("configurationDone", Value::Null);
+        assert_eq!(client.wait_for("stopped")["body"]["reason"], "breakpoint");
+
+        client.send("stepBack", json!({ "threadId": 1 }));
+        assert_eq!(client.wait_for("stopped")["body"]["reason"], "step");
+        let trace = client.send("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 3);
+
+        client.send("reverseContinue", json!({ "threadId": 1 }));
+        let stopped = client.wait_for("stopped");
+        assert_eq!(
+            stopped["body"]["text"],
+            "Reached the start of recorded history"
+        );
+        client.send("disconnect", Value::Null);
+    }
+    #[test]
+    fn test_launch_missing_program_fails() {
+        let mut client = Client::start();
+        client.send("initialize", json!({}));
+        let launch = client.send("launch", json!({ "program": "/nonexistent/prog.obj" }));
+        assert_eq!(launch["success"], false);
+        client.send("disconnect", Value::Null);
+    }
+}
@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::debugger::{Debugger, StopReason};
+    use virtual_vm::history::History;
+    use virtual_vm::run::{Fault, Isa, Registers, Step, VM};
+    use virtual_vm::symbols::{scan_asm, SymbolTable};
+
+    // JSR SUB; ADD R1,R1,#1; HALT; SUB: ADD R2,R2,#1; RET
+    const CALL_PROGRAM: [u16; 5] = [0x4802, 0x1261, 0xF025, 0x14A1, 0xC1C0];
+
+    fn load(program: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    #[test]
+    fn test_step_runs_until_halt() {
+        let mut vm = load(&CALL_PROGRAM);
+        vm.execute().unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 1);
+        assert_eq!(vm.registers_storage[Registers::R_R2 as usize], 1);
+        assert_eq!(vm.console.take_output(), b"Halting the program...\n");
+    }
+    #[test]
+    fn test_fault_leaves_pc_on_instruction() {
+        let mut vm = load(&[0x1261, 0xD000]);
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(
+            vm.step(),
+            Err(Fault::IllegalOpcode {
+                pc: 0x3001,
+                instr: 0xD000
+            })
+        );
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3001);
+    }
+    #[test]
+    fn test_getc_without_input_faults() {
+        let mut vm = load(&[0xF020]);
+        assert_eq!(vm.step(), Err(Fault::EndOfInput { pc: 0x3000 }));
+        vm.console.push_input(b"A");
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(vm.registers_storage[Registers::R_R0 as usize], 'A' as u16);
+    }
+    #[test]
+    fn test_breakpoint_and_continue() {
+        let mut debugger = Debugger::new(load(&CALL_PROGRAM));
+        debugger.add_breakpoint(0x3003);
+        debugger.continue_();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Breakpoint(0x3003)));
+        assert_eq!(debugger.call_depth(), 1);
+        debugger.continue_();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Halted));
+    }
+    #[test]
+    fn test_step_over_and_out() {
+        let mut debugger = Debugger::new(load(&CALL_PROGRAM));
+        debugger.step_over();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!(debugger.pc(), 0x3001);
+        assert_eq!(debugger.vm.registers_storage[Registers::R_R2 as usize], 1);
+
+        let mut debugger = Debugger::new(load(&CALL_PROGRAM));
+        debugger.step_into();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!(debugger.pc(), 0x3003);
+        debugger.step_out();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!(debugger.pc(), 0x3001);
+
+        // The same program as LC-3b, at byte x3000 (word x1800)
+        let lc3b = || {
+            let mut vm = VM::new();
+            vm.isa = Isa::Lc3b;
+            vm.console = Console::buffered(b"");
+            vm.memory.load(0x1800, &CALL_PROGRAM).unwrap();
+            vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+            vm
+        };
+        let mut debugger = Debugger::new(lc3b());
+        debugger.step_over();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!(debugger.pc(), 0x3002);
+        assert_eq!(debugger.vm.registers_storage[Registers::R_R2 as usize], 1);
+
+        let mut vm = lc3b();
+        vm.history = Some(History::new(10));
+        let mut debugger = Debugger::new(vm);
+        debugger.step_into();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!((debugger.pc(), debugger.call_depth()), (0x3006, 1));
+        debugger.step_out();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Step));
+        assert_eq!((debugger.pc(), debugger.call_depth()), (0x3002, 0));
+        assert_eq!(debugger.step_back(), StopReason::Step);
+        assert_eq!((debugger.pc(), debugger.call_depth()), (0x3008, 1));
+    }
+    #[test]
+    fn test_run_budget_returns_none() {
+        // BRnzp #-1 spins forever
+        let mut debugger = Debugger::new(load(&[0x0FFF]));
+        debugger.continue_();
+        assert_eq!(debugger.run(100), None);
+        assert!(debugger.is_running());
+    }
+    #[test]
+    fn test_symbol_table_parse() {
+        let text = "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\tSTART             3000\n//\tLOOP              3004\n";
+        let symbols = SymbolTable::parse(text);
+        assert_eq!(symbols.address_of("LOOP"), Some(0x3004));
+        assert_eq!(symbols.describe(0x3006), "LOOP+2");
+        assert_eq!(symbols.describe(0x2000), "x2000");
+    }
+    #[test]
+    fn test_scan_asm_source_map() {
+        let source = "; hello\n        .ORIG x3000\n        LEA R0, MSG\n\n        PUTS\n        HALT\nMSG     .STRINGZ \"Hi\"\n        .END\n";
+        let (map, symbols) = scan_asm(source).unwrap();
+        assert_eq!(symbols.address_of("MSG"), Some(0x3003));
+        assert_eq!(map.resolve_line(4).map(|l| l.address), Some(0x3001));
+        assert_eq!(map.line_at(0x3002).map(|l| l.line), Some(6));
+        assert_eq!(map.line_at(0x3005).map(|l| l.line), Some(7));
+        assert_eq!(map.line_at(0x3006), None);
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::decode::{decode, DecodedInstr};
+    use virtual_vm::run::{Registers, VM};
+
+    fn load(program: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    #[test]
+    fn test_decode_resolves_pc_relative_addresses() {
+        assert_eq!(
+            decode(0x3000, 0x0FFD),
+            DecodedInstr::Br {
+                nzp: 0x7,
+                target: 0x2FFE
+            }
+        );
+        assert_eq!(
+            decode(0x3000, 0x1A7F),
+            DecodedInstr::AddImm {
+                dr: 5,
+                sr1: 1,
+                imm: 0xFFFF
+            }
+        );
+        assert_eq!(decode(0x3000, 0x4FFF), DecodedInstr::Jsr { target: 0x3000 });
+        assert_eq!(
+            decode(0x3000, 0x7A7E),
+            DecodedInstr::Str {
+                sr: 5,
+                base: 1,
+                offset: 0xFFFE
+            }
+        );
+        assert_eq!(
+            decode(0x3000, 0xD123),
+            DecodedInstr::Illegal { instr: 0xD123 }
+        );
+    }
+    #[test]
+    fn test_self_modifying_code_invalidates_cache() {
+        // LOOP ADD R1,R1,#1 ; LD R2,NEW ; ST R2,LOOP ; ADD R3,R3,#-1 ; BRp LOOP ; HALT
+        // NEW  ADD R1,R1,#4
+        let mut vm = load(&[0x1261, 0x2404, 0x35FD, 0x16FF, 0x03FB, 0xF025, 0x1264]);
+        vm.registers_storage[Registers::R_R3 as usize] = 2;
+        vm.execute().unwrap();
+        // The first pass runs the original ADD #1, the second the patched ADD #4
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 5);
+    }
+    #[test]
+    fn test_direct_memory_writes_are_seen() {
+        let mut vm = load(&[0x1261, 0x0FFE]);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.memory[0x3000] = 0x1262;
+        vm.step().unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 3);
+    }
+    #[test]
+    fn test_cache_can_be_disabled() {
+        let mut cached = load(&[0x1261, 0x1262, 0xF025]);
+        let mut uncached = cached.clone();
+        uncached.decode_cache.set_enabled(false);
+        cached.execute().unwrap();
+        uncached.execute().unwrap();
+        assert!(!uncached.decode_cache.is_enabled());
+        assert_eq!(cached.registers_storage, uncached.registers_storage);
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::dump::{format_diff, hex_dump, parse_range, Difference, Dump, DumpFormat};
+    use virtual_vm::run::VM;
+    use virtual_vm::symbols::SymbolTable;
+
+    fn vm() -> VM {
+        let mut vm = VM::new();
+        vm.memory[0x3000..0x3006].copy_from_slice(&[0xE002, 0xF022, 0xF025, 0x48, 0x69, 0]);
+        vm
+    }
+
+    #[test]
+    fn test_hex_dump_collapses_repeated_lines() {
+        let text = hex_dump(&vm().memory.contents(), 0x3000, 0x302B);
+        assert_eq!(
+            text,
+            "x3000  E002 F022 F025 0048 0069 0000 0000 0000  ...Hi...\n\
+             x3008  0000 0000 0000 0000 0000 0000 0000 0000  ........\n\
+             *\n\
+             x3028  0000 0000 0000 0000                      ....\n"
+        );
+    }
+
+    #[test]
+    fn test_every_format_reads_back() {
+        let dump = vm().dump(0x3000, 0x3040);
+        for format in [
+            DumpFormat::Hex,
+            DumpFormat::Obj,
+            DumpFormat::Raw,
+            DumpFormat::Lc3Hex,
+            DumpFormat::Lc3Bin,
+        ] {
+            let bytes = dump.to_bytes(format);
+            assert_eq!(
+                Dump::parse(&bytes, format, 0x3000),
+                Ok(dump.clone()),
+                "{:?}",
+                format
+            );
+        }
+        assert_eq!(
+            dump.to_bytes(DumpFormat::Obj)[..4],
+            [0x30, 0x00, 0xE0, 0x02]
+        );
+        assert_eq!(dump.to_bytes(DumpFormat::Raw)[..2], [0xE0, 0x02]);
+        assert!(dump
+            .to_bytes(DumpFormat::Lc3Hex)
+            .starts_with(b"3000\nE002\n"));
+        assert!(dump
+            .to_bytes(DumpFormat::Lc3Bin)
+            .starts_with(b"0011000000000000\n1110000000000010\n"));
+    }
+
+    #[test]
+    fn test_bad_dumps_are_rejected() {
+        assert_eq!(
+            Dump::parse(b"x3000  0001\nx3002  0002\n", DumpFormat::Hex, 0),
+            Err("line 2: expected x3001 next".to_string())
+        );
+        assert_eq!(
+            Dump::parse(b"3000 0001\n", DumpFormat::Hex, 0),
+            Err("line 1: expected an address such as x3000".to_string())
+        );
+        assert!(Dump::parse(&[0x30, 0x00, 0x12], DumpFormat::Obj, 0).is_err());
+        assert!(Dump::parse(&[0xFF, 0xFF, 0, 1, 0, 2], DumpFormat::Obj, 0).is_err());
+    }
+
+    #[test]
+    fn test_diff_reports_changed_and_missing_words() {
+        let before = vm().dump(0x3000, 0x3003);
+        let mut changed = vm();
+        changed.memory[0x3001] = 0xF021;
+        let after = changed.dump(0x3001, 0x3004);
+        assert_eq!(
+            before.diff(&after),
+            [
+                Difference {
+                    address: 0x3000,
+                    before: Some(0xE002),
+                    after: None,
+                },
+                Difference {
+                    address: 0x3001,
+                    before: Some(0xF022),
+                    after: Some(0xF021),
+                },
+                Difference {
+                    address: 0x3004,
+                    before: None,
+                    after: Some(0x69),
+                },
+            ]
+        );
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        let text = format_diff(&before.diff(&after), &symbols);
+        assert!(text.starts_with("x3000  E002 -> ----  MAIN\nx3001  F022 -> F021  MAIN+1\n"));
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        assert_eq!(parse_range("MAIN-x30FF", &symbols), Some((0x3000, 0x30FF)));
+        assert_eq!(parse_range("x3001-x3000", &symbols), None);
+        assert_eq!(parse_range("x3000", &symbols), None);
+        assert_eq!(DumpFormat::from_path("out.obj"), DumpFormat::Obj);
+        assert_eq!(DumpFormat::from_path("out.raw"), DumpFormat::Raw);
+        assert_eq!(DumpFormat::from_path("out.txt"), DumpFormat::Hex);
+        assert_eq!(DumpFormat::from_path("out.hex"), DumpFormat::Lc3Hex);
+        assert_eq!(
+            DumpFormat::detect("out.bin", b"0011000000000000\n"),
+            DumpFormat::Lc3Bin
+        );
+        assert_eq!(
+            DumpFormat::detect("out.bin", &[0x30, 0x00]),
+            DumpFormat::Obj
+        );
+        assert_eq!("lc3bin".parse(), Ok(DumpFormat::Lc3Bin));
+        assert!("bin".parse::<DumpFormat>().is_err());
+    }
+}
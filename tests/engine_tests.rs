@@ -0,0 +1,111 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::callstack::CallStack;
+    use virtual_vm::console::Console;
+    use virtual_vm::run::{Fault, Registers, VM};
+
+    fn load(program: &[u16], input: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(input);
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    /// Runs `vm` under both engines and checks they end in the same state.
+    fn run_both(vm: VM) -> (VM, Result<(), Fault>) {
+        let mut interpreted = vm.clone();
+        let mut threaded = vm;
+        threaded.engine = Engine::Threaded;
+        let expected = interpreted.execute();
+        let result = threaded.execute();
+        assert_eq!(result, expected);
+        assert_eq!(threaded.registers_storage, interpreted.registers_storage);
+        assert_eq!(threaded.instruction_count, interpreted.instruction_count);
+        assert!(threaded.memory.contents() == interpreted.memory.contents());
+        assert_eq!(
+            threaded.console.take_output(),
+            interpreted.console.take_output()
+        );
+        assert_eq!(
+            threaded.console.pending_input(),
+            interpreted.console.pending_input()
+        );
+        (threaded, result)
+    }
+
+    #[test]
+    fn test_loop_matches_interpreter() {
+        // AND R1,R1,#0 ; LD R0,COUNT ; LOOP ADD R1,R1,R0 ; ADD R0,R0,#-1 ; BRp LOOP ; HALT ; COUNT .FILL #100
+        let (vm, _) = run_both(load(
+            &[0x5260, 0x2004, 0x1240, 0x103F, 0x03FD, 0xF025, 100],
+            b"",
+        ));
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 5050);
+        assert!(vm.blocks.compiled() >= 2);
+    }
+    #[test]
+    fn test_store_into_running_block() {
+        // LD R2,NEW ; ST R2,PATCH ; PATCH ADD R1,R1,#1 ; HALT ; NEW ADD R1,R1,#4
+        let (vm, _) = run_both(load(&[0x2403, 0x3400, 0x1261, 0xF025, 0x1264], b""));
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 4);
+        assert_eq!(vm.blocks.invalidated(), 1);
+    }
+    #[test]
+    fn test_patched_loop_body_is_rebuilt() {
+        // LOOP ADD R1,R1,#1 ; LD R2,NEW ; ST R2,LOOP ; ADD R3,R3,#-1 ; BRp LOOP ; HALT
+        // NEW  ADD R1,R1,#4
+        let mut vm = load(
+            &[0x1261, 0x2404, 0x35FD, 0x16FF, 0x03FB, 0xF025, 0x1264],
+            b"",
+        );
+        vm.registers_storage[Registers::R_R3 as usize] = 3;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 9);
+    }
+    #[test]
+    fn test_fault_in_block_matches_interpreter() {
+        // ADD R1,R1,#1 ; GETC ; ADD R1,R1,#1 ; GETC ; HALT
+        let (vm, result) = run_both(load(&[0x1261, 0xF020, 0x1261, 0xF020, 0xF025], b"a"));
+        assert_eq!(result, Err(Fault::EndOfInput { pc: 0x3003 }));
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3003);
+    }
+    #[test]
+    fn test_keyboard_polling_matches_interpreter() {
+        // POLL LDI R0,KBSR ; BRzp POLL ; LDI R0,KBDR ; OUT ; ADD R1,R1,#-1 ; BRp POLL ; HALT
+        let mut vm = load(
+            &[
+                0xA007, 0x07FE, 0xA006, 0xF021, 0x127F, 0x03FA, 0xF025, 0, 0xFE00, 0xFE02,
+            ],
+            b"xyz",
+        );
+        vm.registers_storage[Registers::R_R1 as usize] = 3;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.registers_storage[Registers::R_R0 as usize], 'z' as u16);
+    }
+    #[test]
+    fn test_calls_match_interpreter() {
+        // MAIN JSR A ; HALT ; A ADD R5,R7,#0 ; JSR B ; ADD R7,R5,#0 ; RET ; B ADD R1,R1,#1 ; RET
+        let mut vm = load(
+            &[
+                0x4801, 0xF025, 0x1BE0, 0x4802, 0x1F60, 0xC1C0, 0x1261, 0xC1C0,
+            ],
+            b"",
+        );
+        vm.call_stack = Some(CallStack::new(0x3000));
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.call_stack.as_ref().unwrap().edges().len(), 2);
+    }
+    #[test]
+    fn test_2048_matches_interpreter() {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"nwasdwwaassddwasdsdsdawawa");
+        let image = concat!(env!("CARGO_MANIFEST_DIR"), "/2048.obj");
+        let origin = vm.read_image(image).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = origin;
+        let (vm, result) = run_both(vm);
+        assert!(matches!(result, Err(Fault::EndOfInput { .. })));
+        assert!(vm.instruction_count > 100_000);
+    }
+}
@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::asm::{assemble, assemble_with, register};
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::console::Console;
+    use virtual_vm::disasm::{disassemble, disassemble_with};
+    use virtual_vm::extension::Extension;
+    use virtual_vm::history::History;
+    use virtual_vm::run::{Fault, Registers, Step, VM};
+    use virtual_vm::symbols::SymbolTable;
+    use virtual_vm::trace::{TraceSink, Tracer};
+
+    /// MUL DR, SR1, SR2 is xD with 000 in bits 5-3; PUSH SR and POP DR use
+    /// 001 and 010, with R6 as the stack pointer.
+    #[derive(Debug, Clone)]
+    struct Stack;
+
+    impl Extension for Stack {
+        fn execute(&mut self, vm: &mut VM, pc: u16, instr: u16) -> Result<Step, Fault> {
+            let dr = ((instr >> 9) & 0x7) as usize;
+            let sr1 = ((instr >> 6) & 0x7) as usize;
+            let sr2 = (instr & 0x7) as usize;
+            let sp = Registers::R_R6 as usize;
+            match (instr >> 3) & 0x7 {
+                0 => {
+                    let product = vm.registers_storage[sr1].wrapping_mul(vm.registers_storage[sr2]);
+                    vm.registers_storage[dr] = product;
+                    vm.update_flags(dr as u16);
+                }
+                1 => {
+                    vm.registers_storage[sp] = vm.registers_storage[sp].wrapping_sub(1);
+                    vm.mem_write(vm.registers_storage[sp], vm.registers_storage[dr]);
+                }
+                2 => {
+                    vm.registers_storage[dr] = vm.memory_read(vm.registers_storage[sp]);
+                    vm.registers_storage[sp] = vm.registers_storage[sp].wrapping_add(1);
+                }
+                _ => return Err(Fault::IllegalOpcode { pc, instr }),
+            }
+            Ok(Step::Continue)
+        }
+
+        fn disassemble(&self, instr: u16) -> Option<String> {
+            let (dr, sr1, sr2) = ((instr >> 9) & 0x7, (instr >> 6) & 0x7, instr & 0x7);
+            match (instr >> 3) & 0x7 {
+                0 => Some(format!("MUL R{}, R{}, R{}", dr, sr1, sr2)),
+                1 => Some(format!("PUSH R{}", dr)),
+                2 => Some(format!("POP R{}", dr)),
+                _ => None,
+            }
+        }
+
+        fn is_mnemonic(&self, mnemonic: &str) -> bool {
+            ["MUL", "PUSH", "POP"].contains(&mnemonic.to_ascii_uppercase().as_str())
+        }
+
+        fn assemble(&self, mnemonic: &str, operands: &[&str]) -> Result<u16, String> {
+            let operand = |index: usize| register(operands.get(index).copied());
+            match mnemonic.to_ascii_uppercase().as_str() {
+                "MUL" => Ok(0xD000 | operand(0)? << 9 | operand(1)? << 6 | operand(2)?),
+                "PUSH" => Ok(0xD008 | operand(0)? << 9),
+                _ => Ok(0xD010 | operand(0)? << 9),
+            }
+        }
+
+        fn clone_box(&self) -> Box<dyn Extension> {
+            Box::new(self.clone())
+        }
+    }
+
+    const SOURCE: &str = "\
+.ORIG x3000
+        LD R6, STACK
+        AND R1, R1, #0
+        ADD R1, R1, #6
+        ADD R2, R1, #1
+SQUARE  MUL R3, R1, R2
+        PUSH R3
+        POP R4
+        HALT
+STACK   .FILL x4000
+.END
+";
+
+    fn load(words: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.extension = Some(Box::new(Stack));
+        vm.memory.load(0x3000, words).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    #[test]
+    fn test_reserved_opcode_is_illegal_without_an_extension() {
+        let mut vm = load(&[0xD000]);
+        vm.extension = None;
+        assert_eq!(
+            vm.step(),
+            Err(Fault::IllegalOpcode {
+                pc: 0x3000,
+                instr: 0xD000
+            })
+        );
+        assert_eq!(
+            disassemble(0x3000, 0xD000, &SymbolTable::new()),
+            ".FILL xD000"
+        );
+        let error = assemble(".ORIG x3000\nMUL R1, R2, R3\n.END\n").unwrap_err();
+        assert_eq!(error, "line 2: unknown directive R1");
+    }
+
+    #[test]
+    fn test_extension_assembles_runs_and_disassembles() {
+        let assembly = assemble_with(SOURCE, Some(&Stack)).unwrap();
+        let words = &assembly.sections[0].words;
+        assert_eq!(&words[4..7], [0xD642, 0xD608, 0xD810]);
+        assert_eq!(assembly.symbols.address_of("SQUARE"), Some(0x3004));
+        assert_eq!(assembly.symbols.address_of("STACK"), Some(0x3008));
+
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut vm = load(words);
+            vm.engine = engine;
+            assert_eq!(vm.execute(), Ok(()));
+            assert_eq!(vm.registers_storage[3], 42);
+            assert_eq!(vm.registers_storage[4], 42);
+            assert_eq!(vm.registers_storage[6], 0x4000);
+            assert_eq!(vm.memory[0x3FFF], 42);
+        }
+
+        let symbols = SymbolTable::new();
+        let text = |instr| disassemble_with(0x3004, instr, &symbols, Some(&Stack));
+        assert_eq!(text(0xD642), "MUL R3, R1, R2");
+        assert_eq!(text(0xD608), "PUSH R3");
+        assert_eq!(text(0xD038), ".FILL xD038");
+    }
+
+    #[test]
+    fn test_extension_faults_and_history() {
+        // An encoding the extension rejects faults like any other
+        let mut vm = load(&[0xD038]);
+        vm.registers_storage[7] = 0x1234;
+        assert_eq!(
+            vm.step(),
+            Err(Fault::IllegalOpcode {
+                pc: 0x3000,
+                instr: 0xD038
+            })
+        );
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3000);
+        assert!(vm.extension.is_some());
+
+        // Writes made by the handler can be stepped back
+        let mut vm = load(&[0xD608]);
+        vm.history = Some(History::new(10));
+        vm.registers_storage[3] = 7;
+        vm.registers_storage[6] = 0x4000;
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(vm.memory[0x3FFF], 7);
+        assert!(vm.step_back());
+        assert_eq!(vm.memory[0x3FFF], 0);
+        assert_eq!(vm.registers_storage[6], 0x4000);
+    }
+
+    #[test]
+    fn test_trace_uses_the_extension() {
+        let mut vm = load(&[0xD642, 0xF025]);
+        vm.registers_storage[1] = 3;
+        vm.registers_storage[2] = 5;
+        vm.tracer = Some(Tracer::new(
+            TraceSink::Buffered(Vec::new()),
+            SymbolTable::new(),
+        ));
+        vm.execute().unwrap();
+        let trace = vm.tracer.as_mut().unwrap().take_output();
+        assert!(String::from_utf8(trace)
+            .unwrap()
+            .starts_with("x3000  D642  MUL R3, R1, R2          R3=x000F  COND=P\n"));
+    }
+}
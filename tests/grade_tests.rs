@@ -0,0 +1,164 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::path::PathBuf;
+    use virtual_vm::grade::{grade, junit, load_spec, parse_spec, report, TestCase, Verdict};
+    use virtual_vm::run::Fault;
+    use virtual_vm::symbols::SymbolTable;
+
+    // GETC ; ADD R1,R0,#0 ; GETC ; ADD R0,R0,R1 ; LD R2,NEG48 ; ADD R0,R0,R2
+    // OUT ; ADD R3,R0,R2 ; ST R3,RESULT ; HALT ; NEG48 .FILL #-48 ; RESULT .FILL #0
+    const PROGRAM: [u16; 12] = [
+        0xF020, 0x1220, 0xF020, 0x1001, 0x2405, 0x1002, 0xF021, 0x1602, 0x3602, 0xF025, 0xFFD0, 0,
+    ];
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lc3-grade-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_image(dir: &std::path::Path) -> String {
+        let mut bytes = 0x3000u16.to_be_bytes().to_vec();
+        for word in PROGRAM {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        let path = dir.join("add.obj");
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("RESULT", 0x300B);
+        symbols
+    }
+
+    #[test]
+    fn test_toml_and_json_specs_agree() {
+        let dir = temp_dir("formats");
+        std::fs::write(
+            dir.join("spec.toml"),
+            r##"
+max_instructions = 500
+
+[[cases]]
+name = "adds"
+input = "34"
+output = "7"
+registers = { R3 = 7, PC = "x300A" }
+memory = { RESULT = "#7", x300A = -48 }
+timeout = 1.5
+"##,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("spec.json"),
+            json!({
+                "max_instructions": 500,
+                "cases": [{
+                    "name": "adds",
+                    "input": "34",
+                    "output": "7",
+                    "registers": { "R3": 7, "PC": "x300A" },
+                    "memory": { "RESULT": "#7", "x300A": -48 },
+                    "timeout": 1.5
+                }]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let from_toml = load_spec(&dir.join("spec.toml"), &symbols()).unwrap();
+        let from_json = load_spec(&dir.join("spec.json"), &symbols()).unwrap();
+        assert_eq!(from_toml, from_json);
+        let case = &from_toml[0];
+        assert_eq!(case.input, b"34");
+        assert_eq!(case.budget.max_instructions, Some(500));
+        assert_eq!(case.registers, [(3, 7), (8, 0x300A)]);
+        assert_eq!(case.memory, [(0x300A, 0xFFD0), (0x300B, 7)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_spec_errors_name_the_case() {
+        let spec =
+            json!({ "cases": [{ "name": "ok" }, { "name": "bad", "memory": { "NOWHERE": 1 } }] });
+        assert_eq!(
+            parse_spec(&spec, &symbols()),
+            Err("case 2: unknown address or label `NOWHERE`".to_string())
+        );
+        assert!(parse_spec(&json!({}), &symbols()).is_err());
+    }
+
+    #[test]
+    fn test_labels_that_look_like_numbers() {
+        let mut symbols = symbols();
+        symbols.insert("B10", 0x3100);
+        symbols.insert("X1", 0x3101);
+        let spec =
+            json!({ "cases": [{ "name": "labels", "memory": { "B10": 1, "X1": 2, "x4000": 3 } }] });
+        let cases = parse_spec(&spec, &symbols).unwrap();
+        assert_eq!(cases[0].memory, [(0x3100, 1), (0x3101, 2), (0x4000, 3)]);
+    }
+
+    #[test]
+    fn test_cases_pass_fail_and_error() {
+        let dir = temp_dir("run");
+        let image = write_image(&dir);
+        let spec = json!({
+            "cases": [
+                { "name": "3+4", "input": "34", "output": "7", "memory": { "RESULT": 7 } },
+                { "name": "2+2", "input": "22", "output": "5", "registers": { "R3": 5 } },
+                { "name": "no input", "input": "3" },
+            ]
+        });
+        let cases: Vec<TestCase> = parse_spec(&spec, &symbols()).unwrap();
+        let results = grade(&image, &cases).unwrap();
+
+        assert_eq!(results[0].verdict, Verdict::Passed);
+        assert_eq!(results[0].instructions, 10);
+        assert_eq!(
+            results[1].verdict,
+            Verdict::Failed(vec![
+                "output: expected \"5\", got \"4\"".to_string(),
+                "R3: expected x0005, got x0004".to_string(),
+            ])
+        );
+        assert_eq!(
+            results[2].verdict,
+            Verdict::Error(Fault::EndOfInput { pc: 0x3002 })
+        );
+
+        let text = report(&results);
+        assert!(text.starts_with("PASS   3+4 (10 instructions)\nFAIL   2+2 (10 instructions)\n"));
+        assert!(text.contains("\n       R3: expected x0005, got x0004\n"));
+        assert!(text.ends_with("1 of 3 cases passed\n"));
+
+        let xml = junit("add", &results);
+        assert!(xml.contains(r#"tests="3" failures="1" errors="1""#));
+        assert!(xml.contains(r#"<testcase name="3+4" classname="add""#));
+        assert!(xml
+            .contains(r#"<failure message="output: expected &quot;5&quot;, got &quot;4&quot;">"#));
+        assert!(
+            xml.contains("<error message=\"Input ended while waiting for a character at x3002\">")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_runaway_program_is_stopped() {
+        let dir = temp_dir("runaway");
+        let path = dir.join("spin.obj");
+        // ADD R1,R1,#1 ; BRnzp #-2
+        std::fs::write(&path, [0x30, 0x00, 0x12, 0x61, 0x0F, 0xFE]).unwrap();
+        let spec = json!({ "max_instructions": 1000, "cases": [{ "name": "spins" }] });
+        let cases = parse_spec(&spec, &SymbolTable::new()).unwrap();
+        let results = grade(path.to_str().unwrap(), &cases).unwrap();
+        assert!(matches!(
+            results[0].verdict,
+            Verdict::Error(Fault::BudgetExhausted { count: 1000, .. })
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
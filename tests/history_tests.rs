@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::debugger::{Debugger, StopReason};
+    use virtual_vm::history::History;
+    use virtual_vm::run::{Registers, VM};
+
+    // ADD R1,R1,#1 ; ST R1, x3005 ; ADD R1,R1,#1 ; HALT
+    const COUNTER: [u16; 4] = [0x1261, 0x3203, 0x1261, 0xF025];
+
+    fn load(program: &[u16], history: usize) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.history = Some(History::new(history));
+        vm
+    }
+
+    #[test]
+    fn test_step_back_restores_registers_and_memory() {
+        let mut vm = load(&COUNTER, 16);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x3005], 1);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.memory[0x3005], 0);
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3001);
+        assert_eq!(vm.instruction_count, 1);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 0);
+        assert!(!vm.step_back());
+    }
+    #[test]
+    fn test_history_is_bounded() {
+        let mut vm = load(&COUNTER, 2);
+        vm.execute().unwrap();
+        assert_eq!(vm.history.as_ref().unwrap().len(), 2);
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3002);
+    }
+    #[test]
+    fn test_last_write() {
+        let mut vm = load(&COUNTER, 16);
+        vm.execute().unwrap();
+        let (record, write) = vm.history.as_ref().unwrap().last_write(0x3005).unwrap();
+        assert_eq!(record.pc, 0x3001);
+        assert_eq!(record.count, 1);
+        assert_eq!((write.old, write.new), (0, 1));
+        assert!(vm.history.as_ref().unwrap().last_write(0x4000).is_none());
+    }
+    #[test]
+    fn test_step_back_returns_input() {
+        // GETC ; HALT
+        let mut vm = load(&[0xF020, 0xF025], 16);
+        vm.console.push_input(b"xy");
+        vm.step().unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R0 as usize], 'x' as u16);
+        vm.step_back();
+        vm.step().unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R0 as usize], 'x' as u16);
+    }
+    #[test]
+    fn test_reverse_continue_to_breakpoint() {
+        // JSR SUB; ADD R1,R1,#1; HALT; SUB: ADD R2,R2,#1; RET
+        let vm = load(&[0x4802, 0x1261, 0xF025, 0x14A1, 0xC1C0], 64);
+        let mut debugger = Debugger::new(vm);
+        debugger.continue_();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Halted));
+
+        debugger.add_breakpoint(0x3003);
+        assert_eq!(debugger.reverse_continue(), StopReason::Breakpoint(0x3003));
+        assert_eq!(debugger.call_depth(), 1);
+        assert_eq!(debugger.vm.registers_storage[Registers::R_R2 as usize], 0);
+
+        assert_eq!(debugger.step_back(), StopReason::Step);
+        assert_eq!(debugger.pc(), 0x3000);
+        assert_eq!(debugger.reverse_continue(), StopReason::HistoryStart);
+
+        debugger.continue_();
+        assert_eq!(debugger.run_to_stop(), Some(StopReason::Breakpoint(0x3003)));
+    }
+}
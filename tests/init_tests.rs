@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::init::InitState;
+    use virtual_vm::memory::{FlatMemory, MemoryError};
+    use virtual_vm::run::{Registers, VM};
+    use virtual_vm::symbols::SymbolTable;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MULTIPLY", 0x3000);
+        symbols.insert("TABLE", 0x4000);
+        symbols
+    }
+
+    #[test]
+    fn test_parse_registers_and_memory() {
+        let init = InitState::parse(
+            "; set up a call\n\
+             PC  MULTIPLY\n\
+             r1  #-2   ; negative\n\
+             \n\
+             TABLE  1, 2 x10 MULTIPLY\n\
+             x5000  \"hi\\n\" 7\n",
+            &symbols(),
+        )
+        .unwrap();
+        assert_eq!(
+            init.registers,
+            vec![(Registers::R_PC as usize, 0x3000), (1, 0xFFFE)]
+        );
+        assert_eq!(
+            init.memory,
+            vec![
+                (0x4000, vec![1, 2, 0x10, 0x3000]),
+                (0x5000, vec![b'h' as u16, b'i' as u16, b'\n' as u16, 0, 7]),
+            ]
+        );
+        assert_eq!(init.pc(), Some(0x3000));
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let errors = [
+            (
+                "R1 1\nR9 2\n",
+                "line 2: `R9` is not a register, address or label",
+            ),
+            ("R1 1 2\n", "line 1: `R1` takes exactly one value"),
+            ("x4000\n", "line 1: no values for `x4000`"),
+            ("x4000 NOWHERE\n", "line 1: bad value `NOWHERE`"),
+            ("R0 x10000\n", "line 1: bad value `x10000`"),
+        ];
+        for (text, expected) in errors {
+            assert_eq!(
+                InitState::parse(text, &symbols()),
+                Err(expected.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_later_settings_replace_earlier_ones() {
+        let mut init = InitState::parse("PC x3000\nR2 5\n", &symbols()).unwrap();
+        init.set_register(Registers::R_PC as usize, 0x3100);
+        assert_eq!(init.pc(), Some(0x3100));
+        assert_eq!(init.registers.len(), 2);
+    }
+
+    #[test]
+    fn test_subroutine_runs_in_isolation() {
+        // MULTIPLY: AND R2,R2,#0 ; LOOP ADD R2,R2,R0 ; ADD R1,R1,#-1 ; BRp LOOP ; RET
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3005].copy_from_slice(&[0x54A0, 0x1480, 0x127F, 0x03FD, 0xC1C0]);
+        let init = InitState::parse(
+            "PC MULTIPLY\nR0 #6\nR1 7\nR7 x3100\nx3100 xF025\n",
+            &symbols(),
+        )
+        .unwrap();
+        init.apply(&mut vm).unwrap();
+        vm.execute().unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R2 as usize], 42);
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3101);
+    }
+
+    #[test]
+    fn test_memory_runs_wrap_around() {
+        let mut vm = VM::new();
+        let init = InitState::parse("xFFFF 1 2\n", &symbols()).unwrap();
+        init.apply(&mut vm).unwrap();
+        assert_eq!((vm.memory[0xFFFF], vm.memory[0]), (1, 2));
+    }
+
+    #[test]
+    fn test_words_outside_memory_are_reported() {
+        let mut vm = VM::new();
+        vm.memory = Box::new(FlatMemory::new(0x3001));
+        let init = InitState::parse("R1 5\nx2FFF 1 2 3\n", &symbols()).unwrap();
+        assert_eq!(
+            init.apply(&mut vm),
+            Err(MemoryError {
+                address: 0x3001,
+                size: 0x3001
+            })
+        );
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 5);
+        assert_eq!(vm.memory[0x2FFF..0x3001], [1, 2]);
+    }
+}
@@ -0,0 +1,208 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use virtual_vm::asm::{assemble, format_symbols, Assembly};
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::console::Console;
+    use virtual_vm::initcheck::{InitChecker, InitMode, UninitRead, Uninitialized};
+    use virtual_vm::loader::{ImageLoader, Strictness};
+    use virtual_vm::memory::FlatMemory;
+    use virtual_vm::run::{Fault, Isa, Registers, VM};
+
+    const SOURCE: &str = "\
+.ORIG x3000
+MAIN    AND R0, R0, #0
+        ADD R1, R2, #1
+        LDI R4, PTR
+        LD R5, PTR
+        STR R0, R5, #1
+        LDR R6, R5, #1
+        ADD R1, R2, #0
+        HALT
+PTR     .FILL x4000
+.END
+";
+
+    /// Loads `assembly` through the image loader, which marks it initialized.
+    fn load(assembly: &Assembly, mode: InitMode) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.init_checker = Some(InitChecker::new(mode));
+        let image = assembly.sections[0].to_obj();
+        ImageLoader::new(Strictness::Warn)
+            .load_bytes(&mut vm, "a.obj", &image)
+            .unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    #[test]
+    fn test_uninitialized_reads_are_reported() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut vm = load(&assembly, InitMode::Warn);
+        assert_eq!(vm.execute(), Ok(()));
+        let checker = vm.init_checker.as_ref().unwrap();
+        let read = |pc, location| UninitRead { pc, location };
+        assert_eq!(
+            checker.problems(),
+            [
+                read(0x3001, Uninitialized::Register(2)),
+                read(0x3002, Uninitialized::Memory(0x4000)),
+                read(0x3006, Uninitialized::Register(2)),
+            ]
+        );
+        assert!(checker.is_initialized(0x4001));
+        assert!(checker.is_register_initialized(6));
+        assert!(!checker.is_register_initialized(3));
+        assert!(checker
+            .report(&assembly.symbols)
+            .starts_with("Uninitialized reads:\n  x3001  MAIN+1           R2\n"));
+
+        // XOR R1, R1, R1 clears R1 without reading it; LDW R2, R1, #0 reads
+        // word 0
+        let mut vm = VM::new();
+        vm.isa = Isa::Lc3b;
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.memory.load(0x1800, &[0x9241, 0x6440, 0xF025]).unwrap();
+        let mut checker = InitChecker::new(InitMode::Warn);
+        checker.initialize(0x1800..0x1803);
+        vm.init_checker = Some(checker);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(
+            vm.init_checker.unwrap().problems(),
+            [UninitRead {
+                pc: 0x3002,
+                location: Uninitialized::Memory(0)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_uninitialized_reads_fault() {
+        let assembly = assemble(SOURCE).unwrap();
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut vm = load(&assembly, InitMode::Fault);
+            vm.engine = engine;
+            let fault = vm.execute().unwrap_err();
+            assert_eq!(
+                fault,
+                Fault::UninitializedRead {
+                    pc: 0x3001,
+                    location: Uninitialized::Register(2)
+                }
+            );
+            assert_eq!(fault.to_string(), "Read of uninitialized R2 at x3001");
+            assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3001);
+
+            vm.init_checker.as_mut().unwrap().initialize_register(2);
+            let fault = vm.execute().unwrap_err();
+            assert_eq!(
+                fault.to_string(),
+                "Read of uninitialized memory x4000 at x3002"
+            );
+            assert_eq!(vm.registers_storage[4], 0);
+            assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3002);
+        }
+
+        // LEA R0, #0 ; STI R0, x3101 through a pointer nothing set writes
+        // nothing
+        let mut vm = VM::new();
+        vm.quiet = true;
+        vm.memory.load(0x3000, &[0xE000, 0xB0FF, 0xF025]).unwrap();
+        vm.memory[0x3101] = 0x5000;
+        let mut checker = InitChecker::new(InitMode::Fault);
+        checker.initialize(0x3000..0x3003);
+        vm.init_checker = Some(checker);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        assert_eq!(
+            vm.execute(),
+            Err(Fault::UninitializedRead {
+                pc: 0x3001,
+                location: Uninitialized::Memory(0x3101)
+            })
+        );
+        assert_eq!(vm.memory[0x5000], 0);
+        assert!(!vm.init_checker.as_ref().unwrap().is_initialized(0x5000));
+
+        // LEA R0, #0 ; ST R0, x3101 past the end of memory faults without
+        // marking x3101
+        let mut vm = VM::new();
+        vm.quiet = true;
+        vm.memory = Box::new(FlatMemory::new(0x3100));
+        vm.memory.load(0x3000, &[0xE000, 0x30FF, 0xF025]).unwrap();
+        let mut checker = InitChecker::new(InitMode::Warn);
+        checker.initialize(0x3000..0x3003);
+        vm.init_checker = Some(checker);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        assert_eq!(
+            vm.execute(),
+            Err(Fault::MemoryOutOfRange {
+                pc: 0x3001,
+                address: 0x3101,
+                size: 0x3100
+            })
+        );
+        assert!(!vm.init_checker.as_ref().unwrap().is_initialized(0x3101));
+
+        // Running off the end of the image
+        let mut vm = load(
+            &assemble(".ORIG x3000\nADD R0, R0, #0\n.END\n").unwrap(),
+            InitMode::Fault,
+        );
+        vm.init_checker.as_mut().unwrap().initialize_register(0);
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(fault.to_string(), "Executing uninitialized memory at x3001");
+    }
+
+    #[test]
+    fn test_check_uninit_option() {
+        let dir = std::env::temp_dir().join(format!("lc3-uninit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let assembly = assemble(SOURCE).unwrap();
+        let image = dir.join("uninit.obj");
+        fs::write(&image, assembly.sections[0].to_obj()).unwrap();
+        fs::write(dir.join("uninit.sym"), format_symbols(&assembly.symbols)).unwrap();
+
+        let run = |args: &[&str]| {
+            Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+                .args(["run", "-q", "--check-uninit"])
+                .args(args)
+                .arg(&image)
+                .output()
+                .unwrap()
+        };
+        let output = run(&["warn"]);
+        assert_eq!(output.status.code(), Some(0));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Uninitialized reads:\n"), "{}", stderr);
+        assert!(
+            stderr.contains("x3006  MAIN+6           R2\n"),
+            "{}",
+            stderr
+        );
+
+        let output = run(&["fault"]);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Read of uninitialized R2 at x3001"),
+            "{}",
+            stderr
+        );
+
+        // Registers set with --reg count as initialized
+        let output = run(&["fault", "--reg", "R2=0"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Read of uninitialized memory x4000 at x3002"),
+            "{}",
+            stderr
+        );
+        assert_eq!(run(&["loud"]).status.code(), Some(2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(all(test, feature = "jit"))]
+mod tests {
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::console::Console;
+    use virtual_vm::memory::FlatMemory;
+    use virtual_vm::run::{Fault, Registers, VM};
+
+    fn load(program: &[u16], input: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(input);
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    /// Runs `vm` interpreted and with the JIT and checks both end in the same state.
+    fn run_both(vm: VM) -> (VM, Result<(), Fault>) {
+        let mut interpreted = vm.clone();
+        let mut compiled = vm;
+        compiled.engine = Engine::Jit;
+        let expected = interpreted.execute();
+        let result = compiled.execute();
+        assert_eq!(result, expected);
+        assert_eq!(compiled.registers_storage, interpreted.registers_storage);
+        assert_eq!(compiled.instruction_count, interpreted.instruction_count);
+        assert!(compiled.memory.contents() == interpreted.memory.contents());
+        assert_eq!(
+            compiled.console.take_output(),
+            interpreted.console.take_output()
+        );
+        (compiled, result)
+    }
+
+    /// Deterministic pseudo-random numbers for generating programs.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u16 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as u16
+        }
+    }
+
+    /// A loop running `n` random ALU and R5-relative memory instructions 50
+    /// times. R5 and the loop counter R6 are never written by the body.
+    fn random_program(rng: &mut Lcg, n: u16) -> VM {
+        let mut program = vec![0x2C00 | (n + 3)];
+        for _ in 0..n {
+            let dr = rng.next() % 5;
+            let sr1 = rng.next() % 6;
+            let sr2 = rng.next() % 6;
+            let bits = rng.next();
+            let word = match rng.next() % 8 {
+                0 => 0x1000 | dr << 9 | sr1 << 6 | sr2,
+                1 => 0x1020 | dr << 9 | sr1 << 6 | (bits & 0x1F),
+                2 => 0x5000 | dr << 9 | sr1 << 6 | sr2,
+                3 => 0x5020 | dr << 9 | sr1 << 6 | (bits & 0x1F),
+                4 => 0x903F | dr << 9 | sr1 << 6,
+                5 => 0xE000 | dr << 9 | (bits & 0x1FF),
+                6 => 0x6000 | dr << 9 | 5 << 6 | (bits & 0x3F),
+                _ => 0x7000 | dr << 9 | 5 << 6 | (bits & 0x3F),
+            };
+            program.push(word);
+        }
+        program.push(0x1DBF);
+        program.push(0x0200 | (n.wrapping_add(2).wrapping_neg() & 0x1FF));
+        program.push(0xF025);
+        program.push(50);
+
+        let mut vm = load(&program, b"");
+        for register in 0..5 {
+            vm.registers_storage[register] = rng.next();
+        }
+        vm.registers_storage[Registers::R_R5 as usize] = 0x4000;
+        for address in 0x3FE0..0x4020 {
+            vm.memory[address] = rng.next();
+        }
+        vm
+    }
+
+    #[test]
+    fn test_random_programs_match_interpreter() {
+        let mut rng = Lcg(0x5EED);
+        for _ in 0..200 {
+            let n = 1 + rng.next() % 40;
+            let (vm, result) = run_both(random_program(&mut rng, n));
+            assert_eq!(result, Ok(()));
+            assert_eq!(vm.jit.compiled(), 1);
+        }
+    }
+    #[test]
+    fn test_loop_is_compiled() {
+        // AND R1,R1,#0 ; LD R0,COUNT ; LOOP ADD R1,R1,R0 ; ADD R0,R0,#-1 ; BRp LOOP ; HALT ; COUNT .FILL #100
+        let (vm, _) = run_both(load(
+            &[0x5260, 0x2004, 0x1240, 0x103F, 0x03FD, 0xF025, 100],
+            b"",
+        ));
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 5050);
+        assert_eq!(vm.jit.compiled(), 1);
+    }
+    #[test]
+    fn test_self_modifying_hot_loop() {
+        // LOOP ADD R1,R1,#1 ; ADD R3,R3,#-1 ; BRz PATCH ; BRnzp NEXT
+        // PATCH LD R2,NEW ; ST R2,LOOP
+        // NEXT ADD R4,R4,#-1 ; BRp LOOP ; HALT ; NEW ADD R1,R1,#4
+        let mut vm = load(
+            &[
+                0x1261, 0x16FF, 0x0401, 0x0E02, 0x2404, 0x35FA, 0x193F, 0x03F8, 0xF025, 0x1264,
+            ],
+            b"",
+        );
+        vm.registers_storage[Registers::R_R3 as usize] = 30;
+        vm.registers_storage[Registers::R_R4 as usize] = 60;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 30 + 4 * 30);
+    }
+    #[test]
+    fn test_store_into_compiled_block_is_left_to_interpreter() {
+        // LOOP ADD R1,R1,#1 ; STR R2,R5,#0 ; ADD R6,R6,#-1 ; BRp LOOP ; HALT
+        // with R5 pointing at the ADD R6 and R2 holding the same instruction
+        let mut vm = load(&[0x1261, 0x7540, 0x1DBF, 0x03FC, 0xF025], b"");
+        vm.registers_storage[Registers::R_R2 as usize] = 0x1DBF;
+        vm.registers_storage[Registers::R_R5 as usize] = 0x3002;
+        vm.registers_storage[Registers::R_R6 as usize] = 40;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 40);
+        assert!(vm.jit.compiled() >= 1);
+        assert!(vm.blocks.invalidated() >= 40);
+    }
+    #[test]
+    fn test_narrow_memory_matches_interpreter() {
+        // LOOP STR R2,R5,#0 ; ADD R5,R5,#1 ; ADD R6,R6,#-1 ; BRp LOOP ; HALT
+        // storing x1234 into 8-bit words
+        let program = [0x7540, 0x1B61, 0x1DBF, 0x03FC, 0xF025];
+        let mut vm = load(&[], b"");
+        vm.memory = Box::new(FlatMemory::default().with_width(8));
+        vm.memory[0x3000..0x3005].copy_from_slice(&program);
+        vm.registers_storage[Registers::R_R2 as usize] = 0x1234;
+        vm.registers_storage[Registers::R_R5 as usize] = 0x4000;
+        vm.registers_storage[Registers::R_R6 as usize] = 40;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.memory[0x4000], 0x34);
+        assert_eq!(vm.memory[0x4027], 0x34);
+        assert_eq!(vm.jit.compiled(), 0);
+    }
+
+    #[test]
+    fn test_keyboard_polling_matches_interpreter() {
+        // POLL LDI R0,KBSR ; BRzp POLL ; LDI R0,KBDR ; OUT ; ADD R1,R1,#-1 ; BRp POLL ; HALT
+        let mut vm = load(
+            &[
+                0xA007, 0x07FE, 0xA006, 0xF021, 0x127F, 0x03FA, 0xF025, 0, 0xFE00, 0xFE02,
+            ],
+            b"abcdefghijklmnopqrstuvwxyz0123456789",
+        );
+        vm.registers_storage[Registers::R_R1 as usize] = 36;
+        let (vm, _) = run_both(vm);
+        assert_eq!(vm.registers_storage[Registers::R_R0 as usize], '9' as u16);
+    }
+    #[test]
+    fn test_2048_matches_interpreter() {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"nwasdwwaassddwasdsdsdawawa");
+        let image = concat!(env!("CARGO_MANIFEST_DIR"), "/2048.obj");
+        let origin = vm.read_image(image).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = origin;
+        let (vm, result) = run_both(vm);
+        assert!(matches!(result, Err(Fault::EndOfInput { .. })));
+        assert!(vm.jit.compiled() > 0);
+    }
+}
@@ -0,0 +1,309 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::callstack::CallStack;
+    use virtual_vm::console::Console;
+    use virtual_vm::lc3b::{decode, disassemble, Lc3bInstr, Shift};
+    use virtual_vm::loader::{ImageLoader, ImageProblem, Strictness};
+    use virtual_vm::run::{Fault, Isa, Registers, Step, VM};
+    use virtual_vm::symbols::SymbolTable;
+
+    const HALT: u16 = 0xF025;
+
+    /// An LC-3b VM with `program` at byte x3000 (word x1800).
+    fn load(program: &[u16], input: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.isa = Isa::Lc3b;
+        vm.console = Console::buffered(input);
+        vm.quiet = true;
+        vm.memory.load(0x1800, program).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    fn reg(vm: &VM, index: usize) -> u16 {
+        vm.registers_storage[index]
+    }
+
+    #[test]
+    fn test_byte_and_word_loads_and_stores() {
+        let program = [
+            0xE206, // LEA R1, x300E
+            0x2441, // LDB R2, R1, #1
+            0x6641, // LDW R3, R1, #1
+            0x3640, // STB R3, R1, #0
+            0x7442, // STW R2, R1, #2
+            HALT,   // x300A
+            0x0000, // x300C
+            0x80AB, // x300E
+            0x1234, // x3010
+            0x0000, // x3012
+        ];
+        let mut vm = load(&program, b"");
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(reg(&vm, 1), 0x300E);
+        // x300F is the high byte of word x1807, sign-extended
+        assert_eq!(reg(&vm, 2), 0xFF80);
+        assert_eq!(reg(&vm, 3), 0x1234);
+        // STB only replaces the low byte
+        assert_eq!(vm.memory[0x1807], 0x8034);
+        assert_eq!(vm.memory[0x1809], 0xFF80);
+    }
+
+    #[test]
+    fn test_shifts() {
+        let program = [
+            0xD204, // LSHF R1, R0, #4
+            0xD414, // RSHFL R2, R0, #4
+            0xD634, // RSHFA R3, R0, #4
+            HALT,
+        ];
+        let mut vm = load(&program, b"");
+        vm.registers_storage[0] = 0x8421;
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(reg(&vm, 1), 0x4210);
+        assert_eq!(reg(&vm, 2), 0x0842);
+        assert_eq!(reg(&vm, 3), 0xF842);
+        assert_eq!(reg(&vm, Registers::R_COND as usize), 1 << 2);
+    }
+
+    #[test]
+    fn test_branches_calls_and_xor() {
+        let program = [
+            0x5020, // AND R0, R0, #0
+            0xE200, // LEA R1, x3004
+            0x0401, // BRz x3008
+            HALT,   // x3006
+            0x4802, // JSR x300E
+            0x947F, // NOT R2, R1
+            HALT,   // x300C
+            0x9025, // XOR R0, R0, #5
+            0xC1C0, // RET
+        ];
+        let mut vm = load(&program, b"");
+        vm.call_stack = Some(CallStack::new(0x3000));
+        vm.step().unwrap();
+        vm.step().unwrap();
+        // LEA leaves the condition codes alone
+        assert_eq!(reg(&vm, 1), 0x3004);
+        assert_eq!(reg(&vm, Registers::R_COND as usize), 1 << 1);
+        vm.step().unwrap();
+        assert_eq!(reg(&vm, Registers::R_PC as usize), 0x3008);
+        vm.step().unwrap();
+        assert_eq!(reg(&vm, 7), 0x300A);
+        let frame = vm.call_stack.as_ref().unwrap().frames()[0];
+        assert_eq!((frame.call_site, frame.entry), (0x3008, 0x300E));
+
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(reg(&vm, 0), 5);
+        assert_eq!(reg(&vm, 2), 0xCFFB);
+        assert_eq!(vm.call_stack.as_ref().unwrap().depth(), 0);
+    }
+
+    #[test]
+    fn test_traps() {
+        // PUTS prints one character per byte
+        // LEA R0, x3010 ; PUTS ; HALT
+        let mut vm = load(&[0xE007, 0xF022, HALT], b"");
+        vm.memory[0x1808] = 0x6948; // "Hi" at x3010
+        vm.memory[0x1809] = 0x0021; // "!"
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(vm.console.take_output(), b"Hi!");
+
+        // A routine in the trap vector table runs instead of the built-in one
+        let mut vm = load(&[0xF021], b"");
+        vm.memory[0x0021] = 0x4000;
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(reg(&vm, Registers::R_PC as usize), 0x4000);
+        assert_eq!(reg(&vm, 7), 0x3002);
+
+        let mut vm = load(&[0xF024], b"");
+        assert_eq!(
+            vm.step(),
+            Err(Fault::InvalidTrap {
+                pc: 0x3000,
+                vector: 0x24
+            })
+        );
+        let mut vm = load(&[0xF023], b"");
+        assert_eq!(vm.step(), Err(Fault::EndOfInput { pc: 0x3000 }));
+        // GETC then IN, each faulting at its own byte address
+        let mut vm = load(&[0xF020, 0xF023], b"a");
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(vm.step(), Err(Fault::EndOfInput { pc: 0x3002 }));
+    }
+
+    #[test]
+    fn test_unaligned_and_illegal_instructions_fault() {
+        // LDW R0, R1, #0 at an odd address
+        let mut vm = load(&[0x6040], b"");
+        vm.registers_storage[1] = 0x3001;
+        let fault = vm.step().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::UnalignedAccess {
+                pc: 0x3000,
+                address: 0x3001
+            }
+        );
+        assert_eq!(
+            fault.to_string(),
+            "Word access at odd address x3001 at x3000"
+        );
+        assert_eq!(reg(&vm, Registers::R_PC as usize), 0x3000);
+
+        // LDI's opcode is unused
+        let mut vm = load(&[0xA000], b"");
+        assert_eq!(
+            vm.step(),
+            Err(Fault::IllegalOpcode {
+                pc: 0x3000,
+                instr: 0xA000
+            })
+        );
+
+        // JMP R1 to an odd address faults on the next fetch
+        let mut vm = load(&[0xC040], b"");
+        vm.registers_storage[1] = 0x3005;
+        assert_eq!(vm.step(), Ok(Step::Continue));
+        assert_eq!(
+            vm.step(),
+            Err(Fault::UnalignedAccess {
+                pc: 0x3005,
+                address: 0x3005
+            })
+        );
+    }
+
+    #[test]
+    fn test_keyboard_registers_are_shared() {
+        // LDW R0, R1, #0 (KBSR) ; LDW R2, R1, #1 (KBDR)
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut vm = load(&[0x6040, 0x6441, HALT], b"a");
+            vm.engine = engine;
+            vm.registers_storage[1] = 0xFE00;
+            assert_eq!(vm.execute(), Ok(()));
+            assert_eq!(reg(&vm, 0), 0x8000);
+            assert_eq!(reg(&vm, 2), u16::from(b'a'));
+        }
+    }
+
+    #[test]
+    fn test_decode_and_disassemble() {
+        assert_eq!(
+            decode(0x3000, 0xD634),
+            Lc3bInstr::Shf {
+                dr: 3,
+                sr: 0,
+                shift: Shift::RightArithmetic,
+                amount: 4
+            }
+        );
+        assert_eq!(
+            decode(0x3000, 0x6641),
+            Lc3bInstr::Ldw {
+                dr: 3,
+                base: 1,
+                offset: 2
+            }
+        );
+        let symbols = SymbolTable::default();
+        let text = |instr| disassemble(0x3000, instr, &symbols);
+        assert_eq!(text(0x0401), "BRz x3004");
+        assert_eq!(text(0x6641), "LDW R3, R1, #1");
+        assert_eq!(text(0x2441), "LDB R2, R1, #1");
+        assert_eq!(text(0x947F), "NOT R2, R1");
+        assert_eq!(text(0x9025), "XOR R0, R0, #5");
+        assert_eq!(text(0xD414), "RSHFL R2, R0, #4");
+        assert_eq!(text(0xE206), "LEA R1, x300E");
+        assert_eq!(text(0xA000), ".FILL xA000");
+    }
+
+    #[test]
+    fn test_image_origins_are_byte_addresses() {
+        let mut vm = VM::new();
+        vm.isa = Isa::Lc3b;
+        let mut loader = ImageLoader::new(Strictness::Warn);
+        let image = loader
+            .load_bytes(&mut vm, "a.obj", &[0x30, 0x00, 0x12, 0x34])
+            .unwrap();
+        assert_eq!(image.origin, 0x3000);
+        assert_eq!(vm.memory[0x1800], 0x1234);
+
+        let image = loader
+            .load_bytes(&mut vm, "b.obj", &[0x40, 0x01, 0x56, 0x78])
+            .unwrap();
+        let problem = ImageProblem::OddOrigin { origin: 0x4001 };
+        assert_eq!(
+            problem.to_string(),
+            "origin x4001 is an odd LC-3b address; loading at x4000"
+        );
+        assert_eq!(image.warnings, [problem]);
+        assert_eq!(image.origin, 0x4000);
+        assert_eq!(vm.memory[0x2000], 0x5678);
+    }
+
+    #[test]
+    fn test_run_with_isa_option() {
+        let dir = std::env::temp_dir().join(format!("lc3-lc3b-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hi.obj");
+        let words = [0x3000, 0xE007, 0xF022, HALT, 0, 0, 0, 0, 0, 0x6948, 0x0021];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        fs::write(&path, bytes).unwrap();
+
+        let run = |isa: &str| {
+            Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+                .args(["run", "-q", "--isa", isa, path.to_str().unwrap()])
+                .output()
+                .unwrap()
+        };
+        let output = run("lc3b");
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Hi!");
+        assert_eq!(run("lc3c").status.code(), Some(2));
+
+        // Reports disassemble LC-3b instructions at byte addresses
+        // AND R0, R0, #0 ; ADD R0, R0, #1 ; XOR R1, R0, #2 ; HALT
+        let words = [0x3000, 0x5020, 0x1021, 0x9222, HALT];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        fs::write(&path, bytes).unwrap();
+        let coverage = dir.join("coverage.txt");
+        let output = Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+            .args(["run", "-q", "--isa", "lc3b", "--profile", "--coverage"])
+            .arg(&coverage)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("x3004                     XOR R1, R0, #2\n"),
+            "{}",
+            stderr
+        );
+        assert!(stderr.contains("%  XOR\n"), "{}", stderr);
+        let report = fs::read_to_string(&coverage).unwrap();
+        assert!(
+            report.contains("x3002          1                   ADD R0, R0, #1\n"),
+            "{}",
+            report
+        );
+
+        let output = Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+            .args(["disasm", "--isa", "lc3b"])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "x3000  5020                   AND R0, R0, #0\n\
+             x3002  1021                   ADD R0, R0, #1\n\
+             x3004  9222                   XOR R1, R0, #2\n\
+             x3006  F025                   HALT\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
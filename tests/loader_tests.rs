@@ -0,0 +1,203 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::loader::{
+        ImageFormat, ImageLoader, ImageProblem, LoadError, LoadedImage, Strictness,
+    };
+    use virtual_vm::run::VM;
+
+    fn problem(result: Result<LoadedImage, LoadError>) -> ImageProblem {
+        match result {
+            Err(LoadError::Invalid(problem)) => problem,
+            other => panic!("expected an invalid image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_image_loads_without_warnings() {
+        let mut vm = VM::new();
+        let mut loader = ImageLoader::new(Strictness::Error);
+        let image = loader
+            .load_bytes(&mut vm, "a.obj", &[0x30, 0x00, 0x12, 0x34, 0xF0, 0x25])
+            .unwrap();
+        assert_eq!(
+            image,
+            LoadedImage {
+                origin: 0x3000,
+                words: 2,
+                warnings: vec![],
+            }
+        );
+        assert_eq!(vm.memory[0x3000..0x3002], [0x1234, 0xF025]);
+    }
+
+    #[test]
+    fn test_problems_warn_or_fail() {
+        let cases: [(&[u8], ImageProblem); 3] = [
+            (&[0x30, 0x00], ImageProblem::NoWords { origin: 0x3000 }),
+            (
+                &[0x30, 0x00, 0x12, 0x34, 0x56],
+                ImageProblem::OddLength { bytes: 5 },
+            ),
+            (
+                &[0xFF, 0xFE, 0, 1, 0, 2, 0, 3],
+                ImageProblem::PastEndOfMemory {
+                    origin: 0xFFFE,
+                    words: 3,
+                    size: 0x10000,
+                },
+            ),
+        ];
+        for (bytes, expected) in cases {
+            let mut vm = VM::new();
+            let image = ImageLoader::new(Strictness::Warn)
+                .load_bytes(&mut vm, "image", bytes)
+                .unwrap();
+            assert_eq!(image.warnings, std::slice::from_ref(&expected));
+
+            let mut vm = VM::new();
+            let result = ImageLoader::new(Strictness::Error).load_bytes(&mut vm, "image", bytes);
+            assert_eq!(problem(result), expected);
+            assert!(vm.memory.contents().iter().all(|&word| word == 0));
+        }
+
+        // What fits is loaded, without wrapping around to x0000
+        let mut vm = VM::new();
+        ImageLoader::default()
+            .load_bytes(&mut vm, "image", &[0xFF, 0xFE, 0, 1, 0, 2, 0, 3])
+            .unwrap();
+        assert_eq!(
+            (vm.memory[0xFFFE], vm.memory[0xFFFF], vm.memory[0]),
+            (1, 2, 0)
+        );
+    }
+
+    #[test]
+    fn test_empty_image_is_always_an_error() {
+        for strictness in [Strictness::Warn, Strictness::Error] {
+            let mut loader = ImageLoader::new(strictness);
+            let result = loader.load_bytes(&mut VM::new(), "image", &[0x30]);
+            assert_eq!(problem(result), ImageProblem::Empty { bytes: 1 });
+        }
+        assert_eq!(
+            ImageProblem::Empty { bytes: 0 }.to_string(),
+            "too short to hold an origin (0 bytes)"
+        );
+    }
+
+    #[test]
+    fn test_overlapping_images_are_reported() {
+        let mut vm = VM::new();
+        let mut loader = ImageLoader::new(Strictness::Warn);
+        loader
+            .load_bytes(&mut vm, "main.obj", &[0x30, 0x00, 0, 1, 0, 2, 0, 3])
+            .unwrap();
+        loader
+            .load_bytes(&mut vm, "data.obj", &[0x40, 0x00, 0, 9])
+            .unwrap();
+        let image = loader
+            .load_bytes(&mut vm, "patch.obj", &[0x30, 0x02, 0, 7, 0, 8])
+            .unwrap();
+        let overlap = ImageProblem::Overlap {
+            start: 0x3002,
+            end: 0x3002,
+            other: "main.obj".to_string(),
+        };
+        assert_eq!(image.warnings, std::slice::from_ref(&overlap));
+        assert_eq!(
+            overlap.to_string(),
+            "x3002-x3002 overwrites part of main.obj"
+        );
+        assert_eq!(vm.memory[0x3002..0x3004], [7, 8]);
+
+        loader.strictness = Strictness::Error;
+        let result = loader.load_bytes(&mut vm, "again.obj", &[0x40, 0x00, 0, 5]);
+        assert!(matches!(problem(result), ImageProblem::Overlap { .. }));
+        assert_eq!(vm.memory[0x4000], 9);
+    }
+
+    #[test]
+    fn test_text_formats_are_detected() {
+        assert_eq!(ImageFormat::from_path("a.hex"), Some(ImageFormat::Hex));
+        assert_eq!(ImageFormat::from_path("a.asm"), None);
+        assert_eq!(
+            ImageFormat::sniff(b"3000\r\n\n  1234\nf025\n"),
+            ImageFormat::Hex
+        );
+        assert_eq!(
+            ImageFormat::sniff(b"0011000000000000\n1111000000100101"),
+            ImageFormat::Bin
+        );
+        assert_eq!(ImageFormat::sniff(b"3000\nxyz\n"), ImageFormat::Obj);
+        assert_eq!(ImageFormat::sniff(b""), ImageFormat::Obj);
+        // Older tools wrote binary images with a .bin extension
+        let old = std::fs::read("tests/test_image.bin").unwrap();
+        assert_eq!(
+            ImageFormat::detect("test_image.bin", &old),
+            ImageFormat::Obj
+        );
+        assert_eq!(ImageFormat::detect("a.hex", &old), ImageFormat::Hex);
+    }
+
+    #[test]
+    fn test_text_formats_round_trip() {
+        let words = [0x3000, 0x1234, 0xF025];
+        assert_eq!(ImageFormat::Hex.encode(&words), b"3000\n1234\nF025\n");
+        assert_eq!(ImageFormat::Bin.encode(&words[..1]), b"0011000000000000\n");
+        for format in [ImageFormat::Obj, ImageFormat::Hex, ImageFormat::Bin] {
+            assert_eq!(format.decode(&format.encode(&words)), Ok(words.to_vec()));
+        }
+
+        let mut vm = VM::new();
+        let image = ImageLoader::new(Strictness::Error)
+            .load_format(
+                &mut vm,
+                "a.bin",
+                &ImageFormat::Bin.encode(&words),
+                ImageFormat::Bin,
+            )
+            .unwrap();
+        assert_eq!((image.origin, image.words), (0x3000, 2));
+        assert_eq!(vm.memory[0x3000..0x3002], [0x1234, 0xF025]);
+    }
+
+    #[test]
+    fn test_bad_text_lines_are_errors() {
+        let mut loader = ImageLoader::new(Strictness::Warn);
+        let result =
+            loader.load_format(&mut VM::new(), "a.hex", b"3000\n12345\n", ImageFormat::Hex);
+        assert_eq!(
+            problem(result).to_string(),
+            "line 2: expected 4 hex digits, found \"12345\""
+        );
+        let result = loader.load_format(&mut VM::new(), "a.bin", b"0102\n", ImageFormat::Bin);
+        assert!(matches!(
+            problem(result),
+            ImageProblem::BadLine { line: 1, .. }
+        ));
+        let result = loader.load_format(&mut VM::new(), "a.hex", b"\n\n", ImageFormat::Hex);
+        assert_eq!(problem(result), ImageProblem::Empty { bytes: 2 });
+    }
+
+    #[test]
+    fn test_read_image_file_stays_lenient() {
+        let path = std::env::temp_dir().join(format!("lc3-loader-{}.obj", std::process::id()));
+        std::fs::write(&path, [0x30, 0x00, 0x12, 0x34, 0x56]).unwrap();
+        let mut vm = VM::new();
+        assert_eq!(vm.read_image(path.to_str().unwrap()).unwrap(), 0x3000);
+        assert_eq!(vm.memory[0x3000], 0x1234);
+        std::fs::write(&path, []).unwrap();
+        assert!(vm.read_image(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let path = path.with_extension("hex");
+        std::fs::write(&path, "4000\n0042\n").unwrap();
+        assert_eq!(vm.read_image(path.to_str().unwrap()).unwrap(), 0x4000);
+        assert_eq!(
+            vm.read_image_file(std::fs::File::open(&path).unwrap())
+                .unwrap(),
+            0x4000
+        );
+        assert_eq!(vm.memory[0x4000], 0x42);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::budget::{Budget, BudgetKind};
+    use virtual_vm::console::Console;
+    use virtual_vm::loops::LoopDetector;
+    use virtual_vm::run::{Fault, Isa, Registers, VM};
+    use virtual_vm::symbols::SymbolTable;
+
+    fn load(program: &[u16], input: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(input);
+        vm.memory[0x3000..0x3000 + program.len()].copy_from_slice(program);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.loop_detector = Some(LoopDetector::new());
+        vm.budget = Some(Budget {
+            max_instructions: Some(100_000),
+            ..Budget::default()
+        });
+        vm
+    }
+
+    #[test]
+    fn test_branch_to_itself_is_detected() {
+        // ADD R1,R1,#1 ; BRnzp #-1
+        let mut vm = load(&[0x1261, 0x0FFF], b"");
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::InfiniteLoop {
+                pc: 0x3001,
+                length: 1
+            }
+        );
+        assert!(fault.to_string().contains("jumps to itself"));
+        assert_eq!(vm.loop_detector.unwrap().body(), [0x3001]);
+    }
+
+    #[test]
+    fn test_repeated_state_is_reported_with_loop_body() {
+        // LOOP ADD R1,R1,#1 ; ADD R1,R1,#-1 ; BRnzp LOOP
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut vm = load(&[0x1261, 0x127F, 0x0FFD], b"");
+            vm.engine = engine;
+            let fault = vm.execute().unwrap_err();
+            assert!(matches!(fault, Fault::InfiniteLoop { length: 3, .. }));
+            let detector = vm.loop_detector.as_ref().unwrap();
+            let mut body = detector.body().to_vec();
+            body.sort_unstable();
+            assert_eq!(body, [0x3000, 0x3001, 0x3002]);
+            assert_eq!(
+                detector.report(Isa::Lc3, &vm.memory.contents(), &SymbolTable::new()),
+                "Loop body:\n  x3000                   ADD R1, R1, #1\n  x3001                   ADD R1, R1, #-1\n  x3002                   BRnzp x3000\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrapping_counter_is_eventually_detected() {
+        // LOOP ADD R1,R1,#1 ; BRnzp LOOP
+        let mut vm = load(&[0x1261, 0x0FFE], b"");
+        vm.budget = None;
+        let fault = vm.execute().unwrap_err();
+        assert!(matches!(
+            fault,
+            Fault::InfiniteLoop {
+                length: 0x20000,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_keyboard_polling_is_not_reported() {
+        // POLL LDI R0,KBSR ; BRzp POLL ; HALT ; KBSR .FILL xFE00
+        let mut vm = load(&[0xA002, 0x07FE, 0xF025, 0xFE00], b"");
+        let fault = vm.execute().unwrap_err();
+        assert!(matches!(
+            fault,
+            Fault::BudgetExhausted {
+                kind: BudgetKind::Instructions,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_reading_input_resets_detection() {
+        // LOOP GETC ; BRnzp LOOP
+        let mut vm = load(&[0xF020, 0x0FFE], b"aaaaaaaa");
+        assert!(matches!(vm.execute(), Err(Fault::EndOfInput { .. })));
+    }
+
+    #[test]
+    fn test_terminating_loop_is_not_reported() {
+        // LD R0,COUNT ; LOOP ADD R0,R0,#-1 ; BRp LOOP ; HALT ; COUNT .FILL #1000
+        let mut vm = load(&[0x2003, 0x103F, 0x03FE, 0xF025, 1000], b"");
+        assert_eq!(vm.execute(), Ok(()));
+    }
+}
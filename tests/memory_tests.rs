@@ -0,0 +1,140 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::blocks::Engine;
+    use virtual_vm::console::Console;
+    use virtual_vm::loader::{ImageLoader, ImageProblem, Strictness};
+    use virtual_vm::memory::{FlatMemory, Memory, MemoryError, SparseMemory};
+    use virtual_vm::run::{Fault, Registers, VM};
+
+    fn load(memory: impl Memory + 'static, program: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.memory = Box::new(memory);
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.memory.load(0x0100, program).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = 0x0100;
+        vm
+    }
+
+    #[test]
+    fn test_flat_memory_bounds_and_width() {
+        let mut memory = FlatMemory::new(0x1000);
+        assert_eq!(memory.size(), 0x1000);
+        memory.write(0x0FFF, 0x1234).unwrap();
+        assert_eq!(memory.read(0x0FFF), Ok(0x1234));
+        let outside = MemoryError {
+            address: 0x1000,
+            size: 0x1000,
+        };
+        assert_eq!(memory.read(0x1000), Err(outside));
+        assert_eq!(memory.write(0x1000, 1), Err(outside));
+        assert_eq!(outside.to_string(), "x1000 is outside memory (x1000 words)");
+        // Nothing is loaded unless all of it fits
+        assert!(memory.load(0x0FFF, &[7, 8]).is_err());
+        assert_eq!(memory.read(0x0FFF), Ok(0x1234));
+
+        let mut narrow = FlatMemory::new(16).with_width(12);
+        narrow.write(0, 0xFABC).unwrap();
+        assert_eq!(narrow.read(0), Ok(0x0ABC));
+    }
+
+    #[test]
+    fn test_sparse_memory_stores_only_written_pages() {
+        let mut memory = SparseMemory::new(1 << 24);
+        assert_eq!(memory.read(0xABCDEF), Ok(0));
+        assert_eq!(memory.stored(), 0);
+        memory.write(0xABCDEF, 5).unwrap();
+        memory.write(0xABCDEE, 6).unwrap();
+        memory.write(0x10, 7).unwrap();
+        assert_eq!(memory.read(0xABCDEF), Ok(5));
+        assert_eq!(memory.stored(), 2 * 256);
+        assert!(memory.read(1 << 24).is_err());
+
+        // Cloning a VM clones its memory
+        let mut vm = VM::new();
+        vm.memory = Box::new(memory);
+        let copy = vm.clone();
+        vm.memory[0x10] = 9;
+        assert_eq!(copy.memory[0x10], 7);
+        assert_eq!(copy.memory.contents()[0x10], 7);
+    }
+
+    #[test]
+    fn test_access_outside_memory_faults() {
+        // LD R0, #-2 reaches x00FF; LDR R1, R0, #0 then reads x1234
+        let mut vm = load(FlatMemory::new(0x1000), &[0x21FE, 0x6200, 0xF025]);
+        vm.memory[0x00FF] = 0x1234;
+        vm.registers_storage[Registers::R_R1 as usize] = 0x5555;
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::MemoryOutOfRange {
+                pc: 0x0101,
+                address: 0x1234,
+                size: 0x1000,
+            }
+        );
+        assert_eq!(
+            fault.to_string(),
+            "Memory access at x1234 is outside memory (x1000 words) at x0101"
+        );
+        // The faulting load leaves the registers as they were
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 0x5555);
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x0101);
+
+        // STI R0 through x00FF to x1234, then running off the end
+        let mut vm = load(FlatMemory::new(0x1000), &[0xB1FE]);
+        vm.memory[0x00FF] = 0x1234;
+        assert_eq!(vm.execute().unwrap_err().pc(), 0x0100);
+        let mut vm = load(FlatMemory::new(0x0101), &[0x0000]);
+        assert_eq!(
+            vm.execute(),
+            Err(Fault::MemoryOutOfRange {
+                pc: 0x0101,
+                address: 0x0101,
+                size: 0x0101,
+            })
+        );
+    }
+
+    #[test]
+    fn test_sparse_memory_runs_programs_on_every_engine() {
+        // ADD R0, R0, #5 ; ST R0, #1 ; HALT
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut vm = load(SparseMemory::new(1 << 16), &[0x1025, 0x3001, 0xF025]);
+            vm.engine = engine;
+            assert_eq!(vm.execute(), Ok(()));
+            assert_eq!(vm.memory[0x0103], 5);
+        }
+    }
+
+    #[test]
+    fn test_images_are_trimmed_to_memory() {
+        let mut vm = VM::new();
+        vm.memory = Box::new(FlatMemory::new(0x3002));
+        let image = ImageLoader::new(Strictness::Warn)
+            .load_bytes(&mut vm, "a.obj", &[0x30, 0x00, 0, 1, 0, 2, 0, 3])
+            .unwrap();
+        let problem = ImageProblem::PastEndOfMemory {
+            origin: 0x3000,
+            words: 3,
+            size: 0x3002,
+        };
+        assert_eq!(
+            problem.to_string(),
+            "3 words from x3000 run past x3001; the last 1 are dropped"
+        );
+        assert_eq!(image.warnings, [problem]);
+        assert_eq!(image.words, 2);
+
+        let problem = ImageProblem::PastEndOfMemory {
+            origin: 0x3000,
+            words: 3,
+            size: 0,
+        };
+        assert_eq!(
+            problem.to_string(),
+            "3 words from x3000 run past the end of an empty memory; the last 3 are dropped"
+        );
+    }
+}
@@ -1,8 +1,8 @@
 
 #[cfg(test)]
-
 mod tests {
-    use virtual_vm::run::{VM, Registers};
+    use virtual_vm::console::Console;
+    use virtual_vm::run::{Fault, VM, Registers};
 
     use std::fs::File;
     use std::io::Write; 
@@ -102,7 +102,7 @@ mod tests {
         vm.registers_storage[Registers::R_PC as usize] = 0x3000;
         vm.registers_storage[Registers::R_R2 as usize] = 42;
         vm.store(0x3406);
-        assert_eq!(vm.memory[0x3006 as usize], 42);
+        assert_eq!(vm.memory[0x3006], 42);
     }
     #[test]
     fn test_store_indirect_opcode() {
@@ -111,7 +111,7 @@ mod tests {
         vm.registers_storage[Registers::R_R2 as usize] = 42;
         vm.mem_write(0x3006, 0x3007);
         vm.store_indirect(0x3406);
-        assert_eq!(vm.memory[0x3007 as usize], 42);
+        assert_eq!(vm.memory[0x3007], 42);
     }
     #[test]
     fn test_store_register_opcode() {
@@ -119,7 +119,7 @@ mod tests {
         vm.registers_storage[Registers::R_R2 as usize] = 42;
         vm.registers_storage[Registers::R_R3 as usize] = 0x3000;
         vm.store_register(0x64C4);
-        assert_eq!(vm.memory[0x3004 as usize], 42);
+        assert_eq!(vm.memory[0x3004], 42);
     }
     #[test]
     fn test_trap_puts() {
@@ -146,8 +146,16 @@ mod tests {
     #[test]
     fn test_trap_getc() {
         let mut vm = VM::new();
-        vm.trap_getc(Some('A' as u16));
+        assert_eq!(vm.trap_getc(0x3000, Some('A' as u16)), Ok(()));
         assert_eq!(vm.registers_storage[Registers::R_R0 as usize], 'A' as u16);
+
+        // Running out of input is a fault at the TRAP, not a panic
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        assert_eq!(
+            vm.trap_getc(0x3000, None),
+            Err(Fault::EndOfInput { pc: 0x3000 })
+        );
     }
     #[test]
     fn test_trap_out() {
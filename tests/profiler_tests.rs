@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::disasm::disassemble;
+    use virtual_vm::profiler::{Profiler, SubroutineStats};
+    use virtual_vm::run::{Isa, Registers, VM};
+    use virtual_vm::symbols::SymbolTable;
+
+    // Calls SUB three times in a countdown loop:
+    //       AND R0,R0,#0 ; ADD R0,R0,#3
+    // LOOP  JSR SUB ; ADD R0,R0,#-1 ; BRp LOOP ; HALT
+    // SUB   ADD R1,R1,#1 ; RET
+    const PROGRAM: [u16; 8] = [
+        0x5020, 0x1023, 0x4803, 0x103F, 0x03FD, 0xF025, 0x1261, 0xC1C0,
+    ];
+
+    fn profile() -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.profiler = Some(Profiler::new());
+        vm.execute().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_counts_per_address_and_opcode() {
+        let vm = profile();
+        let profiler = vm.profiler.as_ref().unwrap();
+        assert_eq!(profiler.total(), 18);
+        assert_eq!(profiler.count_at(0x3000), 1);
+        assert_eq!(profiler.count_at(0x3006), 3);
+        assert_eq!(profiler.opcode_counts()[0x1], 7);
+        assert_eq!(profiler.opcode_counts()[0x4], 3);
+        assert_eq!(profiler.hot_spots()[0], (0x3002, 3));
+    }
+    #[test]
+    fn test_subroutine_time() {
+        let vm = profile();
+        let subroutines = vm.profiler.as_ref().unwrap().subroutines();
+        assert_eq!(
+            subroutines[&0x3006],
+            SubroutineStats {
+                calls: 3,
+                inclusive: 6,
+                exclusive: 6
+            }
+        );
+    }
+    #[test]
+    fn test_report_is_annotated() {
+        let vm = profile();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3002);
+        symbols.insert("SUB", 0x3006);
+        let report =
+            vm.profiler
+                .as_ref()
+                .unwrap()
+                .report(Isa::Lc3, &vm.memory.contents(), &symbols, 10);
+        assert!(report.starts_with("Profile: 18 instructions"));
+        assert!(report.contains("x3002    LOOP             JSR SUB"));
+        assert!(report.contains("BRp LOOP"));
+        assert!(report.contains("RET"));
+        assert!(report.contains("       3            6            6  SUB"));
+    }
+    #[test]
+    fn test_disassemble() {
+        let symbols = SymbolTable::new();
+        let cases = [
+            (0x1023, "ADD R0, R0, #3"),
+            (0x5020, "AND R0, R0, #0"),
+            (0x1042, "ADD R0, R1, R2"),
+            (0x0FFD, "BRnzp x3000"),
+            (0x4803, "JSR x3006"),
+            (0x4080, "JSRR R2"),
+            (0xC1C0, "RET"),
+            (0x6283, "LDR R1, R2, #3"),
+            (0x927F, "NOT R1, R1"),
+            (0xE002, "LEA R0, x3005"),
+            (0xF025, "HALT"),
+            (0xF030, "TRAP x30"),
+            (0xD000, ".FILL xD000"),
+        ];
+        for (instr, text) in cases {
+            assert_eq!(disassemble(0x3002, instr, &symbols), text);
+        }
+    }
+}
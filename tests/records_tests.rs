@@ -0,0 +1,207 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::dump::{Dump, DumpFormat};
+    use virtual_vm::loader::{ImageFormat, ImageLoader, LoadError, Strictness};
+    use virtual_vm::records::{
+        read_intel_hex, read_srecords, write_intel_hex, write_srecords, Addressing, Records,
+    };
+    use virtual_vm::run::VM;
+
+    fn records() -> Records {
+        Records {
+            segments: vec![
+                Dump {
+                    start: 0x3000,
+                    words: (0..20).map(|word| 0x1000 + word).collect(),
+                },
+                Dump {
+                    start: 0xFFFE,
+                    words: vec![0xABCD, 0xEF01],
+                },
+            ],
+            entry: Some(0x3000),
+        }
+    }
+
+    fn error(result: Result<Records, impl ToString>) -> String {
+        match result {
+            Ok(records) => panic!("expected an error, got {:?}", records),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_intel_hex_round_trips() {
+        for addressing in [Addressing::Word, Addressing::Byte] {
+            let text = write_intel_hex(&records(), addressing);
+            assert_eq!(read_intel_hex(&text, addressing), Ok(records()), "{}", text);
+        }
+        let text = write_intel_hex(&records(), Addressing::Word);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], ":103000001000100110021003100410051006100724");
+        assert_eq!(lines[3], ":04FFFE00ABCDEF0197");
+        assert_eq!(lines[4], ":0400000500003000C7");
+        assert_eq!(lines[5], ":00000001FF");
+
+        // Byte addresses from x8000 on need an extended linear address
+        let text = write_intel_hex(&records(), Addressing::Byte);
+        assert!(text.contains(":020000040001F9\n:04FFFC00ABCDEF0199\n"));
+    }
+
+    #[test]
+    fn test_srecords_round_trip() {
+        for addressing in [Addressing::Word, Addressing::Byte] {
+            let text = write_srecords(&records(), addressing);
+            assert_eq!(read_srecords(&text, addressing), Ok(records()), "{}", text);
+        }
+        let text = write_srecords(&records(), Addressing::Word);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "S0030000FC");
+        assert_eq!(lines[1], "S11330001000100110021003100410051006100720");
+        assert_eq!(lines[5], "S5030004F8");
+        assert_eq!(lines[6], "S9033000CC");
+        // Byte addresses past xFFFF need 24-bit addresses
+        let text = write_srecords(&records(), Addressing::Byte);
+        assert!(text.contains("S20801FFFCABCDEF0193\n"));
+        assert!(text.ends_with("S8040060009B\n"));
+    }
+
+    #[test]
+    fn test_bad_intel_hex_is_rejected() {
+        let read = |text: &str| error(read_intel_hex(text, Addressing::Word));
+        assert_eq!(
+            read(":0400000030010000CC\n:00000001FF\n"),
+            "line 1: checksum is xCC, expected xCB"
+        );
+        assert_eq!(read(":0400000030010000CB\n"), "no end of file record");
+        assert_eq!(
+            read("0400000030010000CB\n"),
+            "line 1: expected a record starting with ':'"
+        );
+        assert_eq!(
+            read(":0500000030010000CA\n"),
+            "line 1: record says it has 5 bytes, found 4"
+        );
+        assert_eq!(
+            read(":00000001FF\n:00000001FF\n"),
+            "line 2: record after the end of file record"
+        );
+        assert_eq!(read(":00000006FA\n"), "line 1: unknown record type 06");
+        assert_eq!(
+            read(":020000003001CD\n:020000003001CD\n:00000001FF\n"),
+            "line 2: word x0000 is written twice"
+        );
+        // An extended linear address of 1 puts word addresses past xFFFF
+        assert_eq!(
+            read(":020000040001F9\n:020000003001CD\n:00000001FF\n"),
+            "line 2: address x10000 is past xFFFF"
+        );
+        assert_eq!(
+            error(read_intel_hex(
+                ":0100000030CF\n:00000001FF\n",
+                Addressing::Byte
+            )),
+            "word x0000 has only one of its two bytes"
+        );
+    }
+
+    #[test]
+    fn test_bad_srecords_are_rejected() {
+        let read = |text: &str| error(read_srecords(text, Addressing::Word));
+        assert_eq!(
+            read("S107000030010000C8\n"),
+            "line 1: checksum is xC8, expected xC7"
+        );
+        assert_eq!(
+            read("S107000030010000C7\nS5030002FA\n"),
+            "line 2: count record says 2 data records, found 1"
+        );
+        assert_eq!(read("S4030000FC\n"), "line 1: unknown record type S4");
+        assert_eq!(
+            read("X107000030010000C7\n"),
+            "line 1: expected a record starting with S0-S9"
+        );
+        assert_eq!(
+            read("S10200FD\n"),
+            "line 1: record too short for its address"
+        );
+    }
+
+    #[test]
+    fn test_segments_load_into_memory() {
+        let text = write_intel_hex(&records(), Addressing::Word);
+        let mut vm = VM::new();
+        let mut loader = ImageLoader::new(Strictness::Error);
+        let image = loader
+            .load_format(&mut vm, "a.ihex", text.as_bytes(), ImageFormat::IntelHex)
+            .unwrap();
+        assert_eq!((image.origin, image.words), (0x3000, 22));
+        assert_eq!(vm.memory[0x3013], 0x1013);
+        assert_eq!(vm.memory[0xFFFF], 0xEF01);
+
+        // Each segment is checked for overlaps
+        let patch = write_srecords(
+            &Records {
+                segments: vec![Dump {
+                    start: 0xFFFF,
+                    words: vec![7],
+                }],
+                entry: None,
+            },
+            Addressing::Word,
+        );
+        let result = loader.load_format(&mut vm, "b.srec", patch.as_bytes(), ImageFormat::SRecord);
+        match result {
+            Err(LoadError::Invalid(problem)) => {
+                assert_eq!(problem.to_string(), "xFFFF-xFFFF overwrites part of a.ihex")
+            }
+            other => panic!("expected an overlap, got {:?}", other),
+        }
+
+        // With byte addressing each word takes two addresses
+        let mut loader = ImageLoader::new(Strictness::Error);
+        loader.addressing = Addressing::Byte;
+        let text = write_srecords(&records(), Addressing::Byte);
+        let image = loader
+            .load_format(
+                &mut VM::new(),
+                "c.srec",
+                text.as_bytes(),
+                ImageFormat::SRecord,
+            )
+            .unwrap();
+        assert_eq!(image.words, 22);
+    }
+
+    #[test]
+    fn test_formats_are_detected() {
+        let intel = b":0400000030010000CB\n:00000001FF\n";
+        assert_eq!(ImageFormat::sniff(intel), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::detect("a.hex", intel), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::detect("a.hex", b"3000\n"), ImageFormat::Hex);
+        assert_eq!(ImageFormat::detect("a.s19", b""), ImageFormat::SRecord);
+        assert_eq!(
+            ImageFormat::sniff(b"S107000030010000C7\n"),
+            ImageFormat::SRecord
+        );
+        assert_eq!(DumpFormat::detect("a.hex", intel), DumpFormat::IntelHex);
+        assert_eq!(DumpFormat::from_path("a.srec"), DumpFormat::SRecord);
+
+        // Dumps hold one segment
+        let dump = Dump {
+            start: 0x4000,
+            words: vec![1, 2, 3],
+        };
+        for format in [DumpFormat::IntelHex, DumpFormat::SRecord] {
+            assert_eq!(
+                Dump::parse(&dump.to_bytes(format), format, 0),
+                Ok(dump.clone())
+            );
+        }
+        let two = write_intel_hex(&records(), Addressing::Word);
+        assert_eq!(
+            Dump::parse(two.as_bytes(), DumpFormat::IntelHex, 0),
+            Err("2 segments where one was expected".to_string())
+        );
+    }
+}
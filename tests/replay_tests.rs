@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests {
+    use virtual_vm::console::Console;
+    use virtual_vm::replay::{InputEvent, InputLog, InputTrace};
+    use virtual_vm::run::{Fault, Registers, VM};
+
+    // LDI R0,KBSR ; BRzp x3000 ; LDI R0,KBDR ; OUT ; GETC ; OUT ; HALT
+    const PROGRAM: [u16; 9] = [
+        0xA006, 0x07FE, 0xA005, 0xF021, 0xF020, 0xF021, 0xF025, 0xFE00, 0xFE02,
+    ];
+
+    fn load(trace: InputTrace) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.input_trace = trace;
+        vm.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    fn record() -> VM {
+        let mut vm = load(InputTrace::Recording(InputLog::new()));
+        for _ in 0..6 {
+            vm.step().unwrap();
+        }
+        vm.console.push_input(b"hi");
+        vm.execute().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_recording_logs_keys_and_empty_polls() {
+        let vm = record();
+        let log = vm.input_trace.log().unwrap();
+        assert_eq!(
+            log.events,
+            vec![
+                InputEvent::NoKey {
+                    first: 0,
+                    last: 4,
+                    polls: 3
+                },
+                InputEvent::Key {
+                    count: 6,
+                    byte: b'h'
+                },
+                InputEvent::Key {
+                    count: 10,
+                    byte: b'i'
+                },
+            ]
+        );
+    }
+    #[test]
+    fn test_replay_reproduces_session() {
+        let mut original = record();
+        let log = original.input_trace.log().unwrap().clone();
+
+        let mut replayed = load(InputTrace::replaying(log));
+        replayed.console.push_input(b"xx");
+        replayed.execute().unwrap();
+        assert_eq!(replayed.instruction_count, original.instruction_count);
+        assert_eq!(replayed.registers_storage, original.registers_storage);
+        assert_eq!(
+            replayed.console.take_output(),
+            original.console.take_output()
+        );
+    }
+    #[test]
+    fn test_replay_divergence_faults() {
+        let log = InputLog::parse("0-0 nokey 1\n9 key 0x61\n").unwrap();
+        let mut vm = load(InputTrace::replaying(log));
+        let fault = vm.execute().unwrap_err();
+        assert_eq!(
+            fault,
+            Fault::ReplayDiverged {
+                pc: 0x3000,
+                count: 2
+            }
+        );
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3000);
+    }
+    #[test]
+    fn test_log_text_round_trip() {
+        let log = record().input_trace.log().unwrap().clone();
+        let text = log.to_string();
+        assert!(text.starts_with("# LC-3 input log v1\n0-4 nokey 3\n6 key 0x68\n"));
+        assert_eq!(InputLog::parse(&text).unwrap(), log);
+        assert!(InputLog::parse("12 key zz").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use virtual_vm::callstack::CallStack;
+    use virtual_vm::console::Console;
+    use virtual_vm::memory::{FlatMemory, Memory, SparseMemory, ADDRESS_SPACE};
+    use virtual_vm::run::{Isa, Registers, VM};
+
+    // ADD R1,R1,#1 ; ST R1, x3005 ; GETC ; ADD R1,R1,#1 ; HALT
+    const PROGRAM: [u16; 5] = [0x1261, 0x3203, 0xF020, 0x1261, 0xF025];
+
+    fn load(input: &[u8]) -> VM {
+        let mut vm = VM::new();
+        vm.console = Console::buffered(input);
+        vm.memory[0x3000..0x3000 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut vm = load(b"ab");
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        let mut bytes = Vec::new();
+        vm.write_snapshot(&mut bytes).unwrap();
+
+        let mut restored = VM::new();
+        restored.console = Console::buffered(b"");
+        restored.read_snapshot(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.registers_storage, vm.registers_storage);
+        assert_eq!(restored.memory.contents(), vm.memory.contents());
+        assert_eq!(restored.instruction_count, 2);
+        assert_eq!(restored.console.pending_input(), b"ab");
+        assert_eq!(restored.psr(), 0x8001);
+    }
+    #[test]
+    fn test_resumed_vm_matches_original() {
+        let mut original = load(b"z");
+        original.step().unwrap();
+        let mut bytes = Vec::new();
+        original.write_snapshot(&mut bytes).unwrap();
+        original.execute().unwrap();
+
+        let mut resumed = VM::new();
+        resumed.console = Console::buffered(b"");
+        resumed.read_snapshot(&mut bytes.as_slice()).unwrap();
+        resumed.execute().unwrap();
+        assert_eq!(resumed.registers_storage, original.registers_storage);
+        assert_eq!(resumed.instruction_count, original.instruction_count);
+        assert_eq!(
+            resumed.registers_storage[Registers::R_R0 as usize],
+            'z' as u16
+        );
+    }
+    #[test]
+    fn test_snapshot_rejects_bad_header() {
+        let mut vm = VM::new();
+        let err = vm.read_snapshot(&mut &b"NOTASNAPSHOT"[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        VM::new().write_snapshot(&mut bytes).unwrap();
+        bytes[9] = 99; // version
+        let err = vm.read_snapshot(&mut bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("unsupported snapshot version 99"));
+    }
+    #[test]
+    fn test_snapshot_must_match_the_machine() {
+        let mut bytes = Vec::new();
+        let mut lc3b = VM::new();
+        lc3b.isa = Isa::Lc3b;
+        lc3b.write_snapshot(&mut bytes).unwrap();
+        let err = VM::new().read_snapshot(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the snapshot is of an LC-3b machine, but this VM runs LC-3"
+        );
+        lc3b.read_snapshot(&mut bytes.as_slice()).unwrap();
+
+        let small = || {
+            let mut vm = VM::new();
+            vm.memory = Box::new(FlatMemory::new(0x1000).with_width(12));
+            vm
+        };
+        let mut bytes = Vec::new();
+        small().write_snapshot(&mut bytes).unwrap();
+        let err = VM::new().read_snapshot(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the snapshot has x1000 words of 12 bits, but this VM's memory has x10000 of 16"
+        );
+        small().read_snapshot(&mut bytes.as_slice()).unwrap();
+    }
+    #[test]
+    fn test_snapshot_resets_call_stack() {
+        // JSR #0 ; HALT
+        let mut vm = VM::new();
+        vm.memory[0x3000..0x3002].copy_from_slice(&[0x4800, 0xF025]);
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        let mut bytes = Vec::new();
+        vm.write_snapshot(&mut bytes).unwrap();
+        vm.call_stack = Some(CallStack::new(0x3000));
+        vm.step().unwrap();
+        assert_eq!(vm.call_stack.as_ref().unwrap().depth(), 1);
+        vm.read_snapshot(&mut bytes.as_slice()).unwrap();
+        assert_eq!(vm.call_stack.as_ref().unwrap().depth(), 0);
+    }
+    /// A `SparseMemory` that shares how many words it has stored.
+    #[derive(Debug, Clone)]
+    struct Watched(SparseMemory, Arc<AtomicUsize>);
+
+    impl Memory for Watched {
+        fn size(&self) -> usize {
+            self.0.size()
+        }
+
+        fn get(&self, address: usize) -> Option<&u16> {
+            self.0.get(address)
+        }
+
+        fn get_mut(&mut self, address: usize) -> Option<&mut u16> {
+            self.0.get_mut(address)?;
+            self.1.store(self.0.stored(), Ordering::Relaxed);
+            self.0.get_mut(address)
+        }
+
+        fn clone_box(&self) -> Box<dyn Memory> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_snapshot_keeps_sparse_memory_sparse() {
+        let mut bytes = Vec::new();
+        load(b"").write_snapshot(&mut bytes).unwrap();
+        let stored = Arc::new(AtomicUsize::new(0));
+        let mut vm = VM::new();
+        vm.memory = Box::new(Watched(SparseMemory::new(ADDRESS_SPACE), stored.clone()));
+        vm.read_snapshot(&mut bytes.as_slice()).unwrap();
+        assert_eq!(vm.memory.contents()[0x3000..0x3005], PROGRAM);
+        assert_eq!(stored.load(Ordering::Relaxed), 256);
+    }
+    #[test]
+    fn test_cli_saves_and_resumes() {
+        let dir = std::env::temp_dir().join(format!("lc3-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("count.obj");
+        let snapshot = dir.join("count.snap");
+        // ADD R1,R1,#1 ; HALT ; ADD R1,R1,#1 ; HALT
+        let words: [u16; 5] = [0x3000, 0x1261, 0xF025, 0x1261, 0xF025];
+        fs::write(
+            &image,
+            words
+                .iter()
+                .flat_map(|w| w.to_be_bytes())
+                .collect::<Vec<u8>>(),
+        )
+        .unwrap();
+
+        let binary = env!("CARGO_BIN_EXE_virtual-vm");
+        let output = Command::new(binary)
+            .arg("--save-snapshot")
+            .arg(&snapshot)
+            .arg(&image)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let mut vm = VM::new();
+        vm.load_snapshot(&snapshot).unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_PC as usize], 0x3002);
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 1);
+
+        // Resuming continues after the first HALT and saves the new state
+        let output = Command::new(binary)
+            .arg("--resume")
+            .arg(&snapshot)
+            .arg("--save-snapshot")
+            .arg(&snapshot)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        vm.load_snapshot(&snapshot).unwrap();
+        assert_eq!(vm.registers_storage[Registers::R_R1 as usize], 2);
+        assert_eq!(vm.instruction_count, 4);
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use virtual_vm::asm::{assemble, format_symbols, Assembly};
+    use virtual_vm::console::Console;
+    use virtual_vm::run::{Registers, VM};
+    use virtual_vm::stackcheck::{StackChecker, StackProblem};
+
+    const SOURCE: &str = "\
+.ORIG x3000
+MAIN    LD R6, STACK
+        JSR LEAKY
+        JSR CLOBBER
+        HALT
+LEAKY   ADD R6, R6, #-1
+        STR R0, R6, #0
+        RET
+CLOBBER LEA R7, DONE
+        RET
+DONE    HALT
+STACK   .FILL x4000
+.END
+";
+
+    fn load(assembly: &Assembly) -> VM {
+        let section = &assembly.sections[0];
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.memory
+            .load(section.origin as usize, &section.words)
+            .unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = section.origin;
+        vm
+    }
+
+    #[test]
+    fn test_unbalanced_stack_and_bad_return() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut vm = load(&assembly);
+        vm.stack_checker = Some(StackChecker::new());
+        assert_eq!(vm.execute(), Ok(()));
+        let checker = vm.stack_checker.as_ref().unwrap();
+        assert_eq!(
+            checker.problems(),
+            [
+                StackProblem::Unbalanced {
+                    pc: 0x3006,
+                    sp: 0x3FFF,
+                    expected: 0x4000,
+                    entry: 0x3004,
+                    call_site: 0x3001,
+                },
+                StackProblem::BadReturn {
+                    pc: 0x3008,
+                    target: 0x3009,
+                },
+            ]
+        );
+        assert_eq!(
+            checker.report(&assembly.symbols),
+            "Stack problems:\n\
+             \x20 x3006  LEAKY+2          RET with R6 = x3FFF, but it was x4000 when LEAKY was called from x3001 (MAIN+1): 1 word pushed and not popped\n\
+             \x20 x3008  CLOBBER+1        RET to x3009 (DONE), which is not after an active call; was R7 overwritten?\n"
+        );
+    }
+
+    #[test]
+    fn test_stack_running_into_code() {
+        // ADD R6, R6, #-1 ; STR R0, R6, #0 ; HALT
+        let mut vm = VM::new();
+        vm.console = Console::buffered(b"");
+        vm.quiet = true;
+        vm.memory.load(0x3000, &[0x1DBF, 0x7180, 0xF025]).unwrap();
+        vm.registers_storage[Registers::R_PC as usize] = 0x3000;
+        vm.registers_storage[Registers::R_R6 as usize] = 0x3010;
+        let mut checker = StackChecker::new();
+        checker.protect(0x3000, 0x300F);
+        vm.stack_checker = Some(checker);
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(
+            vm.stack_checker.unwrap().problems(),
+            [StackProblem::IntoProtected {
+                pc: 0x3000,
+                sp: 0x300F,
+                start: 0x3000,
+                end: 0x300F,
+            }]
+        );
+
+        // Setting R6 outright, even inside a region, is fine; so is a
+        // balanced call
+        let source = "\
+.ORIG x3000
+        LD R6, TOP
+        JSR PUSHPOP
+        HALT
+PUSHPOP ADD R6, R6, #-1
+        STR R7, R6, #0
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+        .BLKW 4
+TOP     .FILL TOP
+.END
+";
+        let assembly = assemble(source).unwrap();
+        let mut vm = load(&assembly);
+        let mut checker = StackChecker::new();
+        checker.protect(0x3000, 0x300D);
+        vm.stack_checker = Some(checker);
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(vm.stack_checker.unwrap().problems(), []);
+    }
+
+    #[test]
+    fn test_check_stack_option() {
+        let dir = std::env::temp_dir().join(format!("lc3-stack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let assembly = assemble(SOURCE).unwrap();
+        let image = dir.join("leaky.obj");
+        fs::write(&image, assembly.sections[0].to_obj()).unwrap();
+        fs::write(dir.join("leaky.sym"), format_symbols(&assembly.symbols)).unwrap();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+            .args(["run", "-q", "--check-stack", image.to_str().unwrap()])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Stack problems:\n"), "{}", stderr);
+        assert!(stderr.contains("x3008  CLOBBER+1 "), "{}", stderr);
+
+        // R6 moving into the image is reported too
+        let image = dir.join("dive.obj");
+        // ADD R6, R6, #-2 ; HALT
+        let words: [u16; 3] = [0x3000, 0x1DBE, 0xF025];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        fs::write(&image, bytes).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_virtual-vm"))
+            .args(["run", "-q", "--check-stack", "--reg", "R6=x3003"])
+            .args(["--max-instructions", "100"])
+            .arg(&image)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("R6 moved to x3001, into the code or data at x3000-x3001"),
+            "{}",
+            stderr
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}